@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+/// Number of analyzed samples, giving as many spectrum bins as the texture is wide
+pub const FFT_SIZE: usize = 1024;
+/// Width of the audio texture, as for Shadertoy audio channels
+pub const TEXTURE_WIDTH: usize = FFT_SIZE / 2;
+
+/// Decibel range mapped to the [0, 1] spectrum values, as done by the Web Audio API
+const MIN_DECIBELS: f32 = -100.0;
+const MAX_DECIBELS: f32 = -30.0;
+
+/// Frequency bounds of the bass, mid and treble bands, in Hz
+const BANDS: [(f32, f32); 3] = [(20.0, 250.0), (250.0, 4000.0), (4000.0, 16000.0)];
+
+#[derive(Debug, Clone, Default)]
+pub struct AudioAnalysis {
+    /// Smoothed magnitude of each frequency bin, between 0 and 1
+    pub spectrum: Vec<f32>,
+    /// Most recent samples, between -1 and 1
+    pub waveform: Vec<f32>,
+    pub rms: f32,
+    /// Mean magnitude of the bass, mid and treble bands, between 0 and 1
+    pub bands: [f32; 3],
+}
+
+pub struct AudioAnalyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    smoothing: f32,
+
+    analysis: AudioAnalysis,
+}
+
+impl AudioAnalyzer {
+    pub fn new(smoothing: f32) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+
+        let window = (0..FFT_SIZE)
+            .map(|index| {
+                0.5 - 0.5
+                    * (2.0 * std::f32::consts::PI * index as f32 / (FFT_SIZE - 1) as f32).cos()
+            })
+            .collect();
+
+        Self {
+            fft,
+            window,
+            buffer: vec![Complex::new(0.0, 0.0); FFT_SIZE],
+            smoothing,
+
+            analysis: AudioAnalysis {
+                spectrum: vec![0.0; TEXTURE_WIDTH],
+                waveform: vec![0.0; TEXTURE_WIDTH],
+                rms: 0.0,
+                bands: [0.0; 3],
+            },
+        }
+    }
+
+    pub fn get_analysis(&self) -> &AudioAnalysis {
+        &self.analysis
+    }
+
+    /// Brings the analysis back to the one of silence, smoothing starting over
+    pub fn reset(&mut self) {
+        self.analysis
+            .spectrum
+            .iter_mut()
+            .for_each(|value| *value = 0.0);
+        self.analysis
+            .waveform
+            .iter_mut()
+            .for_each(|value| *value = 0.0);
+        self.analysis.rms = 0.0;
+        self.analysis.bands = [0.0; 3];
+    }
+
+    /// Analyzes the last `FFT_SIZE` samples
+    pub fn analyze(&mut self, samples: &[f32], sample_rate: u32) -> &AudioAnalysis {
+        for ((value, sample), weight) in self.buffer.iter_mut().zip(samples).zip(&self.window) {
+            *value = Complex::new(sample * weight, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+
+        for (bin, value) in self.analysis.spectrum.iter_mut().enumerate() {
+            let magnitude = self.buffer[bin].norm() / FFT_SIZE as f32;
+            let decibels = 20.0 * magnitude.max(1e-10).log10();
+            let level = ((decibels - MIN_DECIBELS) / (MAX_DECIBELS - MIN_DECIBELS)).clamp(0.0, 1.0);
+
+            *value = self.smoothing * *value + (1.0 - self.smoothing) * level;
+        }
+
+        let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+        for (band, (low, high)) in self.analysis.bands.iter_mut().zip(BANDS.iter()) {
+            let first_bin = ((low / bin_width) as usize).min(TEXTURE_WIDTH - 1);
+            let last_bin = ((high / bin_width) as usize)
+                .max(first_bin + 1)
+                .min(TEXTURE_WIDTH);
+
+            *band = self.analysis.spectrum[first_bin..last_bin]
+                .iter()
+                .sum::<f32>()
+                / (last_bin - first_bin) as f32;
+        }
+
+        let waveform = &samples[samples.len() - TEXTURE_WIDTH..];
+        self.analysis.waveform.copy_from_slice(waveform);
+
+        self.analysis.rms = (samples.iter().map(|sample| sample * sample).sum::<f32>()
+            / samples.len() as f32)
+            .sqrt();
+
+        &self.analysis
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Sine wave of `amplitude` falling exactly on the frequency of `bin`
+    fn sine(bin: usize, amplitude: f32) -> Vec<f32> {
+        (0..FFT_SIZE)
+            .map(|index| {
+                let phase = 2.0 * std::f32::consts::PI * (bin * index) as f32 / FFT_SIZE as f32;
+                amplitude * phase.sin()
+            })
+            .collect()
+    }
+
+    fn loudest_bin(spectrum: &[f32]) -> usize {
+        spectrum
+            .iter()
+            .enumerate()
+            .max_by(|(_, first), (_, second)| first.partial_cmp(second).unwrap())
+            .map(|(bin, _)| bin)
+            .unwrap()
+    }
+
+    /// Spectrum value of a sine of `amplitude`, the Hann window halving it and the FFT
+    /// spreading it over the positive and negative frequencies
+    fn expected_level(amplitude: f32) -> f32 {
+        (20.0 * (amplitude / 4.0).log10() - MIN_DECIBELS) / (MAX_DECIBELS - MIN_DECIBELS)
+    }
+
+    #[test]
+    fn a_sine_lights_its_frequency_bin() {
+        let mut analyzer = AudioAnalyzer::new(0.0);
+
+        // 46.875 Hz per bin, so bins 4, 64 and 200 are 187.5 Hz, 3 kHz and 9.375 kHz
+        for bin in &[4, 64, 200] {
+            let analysis = analyzer.analyze(&sine(*bin, 0.01), SAMPLE_RATE);
+
+            assert_eq!(loudest_bin(&analysis.spectrum), *bin);
+            assert!((analysis.spectrum[*bin] - expected_level(0.01)).abs() < 1e-3);
+            // The window spreads the sine over the next bins, 6 dB lower, and no further
+            for neighbour in &[bin - 1, bin + 1] {
+                assert!((analysis.spectrum[*neighbour] - expected_level(0.005)).abs() < 1e-3);
+            }
+            for (other_bin, value) in analysis.spectrum.iter().enumerate() {
+                if (other_bin as isize - *bin as isize).abs() > 1 {
+                    assert_eq!(*value, 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn band_energy_follows_the_frequency() {
+        let mut analyzer = AudioAnalyzer::new(0.0);
+        let lit_bins_energy = expected_level(0.01) + 2.0 * expected_level(0.005);
+
+        // The bass band spans bins 0 to 4, the next bin being the first of the mid band
+        let bands = analyzer.analyze(&sine(4, 0.01), SAMPLE_RATE).bands;
+        assert!((bands[0] - (lit_bins_energy - expected_level(0.005)) / 5.0).abs() < 1e-3);
+        assert!(bands[1] < bands[0] && bands[2] == 0.0);
+
+        // The mid band spans bins 5 to 84, and the treble band bins 85 to 340
+        let bands = analyzer.analyze(&sine(64, 0.01), SAMPLE_RATE).bands;
+        assert!((bands[1] - lit_bins_energy / 80.0).abs() < 1e-4);
+        assert!(bands[0] == 0.0 && bands[2] == 0.0);
+
+        let bands = analyzer.analyze(&sine(200, 0.01), SAMPLE_RATE).bands;
+        assert!((bands[2] - lit_bins_energy / 256.0).abs() < 1e-4);
+        assert!(bands[0] == 0.0 && bands[1] == 0.0);
+    }
+
+    #[test]
+    fn level_and_waveform_come_from_the_samples() {
+        let mut analyzer = AudioAnalyzer::new(0.0);
+        let samples = sine(64, 0.5);
+
+        let analysis = analyzer.analyze(&samples, SAMPLE_RATE);
+        assert!((analysis.rms - 0.5 / 2f32.sqrt()).abs() < 1e-4);
+        assert_eq!(analysis.waveform, &samples[FFT_SIZE - TEXTURE_WIDTH..]);
+
+        let analysis = analyzer.analyze(&[0.0; FFT_SIZE], SAMPLE_RATE);
+        assert_eq!(analysis.rms, 0.0);
+        assert!(analysis.spectrum.iter().all(|value| *value == 0.0));
+    }
+
+    #[test]
+    fn the_spectrum_is_smoothed_between_analyses() {
+        let mut analyzer = AudioAnalyzer::new(0.75);
+
+        assert_eq!(
+            analyzer.analyze(&sine(64, 0.5), SAMPLE_RATE).spectrum[64],
+            0.25
+        );
+        assert_eq!(
+            analyzer.analyze(&sine(64, 0.5), SAMPLE_RATE).spectrum[64],
+            0.4375
+        );
+
+        analyzer.reset();
+        assert_eq!(analyzer.get_analysis().spectrum[64], 0.0);
+        assert_eq!(analyzer.get_analysis().bands, [0.0; 3]);
+    }
+}
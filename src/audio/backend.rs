@@ -0,0 +1,257 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+/// Source of mono samples analyzed by an `AudioProvider`
+pub trait AudioBackend {
+    fn sample_rate(&self) -> u32;
+
+    /// Fills `window` with the samples heard right before `time`, in seconds since the project start.
+    /// Live backends ignore `time` and return their most recent samples
+    fn read_window(&mut self, time: f64, window: &mut [f32]);
+}
+
+/// Mixes interleaved channels down to mono
+fn downmix<I: Iterator<Item = f32>>(samples: I, channel_count: usize) -> Vec<f32> {
+    let mut mono_samples = Vec::new();
+    let mut accumulator = 0.0;
+
+    for (index, sample) in samples.enumerate() {
+        accumulator += sample;
+        if index % channel_count == channel_count - 1 {
+            mono_samples.push(accumulator / channel_count as f32);
+            accumulator = 0.0;
+        }
+    }
+
+    mono_samples
+}
+
+/// Decoded audio file, read at the position given by the project time so that offline renders stay deterministic
+pub struct FileBackend {
+    samples: Vec<f32>,
+    sample_rate: u32,
+    looping: bool,
+}
+
+impl FileBackend {
+    pub fn new(path: &str, looping: bool) -> Result<Self> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        let (samples, sample_rate) = match extension.as_deref() {
+            Some("wav") => {
+                let mut reader = hound::WavReader::open(path)
+                    .context(format!("Failed to open audio file {:?}", path))?;
+                let spec = reader.spec();
+
+                let samples: Vec<f32> = match spec.sample_format {
+                    hound::SampleFormat::Float => {
+                        reader
+                            .samples::<f32>()
+                            .collect::<Result<Vec<f32>, hound::Error>>()?
+                    }
+                    hound::SampleFormat::Int => {
+                        let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                        reader
+                            .samples::<i32>()
+                            .map(|sample| sample.map(|sample| sample as f32 / scale))
+                            .collect::<Result<Vec<f32>, hound::Error>>()?
+                    }
+                };
+
+                (
+                    downmix(samples.into_iter(), spec.channels as usize),
+                    spec.sample_rate,
+                )
+            }
+            Some("flac") => {
+                let mut reader = claxon::FlacReader::open(path)
+                    .context(format!("Failed to open audio file {:?}", path))?;
+                let stream_info = reader.streaminfo();
+                let scale = (1i64 << (stream_info.bits_per_sample - 1)) as f32;
+
+                let samples = reader
+                    .samples()
+                    .map(|sample| sample.map(|sample| sample as f32 / scale))
+                    .collect::<Result<Vec<f32>, claxon::Error>>()?;
+
+                (
+                    downmix(samples.into_iter(), stream_info.channels as usize),
+                    stream_info.sample_rate,
+                )
+            }
+            _ => bail!(
+                "Unsupported audio file {:?}, expected a wav or flac file",
+                path
+            ),
+        };
+
+        Ok(Self {
+            samples,
+            sample_rate,
+            looping,
+        })
+    }
+}
+
+impl AudioBackend for FileBackend {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read_window(&mut self, time: f64, window: &mut [f32]) {
+        let sample_count = self.samples.len() as i64;
+        let end = (time * self.sample_rate as f64) as i64;
+
+        for (index, value) in window.iter_mut().enumerate() {
+            let mut position = end - (window.len() - index) as i64;
+            if self.looping && sample_count > 0 {
+                position = position.rem_euclid(sample_count);
+            }
+
+            *value = if position >= 0 && position < sample_count {
+                self.samples[position as usize]
+            } else {
+                0.0
+            };
+        }
+    }
+}
+
+#[cfg(feature = "audio-capture")]
+mod capture {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::{bail, Context, Result};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+    use super::AudioBackend;
+
+    /// Number of samples kept around for the analysis
+    const HISTORY_LENGTH: usize = 8192;
+
+    pub struct DeviceBackend {
+        history: Arc<Mutex<VecDeque<f32>>>,
+        sample_rate: u32,
+        _stream: cpal::Stream,
+    }
+
+    fn push_samples<I: Iterator<Item = f32>>(
+        history: &Mutex<VecDeque<f32>>,
+        samples: I,
+        channel_count: usize,
+    ) {
+        let mut history = history.lock().unwrap();
+        for sample in super::downmix(samples, channel_count) {
+            if history.len() == HISTORY_LENGTH {
+                history.pop_front();
+            }
+            history.push_back(sample);
+        }
+    }
+
+    impl DeviceBackend {
+        pub fn new(name: Option<&str>) -> Result<Self> {
+            let host = cpal::default_host();
+
+            let device = if let Some(name) = name {
+                host.input_devices()?.find(|device| {
+                    device
+                        .name()
+                        .map(|device_name| device_name.contains(name))
+                        .unwrap_or(false)
+                })
+            } else {
+                host.default_input_device()
+            }
+            .context(format!("Could not find audio capture device {:?}", name))?;
+
+            let config = device.default_input_config()?;
+            let sample_rate = config.sample_rate().0;
+            let channel_count = config.channels() as usize;
+
+            let history = Arc::new(Mutex::new(VecDeque::with_capacity(HISTORY_LENGTH)));
+            let stream_history = history.clone();
+            let on_error = |e| eprintln!("Audio capture failed: {:?}", e);
+
+            let stream = match config.sample_format() {
+                cpal::SampleFormat::F32 => device.build_input_stream(
+                    &config.into(),
+                    move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                        push_samples(&stream_history, data.iter().copied(), channel_count)
+                    },
+                    on_error,
+                )?,
+                cpal::SampleFormat::I16 => device.build_input_stream(
+                    &config.into(),
+                    move |data: &[i16], _: &cpal::InputCallbackInfo| {
+                        push_samples(
+                            &stream_history,
+                            data.iter().map(|sample| *sample as f32 / i16::MAX as f32),
+                            channel_count,
+                        )
+                    },
+                    on_error,
+                )?,
+                cpal::SampleFormat::U16 => device.build_input_stream(
+                    &config.into(),
+                    move |data: &[u16], _: &cpal::InputCallbackInfo| {
+                        push_samples(
+                            &stream_history,
+                            data.iter()
+                                .map(|sample| *sample as f32 / u16::MAX as f32 * 2.0 - 1.0),
+                            channel_count,
+                        )
+                    },
+                    on_error,
+                )?,
+            };
+
+            if stream.play().is_err() {
+                bail!("Failed to start audio capture");
+            }
+
+            Ok(Self {
+                history,
+                sample_rate,
+                _stream: stream,
+            })
+        }
+    }
+
+    impl AudioBackend for DeviceBackend {
+        fn sample_rate(&self) -> u32 {
+            self.sample_rate
+        }
+
+        fn read_window(&mut self, _time: f64, window: &mut [f32]) {
+            let history = self.history.lock().unwrap();
+            let padding = window.len().saturating_sub(history.len());
+
+            for value in window[..padding].iter_mut() {
+                *value = 0.0;
+            }
+            for (value, sample) in window[padding..].iter_mut().zip(
+                history
+                    .iter()
+                    .skip(history.len().saturating_sub(window.len())),
+            ) {
+                *value = *sample;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "audio-capture")]
+pub fn open_device(name: Option<&str>) -> Result<Box<dyn AudioBackend>> {
+    Ok(Box::new(capture::DeviceBackend::new(name)?))
+}
+
+#[cfg(not(feature = "audio-capture"))]
+pub fn open_device(_name: Option<&str>) -> Result<Box<dyn AudioBackend>> {
+    bail!("Audio capture devices require wvr to be built with the audio-capture feature")
+}
@@ -0,0 +1,56 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_data::types::InputProvider;
+
+use crate::utils::get_path_for_resource;
+
+pub mod analyzer;
+pub mod backend;
+pub mod provider;
+pub mod tempo;
+
+use backend::{AudioBackend, FileBackend};
+use provider::AudioProvider;
+use tempo::TempoTracker;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum AudioInputConfig {
+    /// Decodes a WAV or FLAC file, played in sync with the project time
+    File {
+        path: String,
+        #[serde(default)]
+        looping: bool,
+    },
+    /// Listens to a capture device, the default one when no name is given
+    Device {
+        #[serde(default)]
+        name: Option<String>,
+    },
+}
+
+pub fn audio_input_from_config<P: AsRef<Path>>(
+    project_path: P,
+    input_config: &AudioInputConfig,
+    input_name: &str,
+    tempo_tracker: Option<Arc<Mutex<TempoTracker>>>,
+) -> Result<Box<dyn InputProvider>> {
+    let backend: Box<dyn AudioBackend> = match input_config {
+        AudioInputConfig::File { path, looping } => {
+            let path = get_path_for_resource(&project_path, path);
+            Box::new(FileBackend::new(&path, *looping)?)
+        }
+        AudioInputConfig::Device { name } => backend::open_device(name.as_deref())?,
+    };
+
+    Ok(Box::new(AudioProvider::new(
+        input_name.to_owned(),
+        backend,
+        tempo_tracker,
+    )))
+}
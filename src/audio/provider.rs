@@ -0,0 +1,205 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use wvr_data::types::{DataHolder, InputProvider};
+
+use super::analyzer::{AudioAnalyzer, FFT_SIZE, TEXTURE_WIDTH};
+use super::backend::AudioBackend;
+use super::tempo::TempoTracker;
+
+/// Smoothing applied to the spectrum between two analyses, as Shadertoy does
+const DEFAULT_SMOOTHING: f32 = 0.8;
+
+/// Exposes the analysis of an audio stream to shaders.
+///
+/// The input name is bound to a 512x2 texture holding the spectrum in its first row and the waveform
+/// in its second row, while `<name>_rms`, `<name>_bass`, `<name>_mid` and `<name>_treble` hold the
+/// signal level and band energies.
+pub struct AudioProvider {
+    name: String,
+    backend: Box<dyn AudioBackend>,
+    analyzer: AudioAnalyzer,
+    tempo_tracker: Option<Arc<Mutex<TempoTracker>>>,
+
+    samples: Vec<f32>,
+    time: f64,
+    playing: bool,
+    analyzed: bool,
+}
+
+impl AudioProvider {
+    pub fn new(
+        name: String,
+        backend: Box<dyn AudioBackend>,
+        tempo_tracker: Option<Arc<Mutex<TempoTracker>>>,
+    ) -> Self {
+        Self {
+            name,
+            backend,
+            analyzer: AudioAnalyzer::new(DEFAULT_SMOOTHING),
+            tempo_tracker,
+
+            samples: vec![0.0; FFT_SIZE],
+            time: 0.0,
+            playing: true,
+            analyzed: false,
+        }
+    }
+
+    fn analyze(&mut self) {
+        // A paused input keeps the analysis it had, rather than smoothing it further
+        if self.analyzed || !self.playing {
+            return;
+        }
+
+        self.backend.read_window(self.time, &mut self.samples);
+        let analysis = self
+            .analyzer
+            .analyze(&self.samples, self.backend.sample_rate());
+
+        if let Some(tempo_tracker) = &self.tempo_tracker {
+            tempo_tracker.lock().unwrap().feed(self.time, analysis);
+        }
+
+        self.analyzed = true;
+    }
+
+    fn build_texture(&self) -> DataHolder {
+        let analysis = self.analyzer.get_analysis();
+
+        let mut data = Vec::with_capacity(TEXTURE_WIDTH * 2 * 3);
+        for value in analysis.spectrum.iter() {
+            let value = (value * 255.0) as u8;
+            data.extend_from_slice(&[value, value, value]);
+        }
+        for value in analysis.waveform.iter() {
+            let value = ((value * 0.5 + 0.5).clamp(0.0, 1.0) * 255.0) as u8;
+            data.extend_from_slice(&[value, value, value]);
+        }
+
+        DataHolder::Texture(((TEXTURE_WIDTH as u32, 2), data))
+    }
+}
+
+impl InputProvider for AudioProvider {
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_owned();
+    }
+
+    fn provides(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            format!("{:}_rms", self.name),
+            format!("{:}_bass", self.name),
+            format!("{:}_mid", self.name),
+            format!("{:}_treble", self.name),
+        ]
+    }
+
+    fn set_property(&mut self, _property: &str, _value: &DataHolder) {}
+
+    fn set_time(&mut self, time: f64, _sync: bool) {
+        if (time - self.time).abs() > f64::EPSILON {
+            self.analyzed = false;
+        }
+        self.time = time;
+
+        // The tempo tracker needs every frame, even when no shader reads this input
+        if self.tempo_tracker.is_some() {
+            self.analyze();
+        }
+    }
+
+    fn get(&mut self, uniform_name: &str, _invalidate: bool) -> Option<DataHolder> {
+        self.analyze();
+
+        let analysis = self.analyzer.get_analysis();
+        if uniform_name == self.name {
+            Some(self.build_texture())
+        } else if let Some(suffix) = uniform_name.strip_prefix(&format!("{:}_", self.name)) {
+            match suffix {
+                "rms" => Some(DataHolder::Float(analysis.rms)),
+                "bass" => Some(DataHolder::Float(analysis.bands[0])),
+                "mid" => Some(DataHolder::Float(analysis.bands[1])),
+                "treble" => Some(DataHolder::Float(analysis.bands[2])),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.playing = true;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.playing = false;
+        Ok(())
+    }
+
+    /// Stopping silences the input, while pausing freezes it
+    fn stop(&mut self) -> Result<()> {
+        self.playing = false;
+        self.analyzer.reset();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sine wave whose amplitude is the time it is read at
+    struct SwellingSine;
+
+    impl AudioBackend for SwellingSine {
+        fn sample_rate(&self) -> u32 {
+            48000
+        }
+
+        fn read_window(&mut self, time: f64, window: &mut [f32]) {
+            for (index, sample) in window.iter_mut().enumerate() {
+                let phase = 2.0 * std::f32::consts::PI * (64 * index) as f32 / FFT_SIZE as f32;
+                *sample = time as f32 * phase.sin();
+            }
+        }
+    }
+
+    fn uniform(provider: &mut AudioProvider, uniform_name: &str) -> f32 {
+        match provider.get(uniform_name, false) {
+            Some(DataHolder::Float(value)) => value,
+            _ => panic!("No float value for {:}", uniform_name),
+        }
+    }
+
+    #[test]
+    fn pausing_freezes_the_analysis_and_stopping_silences_it() {
+        let mut provider = AudioProvider::new("audio".to_owned(), Box::new(SwellingSine), None);
+
+        provider.set_time(0.5, false);
+        let rms = uniform(&mut provider, "audio_rms");
+        assert!((rms - 0.5 / 2f32.sqrt()).abs() < 1e-4);
+
+        provider.set_time(0.6, false);
+        let mid = uniform(&mut provider, "audio_mid");
+
+        provider.pause().unwrap();
+        for time in &[0.7, 0.8, 0.9] {
+            provider.set_time(*time, false);
+            assert_eq!(uniform(&mut provider, "audio_mid"), mid);
+        }
+
+        provider.stop().unwrap();
+        provider.set_time(1.0, false);
+        assert_eq!(uniform(&mut provider, "audio_rms"), 0.0);
+        assert_eq!(uniform(&mut provider, "audio_mid"), 0.0);
+
+        provider.play().unwrap();
+        provider.set_time(0.25, false);
+        let rms = uniform(&mut provider, "audio_rms");
+        assert!((rms - 0.25 / 2f32.sqrt()).abs() < 1e-4);
+    }
+}
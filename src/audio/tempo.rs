@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+
+use anyhow::{bail, Result};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::analyzer::AudioAnalysis;
+
+/// Sampling rate of the onset envelope the tempo is estimated from
+const ENVELOPE_RATE: f64 = 100.0;
+/// Duration of audio history used for each estimation, in seconds
+const HISTORY_DURATION: f64 = 8.0;
+/// Time between two estimations, in seconds
+const ESTIMATION_INTERVAL: f64 = 0.5;
+/// Tempo around which ambiguous estimations are resolved, halving or doubling errors being common
+const PREFERRED_BPM: f64 = 120.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TempoConfig {
+    /// Name of the audio input the tempo is detected from
+    pub input: String,
+    pub enable: bool,
+    /// Weight given to the current tempo and phase when applying a new estimation, between 0 and 1
+    pub smoothing: f64,
+    /// Keeps the current tempo and only corrects the beat phase
+    pub lock: bool,
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+}
+
+impl Default for TempoConfig {
+    fn default() -> Self {
+        Self {
+            input: String::new(),
+            enable: true,
+            smoothing: 0.9,
+            lock: false,
+            min_bpm: 70.0,
+            max_bpm: 180.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum TempoTrackingUpdate {
+    Enable(bool),
+    Lock(bool),
+    Smoothing(f64),
+}
+
+impl TempoConfig {
+    pub fn apply(&mut self, update: &TempoTrackingUpdate) {
+        match update {
+            TempoTrackingUpdate::Enable(enable) => self.enable = *enable,
+            TempoTrackingUpdate::Lock(lock) => self.lock = *lock,
+            TempoTrackingUpdate::Smoothing(smoothing) => self.smoothing = smoothing.clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoEstimate {
+    pub bpm: f64,
+    /// Project time of the most recent detected beat, in seconds
+    pub beat_time: f64,
+    /// Strength of the detected periodicity, between 0 and 1
+    pub confidence: f64,
+}
+
+/// Estimates the tempo and beat phase of an audio stream from the onsets found in its spectrum
+pub struct TempoTracker {
+    min_bpm: f64,
+    max_bpm: f64,
+
+    previous_spectrum: Vec<f32>,
+    onsets: VecDeque<(f64, f32)>,
+
+    last_estimation_time: f64,
+    estimate: Option<TempoEstimate>,
+}
+
+impl TempoTracker {
+    pub fn new(min_bpm: f64, max_bpm: f64) -> Result<Self> {
+        // The longest beat period sizes the autocorrelation, so it has to be finite
+        if min_bpm <= 0.0 {
+            bail!("The minimum tempo must be above 0 BPM, got {:}", min_bpm);
+        }
+        if max_bpm <= min_bpm {
+            bail!(
+                "The maximum tempo must be above the minimum one, got {:} to {:} BPM",
+                min_bpm,
+                max_bpm
+            );
+        }
+
+        Ok(Self {
+            min_bpm,
+            max_bpm,
+
+            previous_spectrum: Vec::new(),
+            onsets: VecDeque::new(),
+
+            last_estimation_time: 0.0,
+            estimate: None,
+        })
+    }
+
+    /// Returns the latest estimation, clearing it so that each one is only applied once
+    pub fn take_estimate(&mut self) -> Option<TempoEstimate> {
+        self.estimate.take()
+    }
+
+    pub fn feed(&mut self, time: f64, analysis: &AudioAnalysis) {
+        // Seeking or looping back invalidates the history
+        if let Some((last_time, _)) = self.onsets.back() {
+            if time < *last_time {
+                self.onsets.clear();
+                self.last_estimation_time = time;
+            } else if time - last_time < f64::EPSILON {
+                return;
+            }
+        }
+
+        // Spectral flux: the sum of the increases of each frequency bin
+        let onset = if self.previous_spectrum.len() == analysis.spectrum.len() {
+            analysis
+                .spectrum
+                .iter()
+                .zip(&self.previous_spectrum)
+                .map(|(value, previous)| (value - previous).max(0.0))
+                .sum()
+        } else {
+            0.0
+        };
+        self.previous_spectrum.clone_from(&analysis.spectrum);
+
+        self.onsets.push_back((time, onset));
+        while let Some((first_time, _)) = self.onsets.front() {
+            if time - first_time > HISTORY_DURATION {
+                self.onsets.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if time - self.last_estimation_time >= ESTIMATION_INTERVAL {
+            self.last_estimation_time = time;
+            if let Some(estimate) = self.estimate_tempo() {
+                self.estimate = Some(estimate);
+            }
+        }
+    }
+
+    /// Resamples the onsets, which arrive at the render rate, to a regular envelope
+    fn build_envelope(&self) -> Option<(Vec<f64>, f64)> {
+        let (start_time, _) = *self.onsets.front()?;
+        let (end_time, _) = *self.onsets.back()?;
+
+        let length = ((end_time - start_time) * ENVELOPE_RATE) as usize;
+        if (length as f64) < HISTORY_DURATION * ENVELOPE_RATE * 0.5 {
+            return None;
+        }
+
+        let mut envelope = Vec::with_capacity(length);
+        let mut onsets = self.onsets.iter().peekable();
+        let mut previous = (start_time, 0.0);
+        for index in 0..length {
+            let time = start_time + index as f64 / ENVELOPE_RATE;
+            while let Some((onset_time, onset)) = onsets.peek() {
+                if *onset_time > time {
+                    break;
+                }
+                previous = (*onset_time, *onset as f64);
+                onsets.next();
+            }
+
+            let value = match onsets.peek() {
+                Some((next_time, next_onset)) if *next_time > previous.0 => {
+                    let ratio = (time - previous.0) / (next_time - previous.0);
+                    previous.1 + (*next_onset as f64 - previous.1) * ratio
+                }
+                _ => previous.1,
+            };
+            envelope.push(value);
+        }
+
+        let mean = envelope.iter().sum::<f64>() / envelope.len() as f64;
+        for value in envelope.iter_mut() {
+            *value = (*value - mean).max(0.0);
+        }
+
+        Some((envelope, start_time))
+    }
+
+    fn estimate_tempo(&self) -> Option<TempoEstimate> {
+        let (envelope, start_time) = self.build_envelope()?;
+
+        let min_lag = (60.0 * ENVELOPE_RATE / self.max_bpm).floor() as usize;
+        let max_lag = (60.0 * ENVELOPE_RATE / self.min_bpm).ceil() as usize;
+        if max_lag * 2 >= envelope.len() {
+            return None;
+        }
+
+        let energy = envelope.iter().map(|value| value * value).sum::<f64>();
+        if energy <= f64::EPSILON {
+            return None;
+        }
+
+        let autocorrelation = |lag: usize| -> f64 {
+            envelope
+                .iter()
+                .zip(&envelope[lag..])
+                .map(|(a, b)| a * b)
+                .sum::<f64>()
+                / energy
+        };
+
+        let mut best_lag = 0;
+        let mut best_score = 0.0;
+        let mut scores = vec![0.0; max_lag + 2];
+        for (lag, score) in scores.iter_mut().enumerate().skip(min_lag.max(1)) {
+            *score = autocorrelation(lag);
+
+            // Favours tempos close to the preferred one, on a logarithmic scale
+            let bpm = 60.0 * ENVELOPE_RATE / lag as f64;
+            let weight = (-0.5 * (bpm / PREFERRED_BPM).log2().powi(2)).exp();
+
+            if lag <= max_lag && *score * weight > best_score {
+                best_score = *score * weight;
+                best_lag = lag;
+            }
+        }
+
+        if best_lag == 0 {
+            return None;
+        }
+
+        // Parabolic interpolation around the peak for a sub-sample lag
+        let (previous, current, next) =
+            (scores[best_lag - 1], scores[best_lag], scores[best_lag + 1]);
+        let denominator = previous - 2.0 * current + next;
+        let lag = if denominator.abs() > f64::EPSILON {
+            best_lag as f64 + 0.5 * (previous - next) / denominator
+        } else {
+            best_lag as f64
+        };
+
+        // The phase is the offset of the pulse train best matching the recent onsets
+        let mut best_offset = 0;
+        let mut best_phase_score = 0.0;
+        for offset in 0..best_lag {
+            let mut score = 0.0;
+            let mut position = envelope.len() as f64 - 1.0 - offset as f64;
+            while position >= 0.0 {
+                score += envelope[position as usize];
+                position -= lag;
+            }
+
+            if score > best_phase_score {
+                best_phase_score = score;
+                best_offset = offset;
+            }
+        }
+
+        Some(TempoEstimate {
+            bpm: 60.0 * ENVELOPE_RATE / lag,
+            beat_time: start_time + (envelope.len() - 1 - best_offset) as f64 / ENVELOPE_RATE,
+            confidence: current.clamp(0.0, 1.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_RATE: f64 = 60.0;
+    /// Time of the first click, so that beats do not fall on whole seconds
+    const FIRST_CLICK_TIME: f64 = 0.3;
+
+    /// Analysis of a click train at `bpm` heard at `time`, each click lighting the whole
+    /// spectrum before fading out
+    fn click_train_analysis(bpm: f64, time: f64) -> AudioAnalysis {
+        let period = 60.0 / bpm;
+        let since_click = (time - FIRST_CLICK_TIME).rem_euclid(period);
+        let level = if time >= FIRST_CLICK_TIME {
+            (-since_click / 0.05).exp() as f32
+        } else {
+            0.0
+        };
+
+        AudioAnalysis {
+            spectrum: vec![level; 512],
+            ..AudioAnalysis::default()
+        }
+    }
+
+    /// Feeds the tracker frames from `start_time` to `end_time`, returning the last estimation
+    fn feed_click_train(
+        tempo_tracker: &mut TempoTracker,
+        bpm: f64,
+        start_time: f64,
+        end_time: f64,
+    ) -> Option<TempoEstimate> {
+        let mut estimate = None;
+        let frame_count = ((end_time - start_time) * FRAME_RATE) as usize;
+        for frame in 0..frame_count {
+            let time = start_time + frame as f64 / FRAME_RATE;
+            tempo_tracker.feed(time, &click_train_analysis(bpm, time));
+            if let Some(new_estimate) = tempo_tracker.take_estimate() {
+                estimate = Some(new_estimate);
+            }
+        }
+
+        estimate
+    }
+
+    /// Distance from `time` to the closest click, in beats
+    fn phase_error(bpm: f64, time: f64) -> f64 {
+        let beats = (time - FIRST_CLICK_TIME) * bpm / 60.0;
+        (beats - beats.round()).abs()
+    }
+
+    #[test]
+    fn the_tempo_and_phase_of_a_click_train_are_found() {
+        for bpm in &[90.0, 120.0, 128.0, 150.0] {
+            let mut tempo_tracker = TempoTracker::new(70.0, 180.0).unwrap();
+            let estimate = feed_click_train(&mut tempo_tracker, *bpm, 0.0, 12.0).unwrap();
+
+            assert!(
+                (estimate.bpm - bpm).abs() < 1.0,
+                "{:} BPM found for {:} BPM",
+                estimate.bpm,
+                bpm
+            );
+            assert!(phase_error(*bpm, estimate.beat_time) < 0.1);
+            // The beat found is one of the last ones heard before the estimation
+            assert!(12.0 - estimate.beat_time < 60.0 / bpm + ESTIMATION_INTERVAL);
+            assert!(estimate.confidence > 0.4);
+        }
+    }
+
+    #[test]
+    fn estimations_wait_for_enough_history() {
+        let mut tempo_tracker = TempoTracker::new(70.0, 180.0).unwrap();
+        assert_eq!(feed_click_train(&mut tempo_tracker, 120.0, 0.0, 3.0), None);
+        assert!(feed_click_train(&mut tempo_tracker, 120.0, 3.0, 6.0).is_some());
+
+        // Seeking back starts the history over
+        assert_eq!(feed_click_train(&mut tempo_tracker, 120.0, 1.0, 4.0), None);
+    }
+
+    #[test]
+    fn silence_gives_no_tempo() {
+        let mut tempo_tracker = TempoTracker::new(70.0, 180.0).unwrap();
+        for frame in 0..600 {
+            tempo_tracker.feed(frame as f64 / FRAME_RATE, &AudioAnalysis::default());
+        }
+
+        assert_eq!(tempo_tracker.take_estimate(), None);
+    }
+
+    #[test]
+    fn tempo_ranges_have_to_be_valid() {
+        assert!(TempoTracker::new(0.0, 180.0).is_err());
+        assert!(TempoTracker::new(120.0, 120.0).is_err());
+    }
+}
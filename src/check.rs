@@ -0,0 +1,1129 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use wvr_data::config::filter::FilterConfig;
+use wvr_data::config::project::ProjectConfig;
+
+use crate::config::ExtendedConfig;
+use crate::format::{self, ConfigFormat};
+use crate::layer;
+
+/// Something wrong in a project, located in the file it comes from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{:}:{:}: {:}", self.path.display(), line, self.message),
+            None => write!(f, "{:}: {:}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// Lines at which the values of a config document start, by JSON pointer
+struct ValueLines {
+    lines: HashMap<String, usize>,
+}
+
+/// Pointer segment of a key, the `+` of keys appending to an included list being dropped
+/// so that their values are found at the pointer of the merged list
+fn key_segment(key: &str) -> String {
+    escape_pointer(key.strip_suffix('+').unwrap_or(key))
+}
+
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+struct DocumentScanner<'a> {
+    text: &'a [u8],
+    position: usize,
+    line: usize,
+    lines: HashMap<String, usize>,
+}
+
+impl<'a> DocumentScanner<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text: text.as_bytes(),
+            position: 0,
+            line: 1,
+            lines: HashMap::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.position).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.text.get(self.position + offset).copied()
+    }
+
+    fn advance(&mut self) {
+        if self.peek() == Some(b'\n') {
+            self.line += 1;
+        }
+        self.position += 1;
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') = self.peek() {
+            self.advance();
+        }
+    }
+
+    /// Skips whitespace and the `//` and `/* */` comments RON allows
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            self.skip_whitespace();
+            match (self.peek(), self.peek_at(1)) {
+                (Some(b'/'), Some(b'/')) => {
+                    while !matches!(self.peek(), Some(b'\n') | None) {
+                        self.advance();
+                    }
+                }
+                (Some(b'/'), Some(b'*')) => {
+                    self.advance();
+                    self.advance();
+                    while !matches!(
+                        (self.peek(), self.peek_at(1)),
+                        (Some(b'*'), Some(b'/')) | (None, _)
+                    ) {
+                        self.advance();
+                    }
+                    self.advance();
+                    self.advance();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Reads a string delimited by `quote`, such as a JSON string or a RON char
+    fn read_string(&mut self, quote: u8) -> String {
+        let mut string = Vec::new();
+
+        // Opening quote
+        self.advance();
+        while let Some(byte) = self.peek() {
+            self.advance();
+            match byte {
+                _ if byte == quote => break,
+                b'\\' => {
+                    if let Some(escaped_byte) = self.peek() {
+                        string.push(escaped_byte);
+                        self.advance();
+                    }
+                }
+                _ => string.push(byte),
+            }
+        }
+
+        String::from_utf8_lossy(&string).into_owned()
+    }
+
+    /// Reads a RON raw string such as `r#"text"#`, which has no escapes
+    fn read_raw_string(&mut self) -> String {
+        // Leading `r`
+        self.advance();
+        let mut hash_count = 0;
+        while self.peek() == Some(b'#') {
+            hash_count += 1;
+            self.advance();
+        }
+        // Opening quote
+        self.advance();
+
+        let start = self.position;
+        while let Some(byte) = self.peek() {
+            if byte == b'"' && (1..=hash_count).all(|offset| self.peek_at(offset) == Some(b'#')) {
+                let string = String::from_utf8_lossy(&self.text[start..self.position]).into_owned();
+                for _ in 0..=hash_count {
+                    self.advance();
+                }
+                return string;
+            }
+            self.advance();
+        }
+
+        String::from_utf8_lossy(&self.text[start..]).into_owned()
+    }
+
+    fn is_raw_string_start(&self) -> bool {
+        self.peek() == Some(b'r') && matches!(self.peek_at(1), Some(b'"') | Some(b'#'))
+    }
+
+    fn read_identifier(&mut self) -> String {
+        let start = self.position;
+        while let Some(byte) = self.peek() {
+            if !(byte.is_ascii_alphanumeric() || byte == b'_') {
+                break;
+            }
+            self.advance();
+        }
+
+        String::from_utf8_lossy(&self.text[start..self.position]).into_owned()
+    }
+
+    /// Skips a number, a boolean or any other value written without delimiters
+    fn skip_scalar(&mut self) {
+        while let Some(byte) = self.peek() {
+            if matches!(byte, b',' | b'}' | b']' | b')' | b':') || byte.is_ascii_whitespace() {
+                break;
+            }
+            self.advance();
+        }
+    }
+
+    fn scan_json_value(&mut self, pointer: String) {
+        self.skip_whitespace();
+        self.lines.insert(pointer.clone(), self.line);
+
+        match self.peek() {
+            Some(b'{') => {
+                self.advance();
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b'"') => {
+                            let key = self.read_string(b'"');
+                            self.skip_whitespace();
+                            // Colon
+                            self.advance();
+                            self.scan_json_value(format!("{:}/{:}", pointer, key_segment(&key)));
+                        }
+                        Some(b',') => self.advance(),
+                        Some(b'}') => {
+                            self.advance();
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.advance();
+                let mut index = 0;
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.advance(),
+                        Some(b']') => {
+                            self.advance();
+                            break;
+                        }
+                        Some(_) => {
+                            self.scan_json_value(format!("{:}/{:}", pointer, index));
+                            index += 1;
+                        }
+                        None => break,
+                    }
+                }
+            }
+            Some(b'"') => {
+                self.read_string(b'"');
+            }
+            _ => self.skip_scalar(),
+        }
+    }
+
+    /// Whether the parenthesis just read opens a struct, its first field being named,
+    /// rather than a tuple
+    fn opens_ron_struct(&mut self) -> bool {
+        let (position, line) = (self.position, self.line);
+
+        self.skip_whitespace_and_comments();
+        let is_struct = !self.read_identifier().is_empty() && {
+            self.skip_whitespace_and_comments();
+            self.peek() == Some(b':') && self.peek_at(1) != Some(b':')
+        };
+
+        self.position = position;
+        self.line = line;
+        is_struct
+    }
+
+    /// Scans the content of a parenthesis, read as an object for structs
+    /// and as a list for tuples and enum variants, as RON reads them without their type
+    fn scan_ron_parenthesis(&mut self, pointer: &str) {
+        // Opening parenthesis
+        self.advance();
+        let is_struct = self.opens_ron_struct();
+
+        let mut index = 0;
+        loop {
+            self.skip_whitespace_and_comments();
+            match self.peek() {
+                Some(b',') => self.advance(),
+                Some(b')') => {
+                    self.advance();
+                    break;
+                }
+                None => break,
+                Some(_) if is_struct => {
+                    let key = self.read_identifier();
+                    if key.is_empty() {
+                        break;
+                    }
+                    self.skip_whitespace_and_comments();
+                    // Colon
+                    self.advance();
+                    self.scan_ron_value(format!("{:}/{:}", pointer, key_segment(&key)));
+                }
+                Some(_) => {
+                    self.scan_ron_value(format!("{:}/{:}", pointer, index));
+                    index += 1;
+                }
+            }
+        }
+    }
+
+    fn scan_ron_value(&mut self, pointer: String) {
+        self.skip_whitespace_and_comments();
+        self.lines.entry(pointer.clone()).or_insert(self.line);
+
+        match self.peek() {
+            Some(b'{') => {
+                self.advance();
+                loop {
+                    self.skip_whitespace_and_comments();
+                    let key = match self.peek() {
+                        Some(b',') => {
+                            self.advance();
+                            continue;
+                        }
+                        Some(b'}') => {
+                            self.advance();
+                            break;
+                        }
+                        None => break,
+                        Some(b'"') => self.read_string(b'"'),
+                        Some(_) if self.is_raw_string_start() => self.read_raw_string(),
+                        Some(_) => {
+                            let start = self.position;
+                            self.skip_scalar();
+                            if self.position == start {
+                                break;
+                            }
+                            String::from_utf8_lossy(&self.text[start..self.position]).into_owned()
+                        }
+                    };
+                    self.skip_whitespace_and_comments();
+                    // Colon
+                    self.advance();
+                    self.scan_ron_value(format!("{:}/{:}", pointer, key_segment(&key)));
+                }
+            }
+            Some(b'[') => {
+                self.advance();
+                let mut index = 0;
+                loop {
+                    self.skip_whitespace_and_comments();
+                    match self.peek() {
+                        Some(b',') => self.advance(),
+                        Some(b']') => {
+                            self.advance();
+                            break;
+                        }
+                        Some(_) => {
+                            self.scan_ron_value(format!("{:}/{:}", pointer, index));
+                            index += 1;
+                        }
+                        None => break,
+                    }
+                }
+            }
+            Some(b'(') => self.scan_ron_parenthesis(&pointer),
+            Some(b'"') => {
+                self.read_string(b'"');
+            }
+            Some(b'\'') => {
+                self.read_string(b'\'');
+            }
+            Some(_) if self.is_raw_string_start() => {
+                self.read_raw_string();
+            }
+            Some(byte) if byte.is_ascii_alphabetic() || byte == b'_' => {
+                let identifier = self.read_identifier();
+                self.skip_whitespace_and_comments();
+                if self.peek() == Some(b'(') {
+                    if identifier == "Some" {
+                        // An option holds its value directly
+                        self.advance();
+                        self.scan_ron_value(pointer);
+                        self.skip_whitespace_and_comments();
+                        if self.peek() == Some(b')') {
+                            self.advance();
+                        }
+                    } else {
+                        self.scan_ron_parenthesis(&pointer);
+                    }
+                }
+            }
+            _ => self.skip_scalar(),
+        }
+    }
+}
+
+/// Splits a TOML key such as `a."b.c".d` into its parts
+fn split_toml_key(key: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quote = None;
+
+    for character in key.chars() {
+        match (quote, character) {
+            (None, '"') | (None, '\'') => quote = Some(character),
+            (Some(opening_quote), _) if character == opening_quote => quote = None,
+            (None, '.') => parts.push(std::mem::take(&mut part).trim().to_owned()),
+            _ => part.push(character),
+        }
+    }
+    parts.push(part.trim().to_owned());
+
+    parts
+}
+
+/// Position of the first `=` of a TOML line that is not within a string
+fn toml_assignment(line: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, character) in line.char_indices() {
+        match (quote, character) {
+            (None, '"') | (None, '\'') => quote = Some(character),
+            (Some(opening_quote), _) if character == opening_quote => quote = None,
+            (None, '=') => return Some(index),
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Finds the line of every key and table of a TOML document. Values written inline are
+/// located at the line of their key.
+fn scan_toml(text: &str) -> HashMap<String, usize> {
+    let mut lines = HashMap::new();
+    lines.insert(String::new(), 1);
+
+    // Number of elements of each array of tables seen so far
+    let mut table_counts: HashMap<String, usize> = HashMap::new();
+    let resolve = |keys: &[String], table_counts: &HashMap<String, usize>| {
+        let mut pointer = String::new();
+        for key in keys {
+            pointer = format!("{:}/{:}", pointer, key_segment(key));
+            if let Some(count) = table_counts.get(&pointer) {
+                pointer = format!("{:}/{:}", pointer, count - 1);
+            }
+        }
+        pointer
+    };
+
+    let mut table = String::new();
+    let mut multiline_delimiter: Option<&str> = None;
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.trim();
+
+        if let Some(delimiter) = multiline_delimiter {
+            if line.contains(delimiter) {
+                multiline_delimiter = None;
+            }
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix("[[") {
+            let keys = split_toml_key(header.split("]]").next().unwrap_or_default());
+            let (last_key, parent_keys) = keys.split_last().unwrap();
+            let array_pointer = format!(
+                "{:}/{:}",
+                resolve(parent_keys, &table_counts),
+                key_segment(last_key)
+            );
+            lines.entry(array_pointer.clone()).or_insert(line_number);
+
+            let count = table_counts.entry(array_pointer.clone()).or_insert(0);
+            table = format!("{:}/{:}", array_pointer, count);
+            *count += 1;
+            lines.insert(table.clone(), line_number);
+        } else if let Some(header) = line.strip_prefix('[') {
+            let keys = split_toml_key(header.split(']').next().unwrap_or_default());
+            table = resolve(&keys, &table_counts);
+            lines.insert(table.clone(), line_number);
+        } else if let Some(assignment) = toml_assignment(line) {
+            // Lines within multiline arrays, such as inline tables, are not keys
+            let is_key = line
+                .chars()
+                .next()
+                .map(|character| character.is_ascii_alphanumeric() || "_-\"'".contains(character))
+                .unwrap_or(false);
+            if !is_key {
+                continue;
+            }
+
+            let keys = split_toml_key(&line[..assignment]);
+            let mut pointer = table.clone();
+            for key in &keys {
+                pointer = format!("{:}/{:}", pointer, key_segment(key));
+            }
+            lines.insert(pointer, line_number);
+
+            let value = &line[assignment + 1..];
+            for delimiter in &["\"\"\"", "'''"] {
+                if value.matches(delimiter).count() % 2 == 1 {
+                    multiline_delimiter = Some(delimiter);
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+impl ValueLines {
+    fn new(config_format: ConfigFormat, text: &str) -> Self {
+        let lines = match config_format {
+            ConfigFormat::Json => {
+                let mut scanner = DocumentScanner::new(text);
+                scanner.scan_json_value(String::new());
+                scanner.lines
+            }
+            ConfigFormat::Ron => {
+                let mut scanner = DocumentScanner::new(text);
+                scanner.scan_ron_value(String::new());
+                scanner.lines
+            }
+            ConfigFormat::Toml => scan_toml(text),
+        };
+
+        Self { lines }
+    }
+
+    /// Line of the value at `pointer`, or of the closest value holding it, along with
+    /// the length of the pointer found
+    fn locate(&self, pointer: &str) -> Option<(usize, usize)> {
+        let mut pointer = pointer;
+        loop {
+            if let Some(line) = self.lines.get(pointer) {
+                return Some((pointer.len(), *line));
+            }
+
+            pointer = &pointer[..pointer.rfind('/')?];
+        }
+    }
+
+    /// Deepest value starting at or before `line`, to which an error reported at that line
+    /// belongs
+    fn pointer_at(&self, line: usize) -> Option<&str> {
+        self.lines
+            .iter()
+            .filter(|(_, value_line)| **value_line <= line)
+            .max_by_key(|(pointer, value_line)| (**value_line, pointer.len()))
+            .map(|(pointer, _)| pointer.as_str())
+    }
+}
+
+/// Parses a config, returning the line of the first error along with its description
+fn parse_typed<T: DeserializeOwned>(
+    config_format: ConfigFormat,
+    text: &str,
+) -> Result<(), (Option<usize>, String)> {
+    match config_format {
+        ConfigFormat::Json => serde_json::from_str::<T>(text)
+            .map(|_| ())
+            .map_err(|e| (Some(e.line()), e.to_string())),
+        ConfigFormat::Ron => ron::from_str::<T>(text).map(|_| ()).map_err(|e| {
+            let line = match &e {
+                ron::Error::Parser(_, position) => Some(position.line),
+                _ => None,
+            };
+            (line, e.to_string())
+        }),
+        ConfigFormat::Toml => toml::from_str::<T>(text).map(|_| ()).map_err(|e| {
+            // TOML lines are counted from zero
+            (e.line_col().map(|(line, _)| line + 1), e.to_string())
+        }),
+    }
+}
+
+/// A config read for checking, along with where its values sit in the files it was read from
+struct CheckedFile {
+    path: PathBuf,
+    value: Value,
+    /// Lines of the values of each file, the ones setting a value last coming first
+    layers: Vec<(PathBuf, ValueLines)>,
+}
+
+impl CheckedFile {
+    /// Reads and parses a file, reporting why it cannot be checked further
+    fn read<T: DeserializeOwned>(path: &Path, problems: &mut Vec<Problem>) -> Option<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                problems.push(Problem {
+                    path: path.to_owned(),
+                    line: None,
+                    message: format!("Failed to read the file: {:}", e),
+                });
+                return None;
+            }
+        };
+
+        let config_format = match ConfigFormat::from_path(path) {
+            Ok(config_format) => config_format,
+            Err(e) => {
+                problems.push(Problem {
+                    path: path.to_owned(),
+                    line: None,
+                    message: e.to_string(),
+                });
+                return None;
+            }
+        };
+
+        // Parsing the text directly rather than a `Value` keeps the position of type errors
+        if let Err((line, message)) = parse_typed::<T>(config_format, &text) {
+            problems.push(Problem {
+                path: path.to_owned(),
+                line,
+                message,
+            });
+            return None;
+        }
+
+        Some(Self {
+            path: path.to_owned(),
+            value: config_format.parse(&text).ok()?,
+            layers: vec![(path.to_owned(), ValueLines::new(config_format, &text))],
+        })
+    }
+
+    /// Reads a config along with the fragments it includes, problems being located
+    /// in the file setting the faulty value
+    fn read_layered(path: &Path, problems: &mut Vec<Problem>) -> Option<Self> {
+        let value = match layer::read_layered_config(path) {
+            Ok(value) => value,
+            Err(e) => {
+                problems.push(Problem {
+                    path: path.to_owned(),
+                    line: None,
+                    message: format!("{:#}", e),
+                });
+                return None;
+            }
+        };
+
+        // The root file is applied after its fragments, which are applied in order
+        let mut layer_files = layer::layer_files(path).unwrap_or_default();
+        if !layer_files.is_empty() {
+            layer_files[0] = path.to_owned();
+            layer_files[1..].reverse();
+        }
+        let layers = layer_files
+            .into_iter()
+            .filter_map(|layer_path| {
+                let config_format = ConfigFormat::from_path(&layer_path).ok()?;
+                let text = fs::read_to_string(&layer_path).ok()?;
+                let lines = ValueLines::new(config_format, &text);
+                Some((layer_path, lines))
+            })
+            .collect();
+
+        let checked_file = Self {
+            path: path.to_owned(),
+            value,
+            layers,
+        };
+
+        // The merged document is written out as JSON to find where its type errors sit
+        let merged_text = serde_json::to_string_pretty(&checked_file.value).ok()?;
+        let merged_lines = ValueLines::new(ConfigFormat::Json, &merged_text);
+        let project_error = serde_json::from_str::<ProjectConfig>(&merged_text).err();
+        let extended_error = serde_json::from_str::<ExtendedConfig>(&merged_text).err();
+        let is_valid = project_error.is_none();
+        for e in project_error.into_iter().chain(extended_error) {
+            let problem = match merged_lines.pointer_at(e.line()) {
+                Some(pointer) => checked_file.problem(pointer, e.to_string()),
+                None => Problem {
+                    path: path.to_owned(),
+                    line: None,
+                    message: e.to_string(),
+                },
+            };
+            problems.push(problem);
+        }
+        if !is_valid {
+            return None;
+        }
+
+        Some(checked_file)
+    }
+
+    /// Locates a problem in the file setting the value at `pointer`, or the closest value
+    /// holding it
+    fn problem(&self, pointer: &str, message: String) -> Problem {
+        let mut location: Option<(&Path, usize, usize)> = None;
+        for (layer_path, lines) in &self.layers {
+            if let Some((found_length, line)) = lines.locate(pointer) {
+                if location.map_or(true, |(_, best_length, _)| found_length > best_length) {
+                    location = Some((layer_path, found_length, line));
+                }
+            }
+        }
+
+        match location {
+            Some((layer_path, _, line)) => Problem {
+                path: layer_path.to_owned(),
+                line: Some(line),
+                message,
+            },
+            None => Problem {
+                path: self.path.clone(),
+                line: None,
+                message,
+            },
+        }
+    }
+}
+
+/// Reads every filter config found under `searched_path`, named as `load_available_filter_list` does
+fn check_filters(
+    searched_path: &Path,
+    prefix: &str,
+    filters: &mut HashMap<String, CheckedFile>,
+    problems: &mut Vec<Problem>,
+) {
+    let folder_entries = match searched_path.read_dir() {
+        Ok(folder_entries) => folder_entries,
+        Err(_) => return,
+    };
+
+    for folder_entry in folder_entries.flatten() {
+        let filter_path = folder_entry.path();
+        if !filter_path.is_dir() {
+            continue;
+        }
+
+        let filter_name = format!(
+            "{:}{:}",
+            prefix,
+            filter_path.file_name().unwrap().to_string_lossy()
+        );
+        if let Some(filter_config_path) = format::find_config_file(&filter_path) {
+            if let Some(filter) = CheckedFile::read::<FilterConfig>(&filter_config_path, problems) {
+                filters.insert(filter_name, filter);
+            }
+        } else {
+            check_filters(
+                &filter_path,
+                &format!("{:}/", filter_name),
+                filters,
+                problems,
+            );
+        }
+    }
+}
+
+/// Name of the input or stage a sampler reads from, samplers holding it in their single variant
+fn sampler_source(sampler: &Value) -> Option<&str> {
+    match sampler {
+        Value::String(source) => Some(source),
+        Value::Object(variant) if variant.len() == 1 => variant.values().next()?.as_str(),
+        // RON variants lose their name when read without their type
+        Value::Array(variant) if variant.len() == 1 => variant[0].as_str(),
+        _ => None,
+    }
+}
+
+fn check_stage(
+    project: &CheckedFile,
+    stage_pointer: &str,
+    filters: &HashMap<String, CheckedFile>,
+    known_sources: &HashSet<String>,
+    problems: &mut Vec<Problem>,
+) {
+    let stage = match project.value.pointer(stage_pointer) {
+        Some(stage) => stage,
+        None => return,
+    };
+    let stage_name = stage["name"].as_str().unwrap_or_default();
+
+    let empty_inputs = serde_json::Map::new();
+    let stage_inputs = stage["inputs"].as_object().unwrap_or(&empty_inputs);
+
+    for (uniform_name, sampler) in stage_inputs {
+        let input_pointer = format!(
+            "{:}/inputs/{:}",
+            stage_pointer,
+            escape_pointer(uniform_name)
+        );
+
+        match sampler_source(sampler) {
+            Some(source) if known_sources.contains(source) => (),
+            Some(source) => problems.push(project.problem(
+                &input_pointer,
+                format!(
+                    "Input {:?} of stage {:?} reads from {:?}, which is not an input, an audio input or an earlier stage",
+                    uniform_name, stage_name, source
+                ),
+            )),
+            None => problems.push(project.problem(
+                &input_pointer,
+                format!(
+                    "Input {:?} of stage {:?} does not name what it reads from",
+                    uniform_name, stage_name
+                ),
+            )),
+        }
+    }
+
+    let filter_name = stage["filter"].as_str().unwrap_or_default();
+    let filter = match filters.get(filter_name) {
+        Some(filter) => filter,
+        None => {
+            problems.push(project.problem(
+                &format!("{:}/filter", stage_pointer),
+                format!(
+                    "Stage {:?} uses the filter {:?}, which does not exist",
+                    stage_name, filter_name
+                ),
+            ));
+            return;
+        }
+    };
+
+    let filter_inputs: Vec<&str> = match &filter.value["inputs"] {
+        Value::Array(filter_inputs) => filter_inputs
+            .iter()
+            .filter_map(|filter_input| filter_input.as_str())
+            .collect(),
+        Value::Object(filter_inputs) => filter_inputs.keys().map(String::as_str).collect(),
+        _ => Vec::new(),
+    };
+
+    for filter_input in filter_inputs {
+        if !stage_inputs.contains_key(filter_input) {
+            problems.push(project.problem(
+                &format!("{:}/inputs", stage_pointer),
+                format!(
+                    "Stage {:?} does not bind the input {:?} of the filter {:?}",
+                    stage_name, filter_input, filter_name
+                ),
+            ));
+        }
+    }
+}
+
+/// Checks a project and the filters it can use, returning every problem found
+pub fn check_project(config_path: &Path) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let project = if layer::has_includes(config_path).unwrap_or(false) {
+        CheckedFile::read_layered(config_path, &mut problems)
+    } else {
+        let project = CheckedFile::read::<ProjectConfig>(config_path, &mut problems);
+        // The extended settings sit in the same file, and a mistake in them stops wvr as well
+        if project.is_some() {
+            CheckedFile::read::<ExtendedConfig>(config_path, &mut problems);
+        }
+        project
+    };
+    let project = match project {
+        Some(project) => project,
+        None => return problems,
+    };
+
+    let mut filters = HashMap::new();
+    check_filters(
+        &wvr_data::get_filters_path(),
+        "",
+        &mut filters,
+        &mut problems,
+    );
+    if let Some(project_path) = config_path.parent() {
+        check_filters(
+            &project_path.join("filters"),
+            "",
+            &mut filters,
+            &mut problems,
+        );
+    }
+
+    // Audio inputs are read as textures too
+    let mut known_sources: HashSet<String> = ["inputs", "audio_inputs"]
+        .iter()
+        .filter_map(|section| project.value[*section].as_object())
+        .flat_map(|inputs| inputs.keys().cloned())
+        .collect();
+
+    let stage_count = project.value["render_chain"]
+        .as_array()
+        .map(Vec::len)
+        .unwrap_or(0);
+    for stage_index in 0..stage_count {
+        let stage_pointer = format!("/render_chain/{:}", stage_index);
+        check_stage(
+            &project,
+            &stage_pointer,
+            &filters,
+            &known_sources,
+            &mut problems,
+        );
+
+        if let Some(stage_name) = project.value["render_chain"][stage_index]["name"].as_str() {
+            known_sources.insert(stage_name.to_owned());
+        }
+    }
+
+    check_stage(
+        &project,
+        "/final_stage",
+        &filters,
+        &known_sources,
+        &mut problems,
+    );
+
+    problems
+}
+
+/// Finds the config file of a project given by name, folder or config file
+pub fn find_project_config(project: &str) -> PathBuf {
+    let project_path = PathBuf::from(project);
+
+    if project_path.is_file() {
+        project_path
+    } else {
+        let project_path = if project_path.is_dir() {
+            project_path
+        } else {
+            wvr_data::get_data_path().join("projects").join(project)
+        };
+
+        format::find_config_file(&project_path).unwrap_or_else(|| project_path.join("config.json"))
+    }
+}
+
+/// Prints the problems of a project, returning whether it has none
+pub fn run_check(config_path: &Path) -> bool {
+    let problems = check_project(config_path);
+
+    for problem in &problems {
+        eprintln!("{:}", problem);
+    }
+
+    // RON only gives the position of syntax errors, and TOML not always that of type errors
+    let unlocated_count = problems
+        .iter()
+        .filter(|problem| problem.line.is_none())
+        .count();
+    if unlocated_count > 0 {
+        eprintln!(
+            "{:} problem(s) could not be located at a line: the file could not be read, \
+             or its format does not tell where the error is",
+            unlocated_count
+        );
+    }
+
+    if problems.is_empty() {
+        println!("No problem found in {:?}", config_path);
+    } else {
+        eprintln!("{:} problem(s) found in {:?}", problems.len(), config_path);
+    }
+
+    problems.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(config_format: ConfigFormat, text: &str) -> ValueLines {
+        ValueLines::new(config_format, text)
+    }
+
+    fn line(value_lines: &ValueLines, pointer: &str) -> Option<usize> {
+        value_lines.locate(pointer).map(|(_, line)| line)
+    }
+
+    #[test]
+    fn json_values_are_located_by_pointer() {
+        let json_lines = lines(
+            ConfigFormat::Json,
+            r#"{
+    "view": {
+        "width": 640,
+        "title": "a } b { c"
+    },
+    "render_chain": [
+        {
+            "name": "blur",
+            "inputs": {"iChannel0": {"Linear": "forest"}}
+        },
+        {"name": "glow"}
+    ],
+    "final_stage": {}
+}"#,
+        );
+
+        assert_eq!(line(&json_lines, ""), Some(1));
+        assert_eq!(line(&json_lines, "/view/width"), Some(3));
+        assert_eq!(line(&json_lines, "/view/title"), Some(4));
+        assert_eq!(line(&json_lines, "/render_chain/0/name"), Some(8));
+        assert_eq!(
+            line(&json_lines, "/render_chain/0/inputs/iChannel0"),
+            Some(9)
+        );
+        assert_eq!(line(&json_lines, "/render_chain/1/name"), Some(11));
+        assert_eq!(line(&json_lines, "/final_stage"), Some(13));
+        // Values missing from the document are located at the closest value holding them
+        assert_eq!(line(&json_lines, "/render_chain/1/filter"), Some(11));
+        assert_eq!(line(&json_lines, "/missing"), Some(1));
+    }
+
+    #[test]
+    fn json_escapes_do_not_end_strings() {
+        let json_lines = lines(
+            ConfigFormat::Json,
+            "{\n\"a \\\" }\": \"\\\\\",\n\"path/with~\": {\"b\": 1},\n\"c\": 2\n}",
+        );
+
+        assert_eq!(line(&json_lines, "/a \" }"), Some(2));
+        assert_eq!(line(&json_lines, "/path~1with~0/b"), Some(3));
+        assert_eq!(line(&json_lines, "/c"), Some(4));
+    }
+
+    #[test]
+    fn appended_lists_are_located_at_the_merged_list() {
+        let json_lines = lines(ConfigFormat::Json, "{\n\"render_chain+\": [\n{}\n]\n}");
+
+        assert_eq!(line(&json_lines, "/render_chain/0"), Some(3));
+    }
+
+    #[test]
+    fn ron_values_are_located_by_pointer() {
+        let ron_lines = lines(
+            ConfigFormat::Ron,
+            r##"// A project
+(
+    view: (
+        width: 640,
+        title: "a ) b ( c",
+    ),
+    /* inputs: {} */
+    inputs: {
+        "forest": (
+            type: "Picture",
+            path: r#"res/"forest".jpg"#,
+        ),
+    },
+    render_chain: [
+        (
+            name: "collage",
+            inputs: {
+                "iChannel0": Linear("forest"),
+            },
+            precision: U8,
+        ),
+    ],
+    midi_clock: Some((
+        input: 'x',
+    )),
+)"##,
+        );
+
+        assert_eq!(line(&ron_lines, ""), Some(2));
+        assert_eq!(line(&ron_lines, "/view/width"), Some(4));
+        assert_eq!(line(&ron_lines, "/view/title"), Some(5));
+        assert_eq!(line(&ron_lines, "/inputs"), Some(8));
+        assert_eq!(line(&ron_lines, "/inputs/forest/path"), Some(11));
+        assert_eq!(line(&ron_lines, "/render_chain/0/name"), Some(16));
+        assert_eq!(
+            line(&ron_lines, "/render_chain/0/inputs/iChannel0"),
+            Some(18)
+        );
+        assert_eq!(
+            line(&ron_lines, "/render_chain/0/inputs/iChannel0/0"),
+            Some(18)
+        );
+        assert_eq!(line(&ron_lines, "/render_chain/0/precision"), Some(20));
+        assert_eq!(line(&ron_lines, "/midi_clock/input"), Some(24));
+    }
+
+    #[test]
+    fn toml_values_are_located_by_pointer() {
+        let toml_lines = lines(
+            ConfigFormat::Toml,
+            r#"# A project
+bpm = 120
+description = """
+title = "not a key"
+"""
+
+[view]
+width = 640
+"title.name" = "a = b"
+
+[[render_chain]]
+name = "blur"
+fragment_shader = [
+    { path = "not a key" },
+]
+
+[[render_chain]]
+name = "glow"
+
+[render_chain.inputs]
+iChannel0 = { Linear = "blur" }
+
+[final_stage]
+inputs.iChannel0 = { Linear = "glow" }
+"#,
+        );
+
+        assert_eq!(line(&toml_lines, "/bpm"), Some(2));
+        assert_eq!(line(&toml_lines, "/description"), Some(3));
+        assert_eq!(line(&toml_lines, "/title"), Some(1));
+        assert_eq!(line(&toml_lines, "/view"), Some(7));
+        assert_eq!(line(&toml_lines, "/view/width"), Some(8));
+        assert_eq!(line(&toml_lines, "/view/title.name"), Some(9));
+        assert_eq!(line(&toml_lines, "/render_chain/0/name"), Some(12));
+        assert_eq!(
+            line(&toml_lines, "/render_chain/0/fragment_shader/0"),
+            Some(13)
+        );
+        assert_eq!(line(&toml_lines, "/render_chain/1"), Some(17));
+        assert_eq!(
+            line(&toml_lines, "/render_chain/1/inputs/iChannel0"),
+            Some(21)
+        );
+        assert_eq!(
+            line(&toml_lines, "/final_stage/inputs/iChannel0/Linear"),
+            Some(24)
+        );
+    }
+
+    #[test]
+    fn errors_belong_to_the_deepest_value_before_them() {
+        let json_lines = lines(
+            ConfigFormat::Json,
+            "{\n  \"view\": {\n    \"width\": 640\n  },\n  \"bpm\": 120\n}",
+        );
+
+        assert_eq!(json_lines.pointer_at(3), Some("/view/width"));
+        assert_eq!(json_lines.pointer_at(4), Some("/view/width"));
+        assert_eq!(json_lines.pointer_at(6), Some("/bpm"));
+    }
+}
@@ -0,0 +1,38 @@
+use serde_derive::Serialize;
+
+use wvr_com::data::Message;
+
+use crate::audio::tempo::TempoTrackingUpdate;
+use crate::config::SaveOrder;
+use crate::history::HistoryOrder;
+use crate::preset::PresetOrder;
+use crate::recording::{RecordOrder, RecordingUpdate};
+use crate::sync::TempoOrder;
+use crate::telemetry::{Query, QueryClient};
+
+/// Orders understood by `Wvr`, wrapping the `wvr_com` messages with the ones handled locally
+#[derive(Debug, Clone, Serialize)]
+pub enum Command {
+    Message(Message),
+    Recording(RecordingUpdate),
+    Record(RecordOrder),
+    Tempo(TempoOrder),
+    TempoTracking(TempoTrackingUpdate),
+    /// Asks for part of the live state, answered to the client that asked for it
+    Query {
+        query: Query,
+        client: QueryClient,
+    },
+    Save(SaveOrder),
+    /// Reverts or reapplies the edits made to the project through messages
+    History(HistoryOrder),
+    Preset(PresetOrder),
+    /// Reads the project config file again, applying what changed in it to the running project
+    ReloadConfig,
+}
+
+impl From<Message> for Command {
+    fn from(message: Message) -> Self {
+        Command::Message(message)
+    }
+}
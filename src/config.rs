@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_com::data::{InputUpdate, Message, RenderStageUpdate, SetInfo};
+use wvr_data::config::input::InputConfig;
+use wvr_data::config::project::ProjectConfig;
+use wvr_data::config::rendering::RenderStageConfig;
+use wvr_data::types::Automation;
+
+use crate::audio::tempo::TempoConfig;
+use crate::audio::AudioInputConfig;
+use crate::device::DeviceConfig;
+use crate::format::{self, ConfigFormat};
+use crate::history::HistoryConfig;
+use crate::osc::OscConfig;
+use crate::placeholder::InputFallback;
+use crate::preset::Preset;
+use crate::recording::RecordingConfig;
+use crate::sync::SyncConfig;
+use crate::telemetry::TelemetryConfig;
+use crate::timeline::TimelineConfig;
+
+/// Settings read from the project config file next to the ones known by `ProjectConfig`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtendedConfig {
+    pub recording: RecordingConfig,
+    pub audio_inputs: HashMap<String, AudioInputConfig>,
+    pub tempo: Option<TempoConfig>,
+    pub sync: SyncConfig,
+    pub osc: OscConfig,
+    pub telemetry: TelemetryConfig,
+    pub history: HistoryConfig,
+    pub presets: HashMap<String, Preset>,
+    pub timeline: TimelineConfig,
+    pub input_fallback: InputFallback,
+    pub devices: DeviceConfig,
+}
+
+pub fn apply_stage_update(render_stage_config: &mut RenderStageConfig, update: &RenderStageUpdate) {
+    match update {
+        RenderStageUpdate::Filter(filter_name) => render_stage_config.filter = filter_name.clone(),
+        RenderStageUpdate::FilterModeParams(filter_mode_params) => {
+            render_stage_config.filter_mode_params = filter_mode_params.clone()
+        }
+        RenderStageUpdate::Variable(variable_name, variable_value) => {
+            if let Some((value, _)) = render_stage_config.variables.get_mut(variable_name) {
+                *value = variable_value.clone();
+            } else {
+                render_stage_config.variables.insert(
+                    variable_name.clone(),
+                    (variable_value.clone(), Automation::None),
+                );
+            }
+        }
+        // Offsets are performance adjustments applied on top of the configured values
+        RenderStageUpdate::VariableOffset(_, _) => (),
+        RenderStageUpdate::VariableAutomation(variable_name, variable_automation) => {
+            if let Some((_, automation)) = render_stage_config.variables.get_mut(variable_name) {
+                *automation = variable_automation.clone();
+            }
+        }
+        RenderStageUpdate::Input(input_name, input) => {
+            render_stage_config
+                .inputs
+                .insert(input_name.clone(), input.clone());
+        }
+        RenderStageUpdate::Precision(precision) => {
+            render_stage_config.precision = precision.clone()
+        }
+        RenderStageUpdate::Name(name) => render_stage_config.name = name.clone(),
+    }
+}
+
+pub fn apply_input_update(input_config: &mut InputConfig, input_update: &InputUpdate) {
+    match (input_config, input_update) {
+        (InputConfig::Video { width, .. }, InputUpdate::SetWidth(new_width))
+        | (InputConfig::Picture { width, .. }, InputUpdate::SetWidth(new_width))
+        | (InputConfig::Cam { width, .. }, InputUpdate::SetWidth(new_width)) => {
+            *width = *new_width as _
+        }
+        (InputConfig::Video { height, .. }, InputUpdate::SetHeight(new_height))
+        | (InputConfig::Picture { height, .. }, InputUpdate::SetHeight(new_height))
+        | (InputConfig::Cam { height, .. }, InputUpdate::SetHeight(new_height)) => {
+            *height = *new_height as _
+        }
+        (InputConfig::Video { path, .. }, InputUpdate::SetPath(new_path))
+        | (InputConfig::Picture { path, .. }, InputUpdate::SetPath(new_path))
+        | (InputConfig::Cam { path, .. }, InputUpdate::SetPath(new_path)) => {
+            *path = new_path.clone()
+        }
+        (InputConfig::Video { speed, .. }, InputUpdate::SetSpeed(new_speed)) => {
+            *speed = new_speed.clone()
+        }
+        _ => (),
+    }
+}
+
+/// Mirrors on a project config the changes a message makes to the running project,
+/// so that the live state can be reported or saved
+pub fn apply_message(project_config: &mut ProjectConfig, message: &Message) {
+    match message {
+        Message::Insert((input_name, input_config))
+        | Message::AddInput(input_name, input_config) => {
+            project_config
+                .inputs
+                .insert(input_name.clone(), input_config.clone());
+        }
+        Message::RemoveInput(input_name) => {
+            project_config.inputs.remove(input_name);
+        }
+        Message::RenameInput(old_input_name, new_input_name) => {
+            if let Some(input_config) = project_config.inputs.remove(old_input_name) {
+                project_config
+                    .inputs
+                    .insert(new_input_name.clone(), input_config);
+            }
+        }
+        Message::UpdateInput(input_name, input_update) => {
+            if let Some(input_config) = project_config.inputs.get_mut(input_name) {
+                apply_input_update(input_config, input_update);
+            }
+        }
+        Message::Set(set_info) => match set_info {
+            SetInfo::Bpm(bpm) => project_config.bpm = *bpm as _,
+            SetInfo::Width(width) => project_config.view.width = *width as _,
+            SetInfo::Height(height) => project_config.view.height = *height as _,
+            SetInfo::TargetFps(target_fps) => project_config.view.target_fps = *target_fps as _,
+            SetInfo::DynamicResolution(dynamic_resolution) => {
+                project_config.view.dynamic = *dynamic_resolution
+            }
+            SetInfo::VSync(vsync) => project_config.view.vsync = *vsync,
+            SetInfo::Fullscreen(fullscreen) => project_config.view.fullscreen = *fullscreen,
+            SetInfo::LockedSpeed(locked_speed) => project_config.view.locked_speed = *locked_speed,
+            // Recording is a session action rather than a project setting
+            SetInfo::Screenshot(_) => (),
+        },
+        Message::RemoveRenderStage(render_stage_index) => {
+            if *render_stage_index < project_config.render_chain.len() {
+                project_config.render_chain.remove(*render_stage_index);
+            }
+        }
+        Message::MoveRenderStage(original_index, target_index) => {
+            if *original_index < project_config.render_chain.len() {
+                let render_stage_config = project_config.render_chain.remove(*original_index);
+                let target_index = (*target_index).min(project_config.render_chain.len());
+                project_config
+                    .render_chain
+                    .insert(target_index, render_stage_config);
+            }
+        }
+        Message::AddRenderStage(render_stage_config) => {
+            project_config
+                .render_chain
+                .push(render_stage_config.clone());
+        }
+        Message::UpdateRenderStage(render_stage_index, update) => {
+            if let Some(render_stage_config) =
+                project_config.render_chain.get_mut(*render_stage_index)
+            {
+                apply_stage_update(render_stage_config, update);
+            }
+        }
+        Message::UpdateFinalStage(update) => {
+            apply_stage_update(&mut project_config.final_stage, update);
+        }
+        Message::Start | Message::Pause | Message::Stop => (),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum SaveOrder {
+    /// Overwrites the config file the project was loaded from
+    Save,
+    /// Writes to another file of the project folder, given by its name,
+    /// which later saves then go to
+    SaveAs(PathBuf),
+}
+
+impl SaveOrder {
+    /// Path of the file to write. Save orders can come from the network, so another file
+    /// has to be a plain file name with a config extension, which keeps it in the project folder.
+    pub fn target_path(&self, project_path: &Path, config_path: &Path) -> Result<PathBuf> {
+        let file_name = match self {
+            SaveOrder::Save => return Ok(config_path.to_owned()),
+            SaveOrder::SaveAs(file_name) => file_name,
+        };
+
+        let mut components = file_name.components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => (),
+            _ => bail!(
+                "Cannot save to {:?}, expected the name of a file in the project folder",
+                file_name
+            ),
+        }
+        ConfigFormat::from_path(file_name)?;
+
+        Ok(project_path.join(file_name))
+    }
+}
+
+/// Writes a project config to `target_path`, in the format given by its extension,
+/// backing up the file being replaced
+pub fn save_project_config(
+    target_path: &Path,
+    project_config: &ProjectConfig,
+    extended_config: &ExtendedConfig,
+) -> Result<()> {
+    let config = format::write_project_config(
+        ConfigFormat::from_path(target_path)?,
+        project_config,
+        extended_config,
+    )
+    .context("Failed to serialize the project config")?;
+
+    if target_path.exists() {
+        let mut backup_path = target_path.as_os_str().to_owned();
+        backup_path.push(".bak");
+
+        fs::copy(target_path, &backup_path).context(format!(
+            "Failed to back up the project config to {:?}",
+            backup_path
+        ))?;
+    }
+
+    // Writing next to the target first means a failed save never leaves a truncated config behind
+    let mut temporary_path = target_path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    fs::write(&temporary_path, config).context(format!(
+        "Failed to write the project config to {:?}",
+        temporary_path
+    ))?;
+    fs::rename(&temporary_path, target_path).context(format!(
+        "Failed to write the project config to {:?}",
+        target_path
+    ))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_as_stays_in_the_project_folder() {
+        let project_path = Path::new("/projects/show");
+        let config_path = project_path.join("config.json");
+        let target_path = |file_name: &str| {
+            SaveOrder::SaveAs(PathBuf::from(file_name)).target_path(project_path, &config_path)
+        };
+
+        assert_eq!(
+            target_path("venue.ron").unwrap(),
+            project_path.join("venue.ron")
+        );
+        assert_eq!(
+            SaveOrder::Save
+                .target_path(project_path, &config_path)
+                .unwrap(),
+            config_path
+        );
+
+        for file_name in &[
+            "../other/config.json",
+            "/etc/config.json",
+            "sub/config.json",
+            "..",
+            "config.txt",
+            "",
+        ] {
+            assert!(
+                target_path(file_name).is_err(),
+                "{:?} was accepted",
+                file_name
+            );
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use midir::MidiInput;
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_data::config::input::InputConfig;
+
+use crate::utils::get_path_for_resource;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    /// Reconnects camera and MIDI inputs when their device is plugged back
+    pub watch: bool,
+    /// Time between two scans of the connected devices, in seconds
+    pub scan_interval: f64,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            watch: true,
+            scan_interval: 2.0,
+        }
+    }
+}
+
+/// Change of a device since the previous scan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceChange {
+    Appeared,
+    Disappeared,
+}
+
+/// Whether an input relies on a device that can be plugged and unplugged
+pub fn is_device(input_config: &InputConfig) -> bool {
+    matches!(
+        input_config,
+        InputConfig::Cam { .. } | InputConfig::Midi { .. }
+    )
+}
+
+/// Devices seen during a scan
+pub struct DeviceScan {
+    midi_ports: Vec<String>,
+}
+
+impl DeviceScan {
+    /// Whether the device of an input is connected, cameras being matched by path
+    /// and MIDI controllers by part of their port name.
+    /// Inputs that are not devices give `None`.
+    pub fn is_connected(&self, project_path: &Path, input_config: &InputConfig) -> Option<bool> {
+        match input_config {
+            InputConfig::Cam { path, .. } => {
+                Some(Path::new(&get_path_for_resource(project_path, path)).exists())
+            }
+            InputConfig::Midi { name } => Some(
+                self.midi_ports
+                    .iter()
+                    .any(|port_name| port_name.contains(name.as_str())),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps track of the devices the camera and MIDI inputs rely on, so that they can be
+/// rebuilt when their device is plugged in late or back
+pub struct DeviceWatcher {
+    enabled: bool,
+    scan_interval: Duration,
+    last_scan: Option<Instant>,
+    /// MIDI client listing the ports, created on the first scan needing it
+    midi_input: Option<MidiInput>,
+
+    /// Whether the device of each input was connected at the last scan, by input name
+    connected: HashMap<String, bool>,
+}
+
+impl DeviceWatcher {
+    pub fn new(device_config: &DeviceConfig) -> Self {
+        Self {
+            enabled: device_config.watch,
+            scan_interval: Duration::from_secs_f64(device_config.scan_interval.max(0.1)),
+            last_scan: None,
+            midi_input: None,
+
+            connected: HashMap::new(),
+        }
+    }
+
+    /// Scans the devices when the scan interval has elapsed and some inputs rely on one,
+    /// listing the MIDI ports only when a MIDI input needs them
+    pub fn scan<'a, I: Iterator<Item = &'a InputConfig>>(
+        &mut self,
+        input_configs: I,
+    ) -> Option<DeviceScan> {
+        if !self.enabled {
+            return None;
+        }
+
+        let device_inputs: Vec<&InputConfig> = input_configs
+            .filter(|input_config| is_device(input_config))
+            .collect();
+        if device_inputs.is_empty() {
+            return None;
+        }
+
+        let now = Instant::now();
+        if let Some(last_scan) = self.last_scan {
+            if now - last_scan < self.scan_interval {
+                return None;
+            }
+        }
+        self.last_scan = Some(now);
+
+        let has_midi_inputs = device_inputs
+            .iter()
+            .any(|input_config| matches!(input_config, InputConfig::Midi { .. }));
+        let midi_ports = if has_midi_inputs {
+            self.list_midi_ports()
+        } else {
+            Vec::new()
+        };
+
+        Some(DeviceScan { midi_ports })
+    }
+
+    fn list_midi_ports(&mut self) -> Vec<String> {
+        if self.midi_input.is_none() {
+            match MidiInput::new("wvr device watcher") {
+                Ok(midi_input) => self.midi_input = Some(midi_input),
+                Err(e) => {
+                    eprintln!("Failed to list the MIDI ports: {:?}", e);
+                    return Vec::new();
+                }
+            }
+        }
+
+        match &self.midi_input {
+            Some(midi_input) => midi_input
+                .ports()
+                .iter()
+                .filter_map(|port| midi_input.port_name(port).ok())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Records whether the device of an input is connected, telling how it changed since
+    /// the last scan. A device found on the first scan counts as appeared.
+    pub fn update(&mut self, input_name: &str, is_connected: bool) -> Option<DeviceChange> {
+        let was_connected = self.connected.insert(input_name.to_owned(), is_connected);
+
+        match (was_connected, is_connected) {
+            (Some(true), false) => Some(DeviceChange::Disappeared),
+            (None, true) | (Some(false), true) => Some(DeviceChange::Appeared),
+            _ => None,
+        }
+    }
+
+    /// Forgets the state of an input, so that the next scan finding its device tries it again
+    pub fn forget(&mut self, input_name: &str) {
+        self.connected.remove(input_name);
+    }
+
+    /// Forgets the inputs the project does not have anymore
+    pub fn retain<F: Fn(&str) -> bool>(&mut self, is_kept: F) {
+        self.connected.retain(|input_name, _| is_kept(input_name));
+    }
+}
@@ -0,0 +1,304 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use wvr_data::config::project::ProjectConfig;
+
+use crate::config::ExtendedConfig;
+
+/// Extensions of the config files, in the order they are looked for
+const CONFIG_EXTENSIONS: [&str; 3] = ["json", "ron", "toml"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Ron,
+    Toml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        match extension.as_deref() {
+            Some("json") => Ok(ConfigFormat::Json),
+            Some("ron") => Ok(ConfigFormat::Ron),
+            Some("toml") => Ok(ConfigFormat::Toml),
+            _ => bail!(
+                "Unsupported config format for {:?}, expected a .json, .ron or .toml file",
+                path
+            ),
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, text: &str) -> Result<T> {
+        Ok(match self {
+            ConfigFormat::Json => serde_json::from_str(text)?,
+            ConfigFormat::Ron => ron::from_str(text)?,
+            ConfigFormat::Toml => toml::from_str(text)?,
+        })
+    }
+
+    pub fn write<T: Serialize>(&self, value: &T) -> Result<String> {
+        Ok(match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(value)?,
+            ConfigFormat::Ron => ron::ser::to_string_pretty(value, Default::default())?,
+            // Going through a TOML value puts plain values before tables, as TOML requires
+            ConfigFormat::Toml => toml::to_string_pretty(&toml::Value::try_from(value)?)?,
+        })
+    }
+}
+
+/// Finds the `config` file of a project or filter folder, whatever its format
+pub fn find_config_file(folder_path: &Path) -> Option<PathBuf> {
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|extension| folder_path.join("config").with_extension(extension))
+        .find(|config_path| config_path.exists())
+}
+
+/// Reads a config file in the format given by its extension
+pub fn read_config<T: DeserializeOwned>(config_path: &Path) -> Result<T> {
+    let config_format = ConfigFormat::from_path(config_path)?;
+    let text = fs::read_to_string(config_path)
+        .context(format!("Could not read the config file {:?}", config_path))?;
+
+    config_format
+        .parse(&text)
+        .context(format!("Failed to parse the config file {:?}", config_path))
+}
+
+/// Collects the extended settings differing from their default, serialized with `$serialize`,
+/// so that files only hold the sections their author set
+macro_rules! changed_sections {
+    ($extended_config:expr, $serialize:expr, [$($section:ident),*]) => {{
+        let mut sections = Vec::new();
+        $(
+            if $extended_config.$section != Default::default() {
+                sections.push((stringify!($section), $serialize(&$extended_config.$section)?));
+            }
+        )*
+        sections
+    }};
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<serde_json::Value> {
+    Ok(serde_json::to_value(value)?)
+}
+
+fn to_ron<T: Serialize>(value: &T) -> Result<String> {
+    Ok(ron::ser::to_string_pretty(value, Default::default())?)
+}
+
+/// Drops the null values, which TOML has no way to write
+fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => serde_json::Value::Object(
+            object
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect(),
+        ),
+        serde_json::Value::Array(array) => {
+            serde_json::Value::Array(array.into_iter().map(without_nulls).collect())
+        }
+        value => value,
+    }
+}
+
+/// Builds the JSON document of a project config, along with the extended settings
+/// that differ from their default
+pub fn config_value(
+    project_config: &ProjectConfig,
+    extended_config: &ExtendedConfig,
+) -> Result<serde_json::Value> {
+    let sections: Vec<(&str, serde_json::Value)> = changed_sections!(
+        extended_config,
+        to_json,
+        [
+            recording,
+            audio_inputs,
+            tempo,
+            sync,
+            osc,
+            telemetry,
+            history,
+            presets,
+            timeline,
+            input_fallback,
+            devices
+        ]
+    );
+
+    let mut config = to_json(project_config)?;
+    if let Some(config) = config.as_object_mut() {
+        for (section_name, section) in sections {
+            config.insert(section_name.to_owned(), section);
+        }
+    }
+
+    Ok(config)
+}
+
+/// Writes a project config file, along with the extended settings that differ from their default
+pub fn write_project_config(
+    config_format: ConfigFormat,
+    project_config: &ProjectConfig,
+    extended_config: &ExtendedConfig,
+) -> Result<String> {
+    if config_format == ConfigFormat::Ron {
+        let sections: Vec<(&str, String)> = changed_sections!(
+            extended_config,
+            to_ron,
+            [
+                recording,
+                audio_inputs,
+                tempo,
+                sync,
+                osc,
+                telemetry,
+                history,
+                presets,
+                timeline,
+                input_fallback,
+                devices
+            ]
+        );
+
+        // Both configs are a single RON struct, the extended sections being added as fields
+        // of the project one
+        let project = to_ron(project_config)?;
+        let mut config = project
+            .trim_end()
+            .strip_suffix(')')
+            .context("Unexpected RON project config")?
+            .trim_end()
+            .to_owned();
+        if !config.ends_with(',') && !config.ends_with('(') {
+            config.push(',');
+        }
+        for (section_name, section) in sections {
+            config.push_str(&format!(
+                "\n    {:}: {:},",
+                section_name,
+                section.replace('\n', "\n    ")
+            ));
+        }
+        config.push_str("\n)\n");
+
+        return Ok(config);
+    }
+
+    let config = config_value(project_config, extended_config)?;
+
+    match config_format {
+        ConfigFormat::Toml => config_format.write(&without_nulls(config)),
+        _ => config_format.write(&config),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::device::DeviceConfig;
+    use crate::history::HistoryConfig;
+    use crate::osc::OscConfig;
+    use crate::placeholder::InputFallback;
+    use crate::recording::{Codec, Container, RecordSource};
+
+    /// Extended settings away from their default, so that every section gets written
+    fn extended_config() -> ExtendedConfig {
+        let mut extended_config = ExtendedConfig::default();
+
+        extended_config.recording.path_template = "set_{take}_{source}".to_owned();
+        extended_config.recording.container = Container::Mov;
+        extended_config.recording.codec = Codec::ProRes;
+        extended_config.recording.alpha = true;
+        extended_config.recording.sources = vec![
+            RecordSource::Stage("collage".to_owned()),
+            RecordSource::Stage("glow".to_owned()),
+        ];
+        extended_config.sync.beats_per_bar = 3;
+        extended_config.sync.link = true;
+        extended_config.osc = OscConfig {
+            ip: "0.0.0.0".to_owned(),
+            port: 9001,
+            enable: true,
+        };
+        extended_config.history = HistoryConfig { length: 20 };
+        extended_config.input_fallback = InputFallback::Black;
+        extended_config.devices = DeviceConfig {
+            watch: false,
+            scan_interval: 0.5,
+        };
+
+        extended_config
+    }
+
+    #[test]
+    fn formats_are_found_from_the_extension() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.json")).unwrap(),
+            ConfigFormat::Json
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("show/config.RON")).unwrap(),
+            ConfigFormat::Ron
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.toml")).unwrap(),
+            ConfigFormat::Toml
+        );
+        assert!(ConfigFormat::from_path(Path::new("config.yaml")).is_err());
+        assert!(ConfigFormat::from_path(Path::new("config")).is_err());
+    }
+
+    #[test]
+    fn settings_survive_every_format() {
+        let extended_config = extended_config();
+
+        for config_format in &[ConfigFormat::Json, ConfigFormat::Ron, ConfigFormat::Toml] {
+            let text = config_format.write(&extended_config).unwrap();
+            let read_config: ExtendedConfig = config_format
+                .parse(&text)
+                .unwrap_or_else(|e| panic!("Failed to read back {:?}: {:?}", config_format, e));
+
+            assert_eq!(read_config, extended_config, "{:?} differs", config_format);
+        }
+    }
+
+    #[test]
+    fn settings_survive_a_conversion_through_every_format() {
+        let extended_config = extended_config();
+
+        let json = ConfigFormat::Json.write(&extended_config).unwrap();
+        let from_json: ExtendedConfig = ConfigFormat::Json.parse(&json).unwrap();
+        let ron = ConfigFormat::Ron.write(&from_json).unwrap();
+        let from_ron: ExtendedConfig = ConfigFormat::Ron.parse(&ron).unwrap();
+        let toml = ConfigFormat::Toml.write(&from_ron).unwrap();
+        let from_toml: ExtendedConfig = ConfigFormat::Toml.parse(&toml).unwrap();
+        let json = ConfigFormat::Json.write(&from_toml).unwrap();
+
+        assert_eq!(
+            ConfigFormat::Json.parse::<ExtendedConfig>(&json).unwrap(),
+            extended_config
+        );
+    }
+
+    #[test]
+    fn toml_files_skip_unset_settings() {
+        let text = ConfigFormat::Toml.write(&extended_config()).unwrap();
+
+        assert!(!text.contains("backpressure"));
+        assert!(!text.contains("midi_clock"));
+    }
+}
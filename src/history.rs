@@ -0,0 +1,470 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem::discriminant;
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_com::data::{InputUpdate, Message, RenderStageUpdate, SetInfo};
+use wvr_data::config::input::InputConfig;
+use wvr_data::config::project::ProjectConfig;
+use wvr_data::config::rendering::RenderStageConfig;
+
+/// Time under which successive changes of the same control make a single edit,
+/// so that moving a fader does not fill the history
+const MERGE_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Number of edits that can be undone, the oldest ones being forgotten first
+    pub length: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { length: 100 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum HistoryOrder {
+    Undo,
+    Redo,
+}
+
+/// A message applied to the project, along with the messages that revert it
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub message: Message,
+    pub inverse: Vec<Message>,
+    time: Instant,
+}
+
+/// Journal of the edits made to the running project
+#[derive(Debug)]
+pub struct History {
+    undo_stack: VecDeque<Edit>,
+    redo_stack: Vec<Edit>,
+    length: usize,
+}
+
+impl History {
+    pub fn new(history_config: &HistoryConfig) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            length: history_config.length,
+        }
+    }
+
+    /// Records an applied edit, `inverse` being built by `inverse_message` before applying it
+    pub fn record(&mut self, message: &Message, inverse: Vec<Message>) {
+        self.redo_stack.clear();
+        self.push(message.clone(), inverse);
+    }
+
+    /// Last edit, which an undo reverts by applying its inverse
+    pub fn last_edit(&self) -> Option<&Edit> {
+        self.undo_stack.back()
+    }
+
+    /// Moves the last edit to the redo stack, once its inverse was applied
+    pub fn undone(&mut self) {
+        if let Some(edit) = self.undo_stack.pop_back() {
+            self.redo_stack.push(edit);
+        }
+    }
+
+    /// Last undone edit, which a redo applies again
+    pub fn last_undone_edit(&self) -> Option<&Edit> {
+        self.redo_stack.last()
+    }
+
+    /// Moves the last undone edit back into the history, once its message was applied again.
+    /// The project may have changed in ways the history does not know about since the undo,
+    /// so `inverse` is computed again from the project as it was before the redo.
+    pub fn redone(&mut self, inverse: Vec<Message>) {
+        if let Some(edit) = self.redo_stack.pop() {
+            self.undo_stack.push_back(Edit {
+                message: edit.message,
+                inverse,
+                time: Instant::now(),
+            });
+            self.truncate();
+        }
+    }
+
+    fn push(&mut self, message: Message, inverse: Vec<Message>) {
+        let now = Instant::now();
+
+        if let Some(last_edit) = self.undo_stack.back_mut() {
+            if now - last_edit.time < MERGE_INTERVAL
+                && is_same_control(&last_edit.message, &message)
+            {
+                // The inverse of the first change already restores the state before the gesture
+                last_edit.message = message;
+                last_edit.time = now;
+                return;
+            }
+        }
+
+        self.undo_stack.push_back(Edit {
+            message,
+            inverse,
+            time: now,
+        });
+        self.truncate();
+    }
+
+    fn truncate(&mut self) {
+        while self.undo_stack.len() > self.length {
+            self.undo_stack.pop_front();
+        }
+    }
+}
+
+/// Whether two messages set the same value, the later one overriding the earlier one
+fn is_same_control(first_message: &Message, second_message: &Message) -> bool {
+    match (first_message, second_message) {
+        (Message::Set(first_info), Message::Set(second_info)) => {
+            discriminant(first_info) == discriminant(second_info)
+        }
+        (
+            Message::UpdateRenderStage(
+                first_index,
+                RenderStageUpdate::Variable(first_variable_name, _),
+            ),
+            Message::UpdateRenderStage(
+                second_index,
+                RenderStageUpdate::Variable(second_variable_name, _),
+            ),
+        ) => first_index == second_index && first_variable_name == second_variable_name,
+        (
+            Message::UpdateFinalStage(RenderStageUpdate::Variable(first_variable_name, _)),
+            Message::UpdateFinalStage(RenderStageUpdate::Variable(second_variable_name, _)),
+        ) => first_variable_name == second_variable_name,
+        _ => false,
+    }
+}
+
+fn inverse_stage_update(
+    render_stage_config: &RenderStageConfig,
+    update: &RenderStageUpdate,
+) -> Option<RenderStageUpdate> {
+    match update {
+        RenderStageUpdate::Filter(_) => Some(RenderStageUpdate::Filter(
+            render_stage_config.filter.clone(),
+        )),
+        RenderStageUpdate::FilterModeParams(_) => Some(RenderStageUpdate::FilterModeParams(
+            render_stage_config.filter_mode_params.clone(),
+        )),
+        RenderStageUpdate::Variable(variable_name, _) => render_stage_config
+            .variables
+            .get(variable_name)
+            .map(|(value, _)| RenderStageUpdate::Variable(variable_name.clone(), value.clone())),
+        // Offsets are performance adjustments, which are not part of the project
+        RenderStageUpdate::VariableOffset(_, _) => None,
+        RenderStageUpdate::VariableAutomation(variable_name, _) => render_stage_config
+            .variables
+            .get(variable_name)
+            .map(|(_, automation)| {
+                RenderStageUpdate::VariableAutomation(variable_name.clone(), automation.clone())
+            }),
+        RenderStageUpdate::Input(input_name, _) => render_stage_config
+            .inputs
+            .get(input_name)
+            .map(|input| RenderStageUpdate::Input(input_name.clone(), input.clone())),
+        RenderStageUpdate::Precision(_) => Some(RenderStageUpdate::Precision(
+            render_stage_config.precision.clone(),
+        )),
+        RenderStageUpdate::Name(_) => {
+            Some(RenderStageUpdate::Name(render_stage_config.name.clone()))
+        }
+    }
+}
+
+fn inverse_input_update(input_config: &InputConfig, update: &InputUpdate) -> Option<InputUpdate> {
+    match (input_config, update) {
+        (InputConfig::Video { width, .. }, InputUpdate::SetWidth(_))
+        | (InputConfig::Picture { width, .. }, InputUpdate::SetWidth(_))
+        | (InputConfig::Cam { width, .. }, InputUpdate::SetWidth(_)) => {
+            Some(InputUpdate::SetWidth(*width as _))
+        }
+        (InputConfig::Video { height, .. }, InputUpdate::SetHeight(_))
+        | (InputConfig::Picture { height, .. }, InputUpdate::SetHeight(_))
+        | (InputConfig::Cam { height, .. }, InputUpdate::SetHeight(_)) => {
+            Some(InputUpdate::SetHeight(*height as _))
+        }
+        (InputConfig::Video { path, .. }, InputUpdate::SetPath(_))
+        | (InputConfig::Picture { path, .. }, InputUpdate::SetPath(_))
+        | (InputConfig::Cam { path, .. }, InputUpdate::SetPath(_)) => {
+            Some(InputUpdate::SetPath(path.clone()))
+        }
+        (InputConfig::Video { speed, .. }, InputUpdate::SetSpeed(_)) => {
+            Some(InputUpdate::SetSpeed(speed.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// Builds the messages reverting an input message, given the project inputs before it gets applied
+fn inverse_input_message(
+    inputs: &HashMap<String, InputConfig>,
+    message: &Message,
+) -> Option<Vec<Message>> {
+    let inverse = match message {
+        Message::Insert((input_name, _)) | Message::AddInput(input_name, _) => {
+            match inputs.get(input_name) {
+                Some(input_config) => {
+                    vec![Message::AddInput(input_name.clone(), input_config.clone())]
+                }
+                None => vec![Message::RemoveInput(input_name.clone())],
+            }
+        }
+        Message::RemoveInput(input_name) => vec![Message::AddInput(
+            input_name.clone(),
+            inputs.get(input_name)?.clone(),
+        )],
+        Message::RenameInput(old_input_name, new_input_name) => {
+            inputs.get(old_input_name)?;
+
+            let mut inverse = vec![Message::RenameInput(
+                new_input_name.clone(),
+                old_input_name.clone(),
+            )];
+            // Renaming over an existing input replaces it
+            if let Some(replaced_input_config) = inputs.get(new_input_name) {
+                inverse.push(Message::AddInput(
+                    new_input_name.clone(),
+                    replaced_input_config.clone(),
+                ));
+            }
+
+            inverse
+        }
+        Message::UpdateInput(input_name, update) => vec![Message::UpdateInput(
+            input_name.clone(),
+            inverse_input_update(inputs.get(input_name)?, update)?,
+        )],
+        _ => return None,
+    };
+
+    Some(inverse)
+}
+
+/// Builds the messages reverting `message`, given the project config before it gets applied.
+/// Returns `None` when the message does not edit the project or its effect cannot be reverted.
+pub fn inverse_message(project_config: &ProjectConfig, message: &Message) -> Option<Vec<Message>> {
+    let inverse = match message {
+        Message::Start | Message::Pause | Message::Stop => return None,
+        Message::Insert(_)
+        | Message::AddInput(_, _)
+        | Message::RemoveInput(_)
+        | Message::RenameInput(_, _)
+        | Message::UpdateInput(_, _) => {
+            return inverse_input_message(&project_config.inputs, message)
+        }
+        Message::Set(set_info) => {
+            let view = &project_config.view;
+            let inverse_info = match set_info {
+                SetInfo::Bpm(_) => SetInfo::Bpm(project_config.bpm as _),
+                SetInfo::Width(_) => SetInfo::Width(view.width as _),
+                SetInfo::Height(_) => SetInfo::Height(view.height as _),
+                SetInfo::TargetFps(_) => SetInfo::TargetFps(view.target_fps as _),
+                SetInfo::DynamicResolution(_) => SetInfo::DynamicResolution(view.dynamic),
+                SetInfo::VSync(_) => SetInfo::VSync(view.vsync),
+                SetInfo::Fullscreen(_) => SetInfo::Fullscreen(view.fullscreen),
+                SetInfo::LockedSpeed(_) => SetInfo::LockedSpeed(view.locked_speed),
+                SetInfo::Screenshot(_) => return None,
+            };
+
+            vec![Message::Set(inverse_info)]
+        }
+        Message::RemoveRenderStage(render_stage_index) => {
+            let render_stage_config = project_config.render_chain.get(*render_stage_index)?;
+
+            // Stages are always added at the end of the chain, from where they get moved back
+            vec![
+                Message::AddRenderStage(render_stage_config.clone()),
+                Message::MoveRenderStage(
+                    project_config.render_chain.len() - 1,
+                    *render_stage_index,
+                ),
+            ]
+        }
+        Message::MoveRenderStage(original_index, target_index) => {
+            let render_chain_length = project_config.render_chain.len();
+            if *original_index >= render_chain_length {
+                return None;
+            }
+
+            vec![Message::MoveRenderStage(
+                (*target_index).min(render_chain_length - 1),
+                *original_index,
+            )]
+        }
+        Message::AddRenderStage(_) => vec![Message::RemoveRenderStage(
+            project_config.render_chain.len(),
+        )],
+        Message::UpdateRenderStage(render_stage_index, update) => {
+            vec![Message::UpdateRenderStage(
+                *render_stage_index,
+                inverse_stage_update(
+                    project_config.render_chain.get(*render_stage_index)?,
+                    update,
+                )?,
+            )]
+        }
+        Message::UpdateFinalStage(update) => vec![Message::UpdateFinalStage(inverse_stage_update(
+            &project_config.final_stage,
+            update,
+        )?)],
+    };
+
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midi_input(name: &str) -> InputConfig {
+        InputConfig::Midi {
+            name: name.to_owned(),
+        }
+    }
+
+    fn inputs() -> HashMap<String, InputConfig> {
+        let mut inputs = HashMap::new();
+        inputs.insert("controller".to_owned(), midi_input("nanoKONTROL"));
+        inputs.insert("pads".to_owned(), midi_input("MPD218"));
+        inputs
+    }
+
+    fn bpm(value: f64) -> Message {
+        Message::Set(SetInfo::Bpm(value))
+    }
+
+    #[test]
+    fn adding_an_input_is_reverted_by_removing_it() {
+        let message = Message::AddInput("keys".to_owned(), midi_input("Keystation"));
+
+        assert_eq!(
+            inverse_input_message(&inputs(), &message),
+            Some(vec![Message::RemoveInput("keys".to_owned())])
+        );
+    }
+
+    #[test]
+    fn replacing_an_input_is_reverted_by_adding_it_back() {
+        let message = Message::AddInput("pads".to_owned(), midi_input("Launchpad"));
+
+        assert_eq!(
+            inverse_input_message(&inputs(), &message),
+            Some(vec![Message::AddInput(
+                "pads".to_owned(),
+                midi_input("MPD218")
+            )])
+        );
+    }
+
+    #[test]
+    fn removing_an_input_is_reverted_by_adding_it_back() {
+        let message = Message::RemoveInput("controller".to_owned());
+
+        assert_eq!(
+            inverse_input_message(&inputs(), &message),
+            Some(vec![Message::AddInput(
+                "controller".to_owned(),
+                midi_input("nanoKONTROL")
+            )])
+        );
+        assert_eq!(
+            inverse_input_message(&inputs(), &Message::RemoveInput("missing".to_owned())),
+            None
+        );
+    }
+
+    #[test]
+    fn renaming_over_an_input_restores_the_replaced_one() {
+        let message = Message::RenameInput("controller".to_owned(), "keys".to_owned());
+        assert_eq!(
+            inverse_input_message(&inputs(), &message),
+            Some(vec![Message::RenameInput(
+                "keys".to_owned(),
+                "controller".to_owned()
+            )])
+        );
+
+        let message = Message::RenameInput("controller".to_owned(), "pads".to_owned());
+        assert_eq!(
+            inverse_input_message(&inputs(), &message),
+            Some(vec![
+                Message::RenameInput("pads".to_owned(), "controller".to_owned()),
+                Message::AddInput("pads".to_owned(), midi_input("MPD218")),
+            ])
+        );
+    }
+
+    #[test]
+    fn undone_edits_can_be_redone_until_a_new_edit() {
+        let mut history = History::new(&HistoryConfig::default());
+        history.record(&Message::RemoveInput("pads".to_owned()), Vec::new());
+        history.record(&bpm(128.0), vec![bpm(120.0)]);
+
+        assert_eq!(
+            history.last_edit().map(|edit| &edit.message),
+            Some(&bpm(128.0))
+        );
+        history.undone();
+        assert_eq!(
+            history.last_edit().map(|edit| &edit.message),
+            Some(&Message::RemoveInput("pads".to_owned()))
+        );
+        assert_eq!(
+            history.last_undone_edit().map(|edit| &edit.message),
+            Some(&bpm(128.0))
+        );
+
+        history.redone(vec![bpm(120.0)]);
+        assert_eq!(
+            history.last_edit().map(|edit| &edit.message),
+            Some(&bpm(128.0))
+        );
+        assert!(history.last_undone_edit().is_none());
+
+        history.undone();
+        history.record(&Message::RemoveInput("controller".to_owned()), Vec::new());
+        assert!(history.last_undone_edit().is_none());
+    }
+
+    #[test]
+    fn quick_changes_of_the_same_control_make_a_single_edit() {
+        let mut history = History::new(&HistoryConfig::default());
+        history.record(&bpm(121.0), vec![bpm(120.0)]);
+        history.record(&bpm(122.0), vec![bpm(121.0)]);
+
+        let last_edit = history.last_edit().unwrap();
+        assert_eq!(last_edit.message, bpm(122.0));
+        assert_eq!(last_edit.inverse, vec![bpm(120.0)]);
+
+        history.undone();
+        assert!(history.last_edit().is_none());
+    }
+
+    #[test]
+    fn the_oldest_edits_are_forgotten() {
+        let mut history = History::new(&HistoryConfig { length: 2 });
+        for input_name in &["a", "b", "c"] {
+            history.record(&Message::RemoveInput((*input_name).to_owned()), Vec::new());
+        }
+
+        history.undone();
+        history.undone();
+        history.undone();
+        assert_eq!(
+            history.last_undone_edit().map(|edit| &edit.message),
+            Some(&Message::RemoveInput("b".to_owned()))
+        );
+    }
+}
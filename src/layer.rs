@@ -0,0 +1,324 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+
+use wvr_data::config::project::ProjectConfig;
+
+use crate::config::ExtendedConfig;
+use crate::format::{self, ConfigFormat};
+
+/// Key listing the fragments a config is built upon
+pub const INCLUDE_KEY: &str = "include";
+/// Suffix of the keys whose list is appended to the one of the earlier layers instead of replacing it
+const APPEND_SUFFIX: char = '+';
+
+/// Fragments included by a config, which can be a single path or a list of them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Includes {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for Includes {
+    fn default() -> Self {
+        Includes::Many(Vec::new())
+    }
+}
+
+impl Includes {
+    fn into_paths(self) -> Vec<String> {
+        match self {
+            Includes::One(include) => vec![include],
+            Includes::Many(includes) => includes,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct IncludeList {
+    include: Includes,
+}
+
+/// Whether a config file includes fragments, in which case it can only be read as a whole
+pub fn has_includes(config_path: &Path) -> Result<bool> {
+    let config_format = ConfigFormat::from_path(config_path)?;
+    let text = fs::read_to_string(config_path)
+        .context(format!("Could not find config file {:?}", config_path))?;
+    let include_list: IncludeList = config_format
+        .parse(&text)
+        .context(format!("Failed to read the includes of {:?}", config_path))?;
+
+    Ok(!include_list.include.into_paths().is_empty())
+}
+
+/// Reads a single config file as a JSON document, its includes left unresolved
+fn read_document(config_path: &Path, is_root: bool) -> Result<Value> {
+    let config_format = ConfigFormat::from_path(config_path)?;
+    let text = fs::read_to_string(config_path)
+        .context(format!("Could not find config file {:?}", config_path))?;
+
+    let document = match config_format {
+        // RON enums lose their variant name when read without their type, so RON files
+        // can only be read as complete project configs, which cannot take part in includes
+        ConfigFormat::Ron => {
+            let include_list: IncludeList = config_format.parse(&text)?;
+            if !is_root || !include_list.include.into_paths().is_empty() {
+                bail!(
+                    "{:?} is a RON file, which cannot include or be included, \
+                     as RON cannot be read partially. Convert it with `wvr convert` first",
+                    config_path
+                );
+            }
+
+            let project_config: ProjectConfig = config_format.parse(&text)?;
+            let extended_config: ExtendedConfig = config_format.parse(&text)?;
+            format::config_value(&project_config, &extended_config)?
+        }
+        _ => config_format.parse(&text)?,
+    };
+
+    Ok(document)
+}
+
+/// Merges a layer on top of the earlier ones: objects merge key by key, keys ending with `+`
+/// append their list to the earlier one, and any other value replaces the earlier one
+pub fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                if let Some(appended_key) = key.strip_suffix(APPEND_SUFFIX) {
+                    match (base.get_mut(appended_key), value) {
+                        (Some(Value::Array(base_list)), Value::Array(list)) => {
+                            base_list.extend(list)
+                        }
+                        (_, value) => {
+                            base.insert(appended_key.to_owned(), value);
+                        }
+                    }
+                } else if let Some(base_value) = base.get_mut(&key) {
+                    merge(base_value, value);
+                } else {
+                    base.insert(key, value);
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+fn read_layers(
+    config_path: &Path,
+    include_chain: &mut Vec<PathBuf>,
+    layer_files: &mut Vec<PathBuf>,
+) -> Result<Value> {
+    let canonical_path = fs::canonicalize(config_path)
+        .context(format!("Could not find config file {:?}", config_path))?;
+    if include_chain.contains(&canonical_path) {
+        bail!("{:?} ends up including itself", config_path);
+    }
+    layer_files.push(canonical_path.clone());
+    let is_root = include_chain.is_empty();
+    include_chain.push(canonical_path);
+
+    let mut document = read_document(config_path, is_root)?;
+    let includes = match document
+        .as_object_mut()
+        .and_then(|document| document.remove(INCLUDE_KEY))
+    {
+        Some(includes) => serde_json::from_value::<Includes>(includes)
+            .context(format!("Invalid includes in {:?}", config_path))?
+            .into_paths(),
+        None => Vec::new(),
+    };
+
+    // Included paths are relative to the including file
+    let config_folder = config_path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut config = Value::Object(Map::new());
+    for include in includes {
+        let fragment = read_layers(&config_folder.join(&include), include_chain, layer_files)
+            .context(format!(
+                "Failed to read {:?}, included by {:?}",
+                include, config_path
+            ))?;
+        merge(&mut config, fragment);
+    }
+    merge(&mut config, document);
+
+    include_chain.pop();
+
+    Ok(config)
+}
+
+/// Reads a config file with its includes resolved, as a JSON document.
+/// Fragments are applied in the order they are listed, and the including file last.
+pub fn read_layered_config(config_path: &Path) -> Result<Value> {
+    read_layers(config_path, &mut Vec::new(), &mut Vec::new())
+}
+
+/// Canonical paths of a config file and of every fragment it includes, directly or not
+pub fn layer_files(config_path: &Path) -> Result<Vec<PathBuf>> {
+    let mut layer_files = Vec::new();
+    read_layers(config_path, &mut Vec::new(), &mut layer_files)?;
+
+    Ok(layer_files)
+}
+
+/// Applies an override such as `view.width=1920`, the value being read as JSON when it can be,
+/// and as a string otherwise
+pub fn apply_override(config: &mut Value, assignment: &str) -> Result<()> {
+    let mut assignment_parts = assignment.splitn(2, '=');
+    let path = assignment_parts.next().unwrap_or_default().trim();
+    let value = match assignment_parts.next() {
+        Some(value) => value.trim(),
+        None => bail!("Invalid override {:?}, expected KEY=VALUE", assignment),
+    };
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()));
+
+    let mut target = config;
+    for key in path.split('.') {
+        target = match target {
+            Value::Object(object) => object
+                .entry(key.to_owned())
+                .or_insert_with(|| Value::Object(Map::new())),
+            Value::Array(list) => {
+                let index = key
+                    .parse::<usize>()
+                    .context(format!("{:?} is not a list index in {:?}", key, path))?;
+                let list_length = list.len();
+                list.get_mut(index).context(format!(
+                    "Index {:} is out of the {:} items of the list in {:?}",
+                    index, list_length, path
+                ))?
+            }
+            _ => bail!("{:?} does not lead to a setting", path),
+        };
+    }
+
+    *target = value;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    /// Writes config files in a folder of their own, removed by the caller
+    fn write_configs(folder_name: &str, configs: &[(&str, Value)]) -> PathBuf {
+        let folder_path =
+            std::env::temp_dir().join(format!("wvr-{:}-{:}", folder_name, std::process::id()));
+        fs::create_dir_all(&folder_path).unwrap();
+        for (file_name, config) in configs {
+            fs::write(folder_path.join(file_name), config.to_string()).unwrap();
+        }
+
+        folder_path
+    }
+
+    #[test]
+    fn objects_are_merged_key_by_key() {
+        let mut config = json!({"view": {"width": 640, "height": 480}, "bpm": 89});
+        merge(
+            &mut config,
+            json!({"view": {"width": 1920, "fullscreen": true}, "bpm": 120}),
+        );
+
+        assert_eq!(
+            config,
+            json!({"view": {"width": 1920, "height": 480, "fullscreen": true}, "bpm": 120})
+        );
+    }
+
+    #[test]
+    fn lists_are_replaced_unless_appended() {
+        let mut config = json!({"render_chain": ["blur"], "tags": ["live"]});
+        merge(
+            &mut config,
+            json!({"render_chain+": ["glow"], "tags": ["studio"], "new+": [1]}),
+        );
+
+        assert_eq!(
+            config,
+            json!({"render_chain": ["blur", "glow"], "tags": ["studio"], "new": [1]})
+        );
+    }
+
+    #[test]
+    fn overrides_are_read_as_json_or_as_strings() {
+        let mut config = json!({"view": {"width": 640}, "inputs": {}});
+
+        apply_override(&mut config, "view.width=1920").unwrap();
+        apply_override(&mut config, "view.title = Live set").unwrap();
+        apply_override(&mut config, "inputs.cam.path=\"/dev/video0\"").unwrap();
+
+        assert_eq!(
+            config,
+            json!({
+                "view": {"width": 1920, "title": "Live set"},
+                "inputs": {"cam": {"path": "/dev/video0"}},
+            })
+        );
+    }
+
+    #[test]
+    fn overrides_reach_into_lists() {
+        let mut config = json!({"render_chain": [{"name": "blur"}, {"name": "glow"}]});
+
+        apply_override(&mut config, "render_chain.1.name=bloom").unwrap();
+        assert_eq!(config["render_chain"][1]["name"], json!("bloom"));
+
+        assert!(apply_override(&mut config, "render_chain.2.name=edge").is_err());
+        assert!(apply_override(&mut config, "render_chain.first.name=edge").is_err());
+        assert!(apply_override(&mut config, "render_chain.0.name.length=1").is_err());
+    }
+
+    #[test]
+    fn overrides_need_a_value() {
+        let mut config = json!({});
+
+        assert!(apply_override(&mut config, "view.width").is_err());
+    }
+
+    #[test]
+    fn fragments_are_applied_before_the_including_file() {
+        let folder_path = write_configs(
+            "layer-includes",
+            &[
+                ("base.json", json!({"bpm": 89, "view": {"width": 640}})),
+                ("show.json", json!({"include": "base.json", "bpm": 120})),
+            ],
+        );
+
+        let config = read_layered_config(&folder_path.join("show.json"));
+        let layer_files = layer_files(&folder_path.join("show.json"));
+        fs::remove_dir_all(&folder_path).unwrap();
+
+        assert_eq!(config.unwrap(), json!({"bpm": 120, "view": {"width": 640}}));
+        assert_eq!(layer_files.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn include_cycles_are_refused() {
+        let folder_path = write_configs(
+            "layer-cycle",
+            &[
+                ("first.json", json!({"include": ["second.json"]})),
+                ("second.json", json!({"include": ["first.json"]})),
+            ],
+        );
+
+        let config = read_layered_config(&folder_path.join("first.json"));
+        fs::remove_dir_all(&folder_path).unwrap();
+
+        assert!(config.is_err());
+    }
+}
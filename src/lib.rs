@@ -1,47 +1,89 @@
+use std::fs;
 use std::rc::Rc;
-use std::sync::Mutex;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::{collections::HashMap, time::Instant};
-use std::{fs, str::FromStr};
 use std::{
     path::{Path, PathBuf},
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-};
-use std::{
-    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    sync::mpsc::{Receiver, Sender},
     time::Duration,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 use glium::backend::Facade;
 use glium::glutin;
 use glium::glutin::event::Event;
 use glium::glutin::event_loop::ControlFlow;
 use glium::glutin::event_loop::EventLoop;
+use glium::texture::Texture2d;
+use glium::uniforms::MagnifySamplerFilter;
 use glium::Display;
-use glium::Frame;
-use glutin::event::WindowEvent;
+use glium::HeadlessRenderer;
+use glium::Surface;
+use glutin::event::{ElementState, ModifiersState, VirtualKeyCode, WindowEvent};
 
 use wvr_com::data::{InputUpdate, Message, RenderStageUpdate, SetInfo};
+use wvr_data::config::input::InputConfig;
 use wvr_data::config::project::ProjectConfig;
-use wvr_data::types::{Automation, DataHolder, InputProvider, InputSampler, Speed};
+use wvr_data::types::{Automation, DataHolder, InputProvider, Speed};
 use wvr_rendering::stage::Stage;
-use wvr_rendering::RGBAImageData;
 use wvr_rendering::ShaderView;
 use wvr_script::Script;
 
+use audio::tempo::{TempoConfig, TempoTracker};
+use audio::AudioInputConfig;
+use command::Command;
+use config::{ExtendedConfig, SaveOrder};
+use device::{DeviceChange, DeviceWatcher};
+use history::{History, HistoryOrder};
+use offline::OfflineRenderConfig;
+use osc::OscReplier;
+use placeholder::{InputFallback, PlaceholderProvider};
+use preset::{Crossfade, Preset, PresetOrder};
+use recording::capture::{AsyncReadback, BackpressurePolicy};
+use recording::recorder::Recorder;
+use recording::{RecordOrder, RecordSource, RecordingConfig};
+use sync::link::Link;
+use sync::midi_clock::{MidiClockInput, MidiClockMode, MidiClockOutput};
+use sync::{TapTempo, TempoOrder, NUDGE_STEP};
+use telemetry::{EngineState, Query, QueryClient, StageState, TelemetryEvent, TelemetryServer};
+use timeline::{CueAction, CuePosition, Timeline, MAX_JUMPS_PER_UPDATE};
+
+pub mod audio;
+pub mod check;
+pub mod command;
+pub mod config;
+pub mod device;
+pub mod format;
+pub mod history;
+pub mod layer;
+pub mod offline;
+pub mod osc;
+pub mod placeholder;
+pub mod preset;
+pub mod recording;
+pub mod reload;
+pub mod sync;
+pub mod telemetry;
+pub mod timeline;
 pub mod utils;
 
 pub struct Wvr {
     pub project_path: PathBuf,
+    config_path: PathBuf,
+    config_overrides: Vec<String>,
+    live_config: ProjectConfig,
 
     variables: HashMap<String, (DataHolder, Automation)>,
     env_variable_list: HashMap<String, DataHolder>,
     pub uniform_sources: Rc<Mutex<HashMap<String, Box<dyn InputProvider>>>>,
+    input_fallback: InputFallback,
+    /// Configs of the inputs that failed to load and are shown as placeholders
+    placeholder_inputs: HashMap<String, InputConfig>,
+    /// Configs of the audio inputs, which live outside of the project config
+    audio_inputs: HashMap<String, AudioInputConfig>,
 
     pub shader_view: ShaderView,
 
@@ -54,8 +96,11 @@ pub struct Wvr {
     bpm: f64,
     target_fps: f64,
     locked_speed: bool,
+    /// Whether the tempo and position follow Link, the MIDI clock and the tempo detected from audio
+    follow_external_clocks: bool,
 
     last_update_time: Instant,
+    measured_fps: f64,
 
     frame_count: usize,
     pub time: f64,
@@ -69,15 +114,43 @@ pub struct Wvr {
 
     main_script: Option<Script>,
 
-    screenshot: bool,
+    tempo_config: Option<TempoConfig>,
+    tempo_tracker: Option<Arc<Mutex<TempoTracker>>>,
+    tap_tempo: TapTempo,
+    beats_per_bar: usize,
+    link: Option<Link>,
+    midi_clock_input: Option<MidiClockInput>,
+    midi_clock_output: Option<MidiClockOutput>,
+
+    telemetry: Option<TelemetryServer>,
+    osc_replier: Option<OscReplier>,
+
+    history: History,
+
+    presets: HashMap<String, Preset>,
+    crossfade: Option<Crossfade>,
+
+    timeline: Timeline,
+
+    device_watcher: DeviceWatcher,
+
+    recording_config: RecordingConfig,
+    recording_path: PathBuf,
+    recorders: Vec<Recorder>,
+    capture_readback: AsyncReadback,
     screenshot_frame_count: i64,
-    screenshot_sender: SyncSender<(RGBAImageData, usize)>,
-    _screenshot_thread: Option<thread::JoinHandle<()>>,
-    screenshot_stop: Arc<AtomicBool>,
+    /// Frames captured since the current take started
+    take_frame_count: usize,
 }
 
 impl Wvr {
-    pub fn new(project_path: &Path, config: ProjectConfig, display: &dyn Facade) -> Result<Self> {
+    pub fn new(
+        project_path: &Path,
+        config: ProjectConfig,
+        extended_config: ExtendedConfig,
+        order_sender: Sender<Command>,
+        display: &dyn Facade,
+    ) -> Result<Self> {
         let mut available_filter_list =
             utils::load_available_filter_list(&wvr_data::get_filters_path(), true)?;
         available_filter_list.extend(utils::load_available_filter_list(
@@ -99,80 +172,76 @@ impl Wvr {
             display,
         )?;
 
-        let (screenshot_sender, screenshot_receiver): (
-            SyncSender<(RGBAImageData, usize)>,
-            Receiver<(RGBAImageData, usize)>,
-        ) = sync_channel(1);
+        let recording_config = extended_config.recording;
+
+        let screenshot_path = PathBuf::from_str(&utils::get_path_for_resource(
+            project_path,
+            config.view.screenshot_path.to_str().unwrap(),
+        ))
+        .unwrap();
+
+        let tempo_config = extended_config.tempo;
+        let tempo_tracker = match &tempo_config {
+            Some(tempo_config) => Some(Arc::new(Mutex::new(
+                TempoTracker::new(tempo_config.min_bpm, tempo_config.max_bpm)
+                    .context("Invalid tempo detection settings")?,
+            ))),
+            None => None,
+        };
 
-        let screenshot_stop = Arc::new(AtomicBool::new(false));
-        let screenshot_thread = if config.view.screenshot {
-            let target_duration = if config.view.screenshot_frame_count > 0 {
-                Some(config.view.screenshot_frame_count as f64 / config.view.target_fps as f64)
-            } else {
-                None
+        let link = if extended_config.sync.link {
+            match Link::start(config.bpm as f64) {
+                Ok(link) => Some(link),
+                Err(e) => {
+                    eprintln!("Failed to join the Link session: {:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let (mut midi_clock_input, mut midi_clock_output) = (None, None);
+        if let Some(midi_clock_config) = &extended_config.sync.midi_clock {
+            let result = match midi_clock_config.mode {
+                MidiClockMode::Input => {
+                    MidiClockInput::connect(&midi_clock_config.port, order_sender.clone())
+                        .map(|input| midi_clock_input = Some(input))
+                }
+                MidiClockMode::Output => {
+                    MidiClockOutput::connect(&midi_clock_config.port, midi_clock_config.mtc)
+                        .map(|output| midi_clock_output = Some(output))
+                }
             };
-            let screenshot_stop = screenshot_stop.clone();
-            let screenshot_path = config.view.screenshot_path.clone();
-            let screenshot_path = PathBuf::from_str(&utils::get_path_for_resource(
-                project_path,
-                screenshot_path.to_str().unwrap(),
-            ))
-            .unwrap();
-
-            if config.view.screenshot && !screenshot_path.exists() {
-                fs::create_dir_all(&screenshot_path).context(format!(
-                    "Could not create screenshot output folder {:?}",
-                    screenshot_path
-                ))?;
-            }
-
-            let output_path = screenshot_path
-                .join("output.mkv")
-                .to_str()
-                .unwrap()
-                .to_owned();
-
-            let view_config = config.view.clone();
-            Some(thread::spawn(move || {
-                let mut encoder = wvr_video::encoder::VideoEncoder::new(
-                    &output_path,
-                    view_config.width as usize,
-                    view_config.height as usize,
-                    view_config.target_fps as f64,
-                    target_duration,
-                )
-                .unwrap();
-
-                let pixel_count = (view_config.width * view_config.height) as usize;
-                let mut raw_frame: Vec<u8> = vec![0; pixel_count * 3];
-                loop {
-                    if let Ok((image_data, frame_count)) = screenshot_receiver.try_recv() {
-                        if image_data.data.len() * 3 != raw_frame.len() {
-                            panic!("resolution changed");
-                        }
 
-                        for (index, (r, g, b, _)) in image_data.data.into_iter().enumerate() {
-                            raw_frame[index * 3] = r;
-                            raw_frame[index * 3 + 1] = g;
-                            raw_frame[index * 3 + 2] = b;
-                        }
-                        encoder.encode_frame(
-                            frame_count as f64 / view_config.target_fps as f64,
-                            &raw_frame,
-                        );
-                    } else if screenshot_stop.load(Ordering::Relaxed) {
-                        break;
-                    } else {
-                        thread::sleep(Duration::from_millis(1));
-                    }
+            if let Err(e) = result {
+                eprintln!("Failed to set up the MIDI clock: {:?}", e);
+            }
+        }
+
+        let (mut uniform_sources, placeholder_inputs) =
+            utils::load_inputs(project_path, &config.inputs, extended_config.input_fallback)?;
+        uniform_sources.extend(utils::load_audio_inputs(
+            project_path,
+            &extended_config.audio_inputs,
+            tempo_config
+                .as_ref()
+                .map(|tempo_config| tempo_config.input.as_str())
+                .zip(tempo_tracker.as_ref()),
+        )?);
+
+        let telemetry = if extended_config.telemetry.enable {
+            match TelemetryServer::new(&extended_config.telemetry, order_sender) {
+                Ok(telemetry) => Some(telemetry),
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    None
                 }
-            }))
+            }
         } else {
             None
         };
 
-        let uniform_sources = utils::load_inputs(project_path, &config.inputs)?;
-
         let main_script =
             if let Ok(main_script) = Script::new(project_path.join("src").join("main.rhai")) {
                 Some(main_script)
@@ -180,12 +249,18 @@ impl Wvr {
                 None
             };
 
-        Ok(Self {
+        let mut wvr = Self {
             project_path: project_path.to_owned(),
+            config_path: project_path.join("config.json"),
+            config_overrides: Vec::new(),
+            live_config: config.clone(),
 
             variables: config.variables.clone(),
             env_variable_list,
             uniform_sources: Rc::new(Mutex::new(uniform_sources)),
+            input_fallback: extended_config.input_fallback,
+            placeholder_inputs,
+            audio_inputs: extended_config.audio_inputs,
 
             shader_view,
 
@@ -200,9 +275,12 @@ impl Wvr {
 
             bpm: config.bpm as f64,
             target_fps: config.view.target_fps as f64,
-            locked_speed: config.view.locked_speed,
+            // Recordings started with the project are renders, so they never skip frames
+            locked_speed: config.view.locked_speed || config.view.screenshot,
+            follow_external_clocks: true,
 
             last_update_time: Instant::now(),
+            measured_fps: config.view.target_fps as f64,
 
             frame_count: 0,
             time: 0.0,
@@ -213,13 +291,53 @@ impl Wvr {
 
             main_script,
 
-            screenshot: config.view.screenshot,
+            tempo_config,
+            tempo_tracker,
+            tap_tempo: TapTempo::new(),
+            beats_per_bar: extended_config.sync.beats_per_bar.max(1),
+            link,
+            midi_clock_input,
+            midi_clock_output,
+
+            telemetry,
+            osc_replier: None,
+
+            history: History::new(&extended_config.history),
+
+            presets: extended_config.presets,
+            crossfade: None,
+
+            timeline: Timeline::new(&extended_config.timeline),
+
+            device_watcher: DeviceWatcher::new(&extended_config.devices),
+
+            recording_config,
+            recording_path: screenshot_path,
+            recorders: Vec::new(),
+            capture_readback: AsyncReadback::new(),
             screenshot_frame_count: config.view.screenshot_frame_count,
-            screenshot_sender,
-            _screenshot_thread: screenshot_thread,
+            take_frame_count: 0,
+        };
 
-            screenshot_stop,
-        })
+        if config.view.screenshot {
+            wvr.handle_record_order(&RecordOrder::Start)?;
+        }
+
+        Ok(wvr)
+    }
+
+    pub fn set_config_path(&mut self, config_path: PathBuf) {
+        self.config_path = config_path;
+    }
+
+    /// Lets queries received over OSC be answered to their sender
+    pub fn set_osc_replier(&mut self, osc_replier: OscReplier) {
+        self.osc_replier = Some(osc_replier);
+    }
+
+    /// Keeps the command line overrides applied when the config gets reloaded
+    pub fn set_config_overrides(&mut self, config_overrides: Vec<String>) {
+        self.config_overrides = config_overrides;
     }
 
     pub fn set_focused(&mut self, focused: bool) {
@@ -241,10 +359,102 @@ impl Wvr {
         }
     }
 
-    pub fn update(&mut self, display: &dyn Facade, resolution: (usize, usize)) -> Result<()> {
-        if self.screenshot {
-            self.locked_speed = true;
+    /// Applies the latest tempo detected from audio, returning the beat offset that brings
+    /// the beat phase closer to the detected one
+    fn follow_detected_tempo(&mut self, beat_diff: f64) -> f64 {
+        let (smoothing, lock) = match &self.tempo_config {
+            Some(tempo_config) if tempo_config.enable => {
+                (tempo_config.smoothing, tempo_config.lock)
+            }
+            _ => return 0.0,
+        };
+
+        let estimate = match &self.tempo_tracker {
+            Some(tempo_tracker) => tempo_tracker.lock().unwrap().take_estimate(),
+            None => None,
+        };
+        let estimate = if let Some(estimate) = estimate {
+            estimate
+        } else {
+            return 0.0;
+        };
+
+        if !lock {
+            self.bpm = smoothing * self.bpm + (1.0 - smoothing) * estimate.bpm;
+        }
+
+        // Detected beats should fall on whole beat values
+        let detected_beat = self.beat - (self.time - estimate.beat_time) * self.bpm / 60.0;
+        let phase_error = detected_beat - detected_beat.round();
+
+        // Late beats are caught up by slowing down, as going back would fire timeline cues again
+        (-phase_error * (1.0 - smoothing) * estimate.confidence).max(-beat_diff)
+    }
+
+    /// Follows the tempo and beat phase of the Link session, returning the beat offset bringing
+    /// the beat expected after `beat_diff` towards the session beat, modulo the bar length
+    fn follow_link(&mut self, beat_diff: f64) -> f64 {
+        let link = if let Some(link) = &self.link {
+            link
+        } else {
+            return 0.0;
+        };
+
+        if let Some((bpm, session_beat)) = link.get_session_beat() {
+            self.bpm = bpm;
+
+            sync::link::beat_correction(
+                self.beat + beat_diff,
+                session_beat,
+                beat_diff,
+                self.beats_per_bar as f64,
+            )
+        } else {
+            link.set_timeline(self.bpm, self.beat + beat_diff);
+            0.0
         }
+    }
+
+    /// Follows the clock and timecode of an external MIDI sequencer, returning the time and beat
+    /// offsets aligning the ones expected after `time_diff` and `beat_diff` on the sequencer
+    fn follow_midi_clock(&mut self, time_diff: f64, beat_diff: f64) -> (f64, f64) {
+        let midi_clock_input = if let Some(midi_clock_input) = &self.midi_clock_input {
+            midi_clock_input
+        } else {
+            return (0.0, 0.0);
+        };
+
+        let time_offset = match midi_clock_input.get_timecode() {
+            Some(time) => time - (self.time + time_diff),
+            None => 0.0,
+        };
+
+        let beat_offset = match midi_clock_input.get_clock() {
+            Some((bpm, beat)) => {
+                self.bpm = bpm;
+                beat - (self.beat + beat_diff)
+            }
+            None => 0.0,
+        };
+
+        (time_offset, beat_offset)
+    }
+
+    /// Sends the current position to the device following the MIDI clock
+    fn publish_midi_clock(&self) {
+        if let Some(midi_clock_output) = &self.midi_clock_output {
+            midi_clock_output.set_position(self.bpm, self.beat, self.time, self.playing);
+        }
+    }
+
+    /// Shares a local tempo change with the Link session
+    fn publish_tempo(&self) {
+        if let Some(link) = &self.link {
+            link.set_timeline(self.bpm, self.beat);
+        }
+    }
+
+    pub fn update(&mut self, display: &dyn Facade, resolution: (usize, usize)) -> Result<()> {
         let new_update_time = Instant::now();
 
         let beat_diff = if self.locked_speed {
@@ -260,7 +470,22 @@ impl Wvr {
             (new_update_time - self.last_update_time).as_secs_f64()
         };
 
+        let frame_duration = (new_update_time - self.last_update_time).as_secs_f64();
+        if frame_duration > 0.0 {
+            self.measured_fps = 0.9 * self.measured_fps + 0.1 / frame_duration;
+        }
+
+        let (time_diff, beat_diff) = if self.follow_external_clocks {
+            let beat_diff = beat_diff + self.follow_detected_tempo(beat_diff);
+            let beat_diff = beat_diff + self.follow_link(beat_diff);
+            let (time_offset, beat_offset) = self.follow_midi_clock(time_diff, beat_diff);
+            (time_diff + time_offset, beat_diff + beat_offset)
+        } else {
+            (time_diff, beat_diff)
+        };
+
         self.update_time(time_diff, beat_diff);
+        self.publish_midi_clock();
 
         let stage_index_list = self.shader_view.stage_index_list();
 
@@ -285,7 +510,11 @@ impl Wvr {
             }
         }
 
-        if !self.screenshot {
+        self.update_timeline(display);
+        self.update_crossfade(display)?;
+        self.update_devices();
+
+        if !self.is_recording() {
             self.shader_view.set_resolution(display, resolution)?;
         }
 
@@ -309,6 +538,140 @@ impl Wvr {
 
         self.last_update_time = new_update_time;
 
+        let is_state_due = self
+            .telemetry
+            .as_mut()
+            .map(|telemetry| telemetry.is_state_due())
+            .unwrap_or(false);
+        if is_state_due {
+            let state = TelemetryEvent::State(self.get_engine_state());
+            self.publish_telemetry(&state);
+        }
+
+        Ok(())
+    }
+
+    fn publish_telemetry(&self, event: &TelemetryEvent) {
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.publish(event);
+        }
+    }
+
+    /// Reports an error that does not interrupt the engine
+    fn report_error(&self, error: &anyhow::Error) {
+        eprintln!("{:?}", error);
+        self.publish_telemetry(&TelemetryEvent::Error {
+            message: format!("{:?}", error),
+        });
+    }
+
+    pub fn get_engine_state(&self) -> EngineState {
+        let (mut audio_inputs, mut inputs): (Vec<String>, Vec<String>) = self
+            .uniform_sources
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .partition(|input_name| self.audio_inputs.contains_key(input_name));
+        inputs.sort();
+        audio_inputs.sort();
+
+        EngineState {
+            frame_count: self.frame_count,
+            time: self.time,
+            beat: self.beat,
+            bpm: self.bpm,
+            fps: self.measured_fps,
+            playing: self.playing,
+            recording: self.is_recording(),
+            resolution: self.shader_view.get_resolution(),
+            render_chain: self
+                .live_config
+                .render_chain
+                .iter()
+                .map(|render_stage_config| StageState::from_config(render_stage_config, self.beat))
+                .collect(),
+            final_stage: StageState::from_config(&self.live_config.final_stage, self.beat),
+            inputs,
+            audio_inputs,
+            variables: self.env_variable_list.clone(),
+        }
+    }
+
+    /// Brings into the live config the changes no message reports
+    fn sync_live_config(&mut self) {
+        // The tempo also changes through taps, Link or MIDI clock
+        self.live_config.bpm = self.bpm as _;
+    }
+
+    pub fn handle_save_order(&mut self, save_order: &SaveOrder) -> Result<()> {
+        self.sync_live_config();
+
+        let target_path = save_order.target_path(&self.project_path, &self.config_path)?;
+
+        // The running config has its fragments and overrides merged in, so writing it over
+        // a layered config would flatten it: it can only be saved as a new, standalone file
+        let is_layered = !self.config_overrides.is_empty()
+            || layer::has_includes(&self.config_path).unwrap_or(false);
+        if is_layered {
+            if let SaveOrder::Save = save_order {
+                bail!(
+                    "{:?} includes fragments or has overrides, save the project under another name instead",
+                    self.config_path
+                );
+            }
+
+            if target_path.exists() {
+                let canonical_target = fs::canonicalize(&target_path)
+                    .context(format!("Could not find {:?}", target_path))?;
+                if layer::layer_files(&self.config_path)?.contains(&canonical_target) {
+                    bail!(
+                        "Cannot save to {:?}, which the project config is built from",
+                        target_path
+                    );
+                }
+            }
+        }
+
+        // Settings that are only read at startup are kept as the current file has them
+        let (_, mut extended_config) = utils::load_config(&self.config_path, &[])
+            .context("Failed to read the settings of the current project config")?;
+        extended_config.presets = self.presets.clone();
+        extended_config.audio_inputs = self.audio_inputs.clone();
+
+        config::save_project_config(&target_path, &self.live_config, &extended_config)?;
+        println!("Saved project to {:?}", target_path);
+
+        // The saved file has the overrides baked in
+        self.config_path = target_path;
+        self.config_overrides.clear();
+
+        Ok(())
+    }
+
+    pub fn handle_query(&self, query: &Query, client: QueryClient) -> Result<()> {
+        let response = match query {
+            Query::Project => TelemetryEvent::Project {
+                config: self.live_config.clone(),
+                audio_inputs: self.audio_inputs.clone(),
+                state: self.get_engine_state(),
+            },
+            Query::State => TelemetryEvent::State(self.get_engine_state()),
+        };
+
+        match client {
+            QueryClient::Telemetry(client_id) => self
+                .telemetry
+                .as_ref()
+                .context("The telemetry server is disabled")?
+                .respond(client_id, &response),
+            QueryClient::Osc(address) => self
+                .osc_replier
+                .as_ref()
+                .context("The OSC server is disabled")?
+                .reply(address, *query, &response)?,
+        }
+
         Ok(())
     }
 
@@ -318,52 +681,586 @@ impl Wvr {
         Ok(())
     }
 
-    pub fn render_final_stage(
+    pub fn render_final_stage<S: Surface>(
         &mut self,
         display: &dyn Facade,
-        window_frame: &mut Frame,
+        surface: &mut S,
     ) -> Result<()> {
-        self.shader_view.render_final_stage(display, window_frame)?;
-
-        if self.screenshot {
-            let mut currently_rendered_stage = None;
-            if let Some(final_stage_input) = self
-                .shader_view
-                .get_final_stage()
-                .get_input_map()
-                .get("iChannel0")
-            {
-                match final_stage_input {
-                    InputSampler::Nearest(input_name) => {
-                        currently_rendered_stage = Some(input_name.to_string())
-                    }
-                    InputSampler::Linear(input_name) => {
-                        currently_rendered_stage = Some(input_name.to_string())
+        // Paused frames are skipped so that takes only contain the played animation
+        let is_capturing = self.playing && self.is_recording();
+        let is_capturing_final_stage = is_capturing
+            && self
+                .recorders
+                .iter()
+                .any(|recorder| recorder.get_source() == &RecordSource::Final);
+
+        if is_capturing_final_stage {
+            let capture_texture = self
+                .capture_readback
+                .get_texture(display, self.shader_view.get_resolution())?;
+
+            self.shader_view
+                .render_final_stage(display, &mut capture_texture.as_surface())?;
+            capture_texture
+                .as_surface()
+                .fill(surface, MagnifySamplerFilter::Linear);
+
+            self.capture_readback.request();
+            self.push_captured_frames(false);
+        } else {
+            self.shader_view.render_final_stage(display, surface)?;
+        }
+
+        if is_capturing {
+            let resolution = self.shader_view.get_resolution();
+
+            for recorder in self.recorders.iter_mut() {
+                if let RecordSource::Stage(stage_name) = recorder.get_source().clone() {
+                    if let Some(image_data) = self.shader_view.take_screenshot(&stage_name) {
+                        if let Err(e) = recorder.push_frame(&image_data?.data, resolution, false) {
+                            eprintln!("{:?}", e);
+                        }
                     }
-                    InputSampler::Mipmaps(input_name) => {
-                        currently_rendered_stage = Some(input_name.to_string())
+                }
+            }
+
+            self.take_frame_count += 1;
+        }
+
+        self.frame_count += 1;
+
+        Ok(())
+    }
+
+    /// Hands the finished readbacks of the final stage over to its recorder,
+    /// waiting for all of them when flushing
+    fn push_captured_frames(&mut self, flush: bool) {
+        while let Some(mut frame) = self.capture_readback.next_ready(flush) {
+            let pixels = frame.pixel_buffer.map_read();
+
+            for recorder in self.recorders.iter_mut() {
+                if recorder.get_source() == &RecordSource::Final {
+                    if let Err(e) = recorder.push_frame(&pixels, frame.resolution, true) {
+                        eprintln!("{:?}", e);
                     }
                 }
             }
-            if let Some(currently_rendered_stage) = currently_rendered_stage {
-                if let Some(texture) = self.shader_view.take_screenshot(&currently_rendered_stage) {
-                    if let Err(e) = self.screenshot_sender.send((texture?, self.frame_count)) {
-                        eprintln!(
-                            "Screenshot processing thread seems to have crashed:\n {:?}",
-                            e
-                        );
-                        self.screenshot = false;
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        !self.recorders.is_empty()
+    }
+
+    fn start_recording(&mut self) -> Result<()> {
+        if self.is_recording() {
+            return Ok(());
+        }
+
+        let resolution = self.shader_view.get_resolution();
+        let policy = self.get_backpressure_policy();
+
+        self.capture_readback.clear();
+        self.take_frame_count = 0;
+
+        let stage_names: Vec<&str> = self
+            .live_config
+            .render_chain
+            .iter()
+            .map(|render_stage_config| render_stage_config.name.as_str())
+            .collect();
+
+        let mut sources: Vec<RecordSource> = Vec::new();
+        for source in &self.recording_config.sources {
+            // A take of a missing stage would silently stay empty
+            if let RecordSource::Stage(stage_name) = source {
+                if !stage_names.contains(&stage_name.as_str()) {
+                    bail!(
+                        "Cannot record stage {:?}, which is not in the render chain. Existing stages: {:}",
+                        stage_name,
+                        stage_names.join(", ")
+                    );
+                }
+            }
+
+            if !sources.contains(source) {
+                sources.push(source.clone());
+            }
+        }
+
+        for source in sources {
+            let mut recorder = Recorder::new(self.recording_path.clone(), source);
+
+            if let Err(e) =
+                recorder.start(&self.recording_config, resolution, self.target_fps, policy)
+            {
+                self.stop_recording();
+                return Err(e);
+            }
+
+            self.recorders.push(recorder);
+        }
+
+        Ok(())
+    }
+
+    fn stop_recording(&mut self) {
+        self.push_captured_frames(true);
+
+        for recorder in self.recorders.iter_mut() {
+            recorder.stop();
+        }
+
+        self.recorders.clear();
+    }
+
+    fn get_backpressure_policy(&self) -> BackpressurePolicy {
+        self.recording_config
+            .backpressure
+            .unwrap_or(if self.locked_speed {
+                BackpressurePolicy::Block
+            } else {
+                BackpressurePolicy::Drop
+            })
+    }
+
+    pub fn handle_command(&mut self, display: &dyn Facade, command: &Command) -> Result<()> {
+        let result = self.apply_command(display, command);
+
+        match &result {
+            Ok(()) => self.publish_telemetry(&TelemetryEvent::Order {
+                order: command.clone(),
+            }),
+            Err(e) => self.publish_telemetry(&TelemetryEvent::Error {
+                message: format!("{:?}", e),
+            }),
+        }
+
+        result
+    }
+
+    fn apply_command(&mut self, display: &dyn Facade, command: &Command) -> Result<()> {
+        match command {
+            Command::Message(message) => {
+                self.sync_live_config();
+                let inverse = history::inverse_message(&self.live_config, message);
+
+                self.handle_message(display, message)?;
+
+                if let Some(inverse) = inverse {
+                    self.history.record(message, inverse);
+                }
+            }
+            Command::History(history_order) => self.handle_history_order(display, history_order)?,
+            Command::Preset(preset_order) => self.handle_preset_order(display, preset_order)?,
+            Command::ReloadConfig => self.reload_config(display),
+            Command::Recording(update) => self.recording_config.apply(update)?,
+            Command::Record(record_order) => self.handle_record_order(record_order)?,
+            Command::Tempo(tempo_order) => self.handle_tempo_order(tempo_order),
+            Command::Query { query, client } => self.handle_query(query, *client)?,
+            Command::Save(save_order) => self.handle_save_order(save_order)?,
+            Command::TempoTracking(update) => {
+                if let Some(tempo_config) = &mut self.tempo_config {
+                    tempo_config.apply(update);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn handle_history_order(
+        &mut self,
+        display: &dyn Facade,
+        history_order: &HistoryOrder,
+    ) -> Result<()> {
+        match history_order {
+            // Edits only move to the other stack once applied, so that a failing one stays in place
+            HistoryOrder::Undo => {
+                let inverse = self
+                    .history
+                    .last_edit()
+                    .context("Nothing to undo")?
+                    .inverse
+                    .clone();
+                for message in &inverse {
+                    self.handle_message(display, message)?;
+                }
+                self.history.undone();
+            }
+            HistoryOrder::Redo => {
+                let message = self
+                    .history
+                    .last_undone_edit()
+                    .context("Nothing to redo")?
+                    .message
+                    .clone();
+
+                // The project may have changed since the undo, so that the edit could not be
+                // reverted anymore once redone
+                self.sync_live_config();
+                let inverse = history::inverse_message(&self.live_config, &message)
+                    .context("The edit cannot be redone on the project as it is now")?;
+                self.handle_message(display, &message)?;
+                self.history.redone(inverse);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the changes of the project config file, errors being reported rather than
+    /// stopping playback
+    pub fn reload_config(&mut self, display: &dyn Facade) {
+        let (new_config, new_extended_config) =
+            match utils::load_config(&self.config_path, &self.config_overrides) {
+                Ok(configs) => configs,
+                Err(e) => {
+                    self.report_error(&e);
+                    return;
+                }
+            };
+
+        self.sync_live_config();
+        let changes = reload::config_changes(&self.live_config, &new_config);
+
+        for message in &changes.messages {
+            if let Err(e) = self.handle_message(display, message) {
+                self.report_error(&e);
+            }
+        }
+        for change in &changes.restart_needed {
+            self.report_error(&anyhow!("{:} need a restart to be applied", change));
+        }
+
+        self.variables = new_config.variables.clone();
+        self.env_variable_list
+            .retain(|variable_name, _| new_config.variables.contains_key(variable_name));
+        self.live_config.variables = new_config.variables;
+
+        self.reload_audio_inputs(&new_extended_config.audio_inputs);
+
+        println!(
+            "Reloaded project config {:?}, {:} changes applied",
+            self.config_path,
+            changes.messages.len()
+        );
+    }
+
+    /// Rebuilds the audio inputs whose config changed, and adds and removes the others
+    fn reload_audio_inputs(&mut self, new_audio_inputs: &HashMap<String, AudioInputConfig>) {
+        let removed_inputs: Vec<String> = self
+            .audio_inputs
+            .keys()
+            .filter(|input_name| !new_audio_inputs.contains_key(*input_name))
+            .cloned()
+            .collect();
+        for input_name in removed_inputs {
+            self.audio_inputs.remove(&input_name);
+            self.uniform_sources.lock().unwrap().remove(&input_name);
+        }
+
+        for (input_name, input_config) in new_audio_inputs {
+            if self.audio_inputs.get(input_name) == Some(input_config) {
+                continue;
+            }
+
+            if let Err(e) = self.add_audio_input(input_name, input_config) {
+                self.report_error(&e);
+            }
+        }
+    }
+
+    fn add_audio_input(&mut self, input_name: &str, input_config: &AudioInputConfig) -> Result<()> {
+        let tempo_tracker = match (&self.tempo_config, &self.tempo_tracker) {
+            (Some(tempo_config), Some(tempo_tracker)) if tempo_config.input == input_name => {
+                Some(tempo_tracker.clone())
+            }
+            _ => None,
+        };
+
+        let mut input_provider = audio::audio_input_from_config(
+            &self.project_path,
+            input_config,
+            input_name,
+            tempo_tracker,
+        )
+        .context(format!("Failed to load audio input {:?}", input_name))?;
+        if !self.playing {
+            input_provider.pause()?;
+        }
+
+        self.audio_inputs
+            .insert(input_name.to_owned(), input_config.clone());
+        self.uniform_sources
+            .lock()
+            .unwrap()
+            .insert(input_name.to_owned(), input_provider);
+
+        Ok(())
+    }
+
+    pub fn handle_preset_order(
+        &mut self,
+        display: &dyn Facade,
+        preset_order: &PresetOrder,
+    ) -> Result<()> {
+        match preset_order {
+            PresetOrder::Store(preset_name) => {
+                self.presets
+                    .insert(preset_name.clone(), Preset::capture(&self.live_config));
+            }
+            PresetOrder::Recall(preset_name, duration) => {
+                let preset = self
+                    .presets
+                    .get(preset_name)
+                    .context(format!("Unknown preset {:?}", preset_name))?;
+
+                self.crossfade = Some(Crossfade::new(
+                    preset,
+                    &self.live_config,
+                    self.beat,
+                    *duration,
+                ));
+                // Applies a crossfade without duration right away
+                self.update_crossfade(display)?;
+            }
+            PresetOrder::Delete(preset_name) => {
+                self.presets
+                    .remove(preset_name)
+                    .context(format!("Unknown preset {:?}", preset_name))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the timeline cues met since the last frame, following their jumps
+    fn update_timeline(&mut self, display: &dyn Facade) {
+        for _ in 0..MAX_JUMPS_PER_UPDATE {
+            let step = self.timeline.advance(self.time, self.beat);
+
+            // A failing cue is skipped so that the show goes on
+            for action in &step.actions {
+                let result = match action {
+                    CueAction::Message(message) => self.handle_message(display, message),
+                    CueAction::RecallPreset { name, duration } => self.handle_preset_order(
+                        display,
+                        &PresetOrder::Recall(name.clone(), *duration),
+                    ),
+                    // Jumps are taken by the timeline itself
+                    CueAction::Jump { .. } => Ok(()),
+                };
+
+                if let Err(e) = result {
+                    eprintln!("Failed to run a timeline cue: {:?}", e);
+                }
+            }
+
+            let (time_diff, beat_diff) = match step.jump {
+                Some(CuePosition::Beat(beat)) => {
+                    let beat_diff = beat - self.beat;
+                    (beat_diff * 60.0 / self.bpm, beat_diff)
+                }
+                Some(CuePosition::Time(time)) => {
+                    let time_diff = time - self.time;
+                    (time_diff, time_diff * self.bpm / 60.0)
+                }
+                None => break,
+            };
+
+            self.update_time(time_diff, beat_diff);
+            self.timeline.jumped(self.time, self.beat);
+        }
+    }
+
+    /// Rebuilds the camera and MIDI inputs whose device got plugged in,
+    /// and shows a placeholder for those whose device got unplugged
+    fn update_devices(&mut self) {
+        let input_list = &self.live_config.inputs;
+        self.device_watcher
+            .retain(|input_name| input_list.contains_key(input_name));
+
+        let device_scan = match self.device_watcher.scan(input_list.values()) {
+            Some(device_scan) => device_scan,
+            None => return,
+        };
+
+        let input_list = self.live_config.inputs.clone();
+
+        for (input_name, input_config) in &input_list {
+            let is_connected = match device_scan.is_connected(&self.project_path, input_config) {
+                Some(is_connected) => is_connected,
+                None => continue,
+            };
+
+            let is_placeholder = self.placeholder_inputs.contains_key(input_name);
+            match self.device_watcher.update(input_name, is_connected) {
+                Some(DeviceChange::Appeared) if is_placeholder => {
+                    println!("Reconnecting input {:?} to its device", input_name);
+                    if let Err(e) = self.add_input(input_name, input_config) {
+                        self.report_error(&e);
                     }
+
+                    // Devices are often not ready as soon as they show up
+                    if self.placeholder_inputs.contains_key(input_name) {
+                        self.device_watcher.forget(input_name);
+                    }
+                }
+                // Under the `Abort` fallback, inputs never turn into placeholders
+                Some(DeviceChange::Disappeared) if self.input_fallback == InputFallback::Abort => {
+                    eprintln!("The device of input {:?} got disconnected", input_name);
                 }
+                Some(DeviceChange::Disappeared) if !is_placeholder => {
+                    eprintln!(
+                        "The device of input {:?} got disconnected, replaced by a placeholder",
+                        input_name
+                    );
+                    self.placeholder_inputs
+                        .insert(input_name.clone(), input_config.clone());
+                    self.uniform_sources.lock().unwrap().insert(
+                        input_name.clone(),
+                        Box::new(PlaceholderProvider::new(
+                            input_name,
+                            input_config,
+                            self.input_fallback,
+                        )),
+                    );
+                }
+                _ => (),
             }
         }
+    }
 
-        self.frame_count += 1;
+    /// Moves the variables along the running crossfade, if any
+    fn update_crossfade(&mut self, display: &dyn Facade) -> Result<()> {
+        let (stage_messages, variable_values, is_finished) = match &self.crossfade {
+            Some(crossfade) => (
+                crossfade.stage_messages(&self.live_config, self.beat),
+                crossfade.variable_values(self.beat),
+                crossfade.is_finished(self.beat),
+            ),
+            None => return Ok(()),
+        };
+
+        if is_finished {
+            self.crossfade = None;
+        }
+
+        for message in &stage_messages {
+            self.handle_message(display, message)?;
+        }
+
+        for (variable_name, variable_value) in variable_values {
+            if let Some((value, _)) = self.variables.get_mut(&variable_name) {
+                *value = variable_value.clone();
+            }
+            if let Some((value, _)) = self.live_config.variables.get_mut(&variable_name) {
+                *value = variable_value;
+            }
+        }
 
         Ok(())
     }
 
+    pub fn handle_tempo_order(&mut self, tempo_order: &TempoOrder) {
+        match tempo_order {
+            TempoOrder::Tap => {
+                if let Some(bpm) = self.tap_tempo.tap(Instant::now()) {
+                    self.bpm = bpm;
+                    // Taps land on beats
+                    self.beat = self.beat.round();
+
+                    self.publish_tempo();
+                }
+            }
+            TempoOrder::Nudge(beat_offset) => self.beat += beat_offset,
+            TempoOrder::ResyncToBar => {
+                let beats_per_bar = self.beats_per_bar as f64;
+                self.beat = (self.beat / beats_per_bar).round() * beats_per_bar;
+
+                self.timeline.seek(self.time, self.beat);
+            }
+            TempoOrder::Restart => {
+                self.update_time(-self.time, -self.beat);
+                self.timeline.restart();
+
+                self.publish_tempo();
+            }
+            TempoOrder::SetBeatsPerBar(beats_per_bar) => {
+                self.beats_per_bar = (*beats_per_bar).max(1);
+            }
+        }
+    }
+
+    pub fn handle_record_order(&mut self, record_order: &RecordOrder) -> Result<()> {
+        match record_order {
+            RecordOrder::Start => self.start_recording()?,
+            RecordOrder::Stop => self.stop_recording(),
+            RecordOrder::Split => {
+                if self.is_recording() {
+                    self.stop_recording();
+                    self.start_recording()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads an input, or a placeholder standing for it when it fails to load.
+    /// Under the `Abort` fallback, the input is left out and the error returned.
+    fn add_input(&mut self, input_name: &str, input_config: &InputConfig) -> Result<()> {
+        let input_provider: Box<dyn InputProvider> = match utils::input_from_config(
+            &self.project_path,
+            input_config,
+            input_name,
+            self.beat,
+            self.time,
+            self.playing,
+        ) {
+            Ok(input_provider) => {
+                self.placeholder_inputs.remove(input_name);
+                input_provider
+            }
+            Err(e) => {
+                let e = e.context(format!("Failed to load input {:?}", input_name));
+                let placeholder = match placeholder::placeholder_for(
+                    input_name,
+                    input_config,
+                    self.input_fallback,
+                ) {
+                    Some(placeholder) => placeholder,
+                    None => return Err(e),
+                };
+                self.report_error(&e);
+
+                self.placeholder_inputs
+                    .insert(input_name.to_owned(), input_config.clone());
+                Box::new(placeholder)
+            }
+        };
+
+        self.uniform_sources
+            .lock()
+            .unwrap()
+            .insert(input_name.to_owned(), input_provider);
+
+        Ok(())
+    }
+
+    /// Applies a message to the running project, whatever sent it, and tells the telemetry
+    /// clients about the change
     pub fn handle_message(&mut self, display: &dyn Facade, message: &Message) -> Result<()> {
+        self.apply_message(display, message)?;
+        self.publish_telemetry(&TelemetryEvent::Change {
+            message: message.clone(),
+        });
+
+        Ok(())
+    }
+
+    fn apply_message(&mut self, display: &dyn Facade, message: &Message) -> Result<()> {
         match message {
             Message::Start => {
                 self.play()?;
@@ -375,26 +1272,12 @@ impl Wvr {
                 self.stop();
             }
             Message::Insert((input_name, input_config)) => {
-                match utils::input_from_config(
-                    &self.project_path,
-                    input_config,
-                    input_name,
-                    self.beat,
-                    self.time,
-                    self.playing,
-                ) {
-                    Ok(input_provider) => {
-                        self.uniform_sources
-                            .lock()
-                            .unwrap()
-                            .insert(input_name.clone(), input_provider);
-                    }
-                    Err(e) => eprintln!("{:?}", e),
-                }
+                self.add_input(input_name, input_config)?;
             }
             Message::Set(set_info) => match set_info {
                 SetInfo::Bpm(bpm) => {
                     self.bpm = *bpm;
+                    self.publish_tempo();
                 }
                 SetInfo::Width(width) => {
                     let previous_dynamic_resolution = self.shader_view.get_dynamic_resolution();
@@ -438,7 +1321,11 @@ impl Wvr {
                     self.locked_speed = *locked_speed;
                 }
                 SetInfo::Screenshot(screenshot) => {
-                    self.screenshot = *screenshot;
+                    if *screenshot {
+                        self.handle_record_order(&RecordOrder::Start)?;
+                    } else {
+                        self.handle_record_order(&RecordOrder::Stop)?;
+                    }
                 }
             },
             Message::RemoveRenderStage(render_stage_index) => {
@@ -521,24 +1408,21 @@ impl Wvr {
                 }
             }
             Message::AddInput(input_name, input_config) => {
-                match utils::input_from_config(
-                    &self.project_path,
-                    input_config,
-                    input_name,
-                    self.beat,
-                    self.time,
-                    self.playing,
-                ) {
-                    Ok(input_provider) => {
-                        self.uniform_sources
-                            .lock()
-                            .unwrap()
-                            .insert(input_name.clone(), input_provider);
-                    }
-                    Err(e) => eprintln!("{:?}", e),
-                }
+                self.add_input(input_name, input_config)?;
+            }
+            Message::UpdateInput(input_name, input_order @ InputUpdate::SetPath(_))
+                if self.placeholder_inputs.contains_key(input_name) =>
+            {
+                // A placeholder gets another go at loading its input when given a new path
+                let mut input_config = self.placeholder_inputs[input_name].clone();
+                config::apply_input_update(&mut input_config, input_order);
+                self.add_input(input_name, &input_config)?;
             }
             Message::UpdateInput(input_name, input_order) => {
+                if let Some(input_config) = self.placeholder_inputs.get_mut(input_name) {
+                    config::apply_input_update(input_config, input_order);
+                }
+
                 if let Some(input) = self.uniform_sources.lock().unwrap().get_mut(input_name) {
                     match input_order {
                         InputUpdate::SetHeight(new_height) => {
@@ -562,6 +1446,15 @@ impl Wvr {
                 }
             }
             Message::RenameInput(old_input_name, new_input_name) => {
+                if let Some(input_config) = self.placeholder_inputs.remove(old_input_name) {
+                    self.placeholder_inputs
+                        .insert(new_input_name.clone(), input_config);
+                }
+                if let Some(input_config) = self.audio_inputs.remove(old_input_name) {
+                    self.audio_inputs
+                        .insert(new_input_name.clone(), input_config);
+                }
+
                 if let Some(mut input) = self.uniform_sources.lock().unwrap().remove(old_input_name)
                 {
                     input.set_name(new_input_name);
@@ -572,10 +1465,14 @@ impl Wvr {
                 }
             }
             Message::RemoveInput(input_name) => {
+                self.placeholder_inputs.remove(input_name);
+                self.audio_inputs.remove(input_name);
                 self.uniform_sources.lock().unwrap().remove(input_name);
             }
         }
 
+        config::apply_message(&mut self.live_config, message);
+
         Ok(())
     }
 
@@ -583,6 +1480,18 @@ impl Wvr {
         self.playing
     }
 
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn set_locked_speed(&mut self, locked_speed: bool) {
+        self.locked_speed = locked_speed;
+    }
+
+    pub fn set_follow_external_clocks(&mut self, follow_external_clocks: bool) {
+        self.follow_external_clocks = follow_external_clocks;
+    }
+
     pub fn stop(&mut self) {
         if self.stopped {
             return;
@@ -594,7 +1503,10 @@ impl Wvr {
             }
         }
 
-        self.screenshot_stop.store(true, Ordering::Relaxed);
+        self.stop_recording();
+
+        // Dropping the clock output lets the devices following it know the show is over
+        self.midi_clock_output = None;
 
         self.stopped = true;
         self.playing = false;
@@ -610,6 +1522,7 @@ impl Wvr {
         }
 
         self.playing = false;
+        self.publish_midi_clock();
 
         Ok(())
     }
@@ -627,6 +1540,7 @@ impl Wvr {
         }
 
         self.playing = true;
+        self.publish_midi_clock();
 
         Ok(())
     }
@@ -644,8 +1558,31 @@ impl Wvr {
     pub fn get_screenshot_frame_count(&self) -> i64 {
         self.screenshot_frame_count
     }
+    pub fn get_take_frame_count(&self) -> usize {
+        self.take_frame_count
+    }
+    /// Whether the current take holds the number of frames asked for by `screenshot_frame_count`
+    pub fn is_take_complete(&self) -> bool {
+        self.is_recording()
+            && self.screenshot_frame_count != -1
+            && self.take_frame_count as i64 >= self.screenshot_frame_count
+    }
     pub fn get_recording(&self) -> bool {
-        self.screenshot
+        self.is_recording()
+    }
+    pub fn get_bpm(&self) -> f64 {
+        self.bpm
+    }
+    pub fn get_beats_per_bar(&self) -> usize {
+        self.beats_per_bar
+    }
+    pub fn get_recording_stats(&self) -> (usize, usize, usize) {
+        self.recorders
+            .iter()
+            .map(|recorder| recorder.get_stats())
+            .fold((0, 0, 0), |total, stats| {
+                (total.0 + stats.0, total.1 + stats.1, total.2 + stats.2)
+            })
     }
 }
 
@@ -653,18 +1590,23 @@ pub fn start_wvr(
     display: Display,
     mut wvr: Wvr,
     event_loop: EventLoop<()>,
-    order_receiver: Receiver<Message>,
+    order_receiver: Receiver<Command>,
 ) {
+    let mut modifiers = ModifiersState::empty();
+    let mut last_progress_report = Instant::now();
+
     event_loop.run(move |event, _, control_flow| {
-        if wvr.get_recording() {
-            println!(
-                "{:} / {:}",
-                wvr.get_frame_count(),
-                wvr.get_screenshot_frame_count()
-            );
-            if wvr.get_screenshot_frame_count() != -1
-                && wvr.get_frame_count() as i64 >= wvr.get_screenshot_frame_count()
-            {
+        if wvr.get_recording() && wvr.get_screenshot_frame_count() != -1 {
+            if last_progress_report.elapsed() >= Duration::from_secs(1) {
+                println!(
+                    "Recorded {:} / {:} frames",
+                    wvr.get_take_frame_count(),
+                    wvr.get_screenshot_frame_count()
+                );
+                last_progress_report = Instant::now();
+            }
+
+            if wvr.is_take_complete() {
                 *control_flow = ControlFlow::Exit;
 
                 wvr.stop();
@@ -682,6 +1624,50 @@ pub fn start_wvr(
                     wvr.set_focused(focused);
                 } else if let WindowEvent::CursorMoved { position, .. } = event {
                     wvr.set_mouse_position((position.x, position.y));
+                } else if let WindowEvent::ModifiersChanged(new_modifiers) = event {
+                    modifiers = new_modifiers;
+                } else if let WindowEvent::KeyboardInput { input, .. } = event {
+                    if input.state == ElementState::Pressed {
+                        let command = match (input.virtual_keycode, modifiers.ctrl()) {
+                            (Some(VirtualKeyCode::S), true) => Some(Command::Save(SaveOrder::Save)),
+                            (Some(VirtualKeyCode::Z), true) if modifiers.shift() => {
+                                Some(Command::History(HistoryOrder::Redo))
+                            }
+                            (Some(VirtualKeyCode::Z), true) => {
+                                Some(Command::History(HistoryOrder::Undo))
+                            }
+                            (Some(VirtualKeyCode::Y), true) => {
+                                Some(Command::History(HistoryOrder::Redo))
+                            }
+                            (Some(VirtualKeyCode::Space), false) => {
+                                Some(Command::Tempo(TempoOrder::Tap))
+                            }
+                            (Some(VirtualKeyCode::Left), false) => {
+                                Some(Command::Tempo(TempoOrder::Nudge(-NUDGE_STEP)))
+                            }
+                            (Some(VirtualKeyCode::Right), false) => {
+                                Some(Command::Tempo(TempoOrder::Nudge(NUDGE_STEP)))
+                            }
+                            (Some(VirtualKeyCode::Return), false) => {
+                                Some(Command::Tempo(TempoOrder::ResyncToBar))
+                            }
+                            (Some(VirtualKeyCode::Up), false) => Some(Command::Tempo(
+                                TempoOrder::SetBeatsPerBar(wvr.get_beats_per_bar() + 1),
+                            )),
+                            (Some(VirtualKeyCode::Down), false) => {
+                                Some(Command::Tempo(TempoOrder::SetBeatsPerBar(
+                                    wvr.get_beats_per_bar().saturating_sub(1),
+                                )))
+                            }
+                            _ => None,
+                        };
+
+                        if let Some(command) = command {
+                            if let Err(e) = wvr.handle_command(&display, &command) {
+                                eprintln!("{:?}", e);
+                            }
+                        }
+                    }
                 }
             }
             Event::RedrawRequested(_) => {
@@ -730,8 +1716,148 @@ pub fn start_wvr(
             e => println!("{:?}", e),
         }
 
-        for message in order_receiver.try_iter() {
-            wvr.handle_message(&display, &message).unwrap();
+        // A failing order, such as an undo with an empty history, must not stop the show
+        for command in order_receiver.try_iter() {
+            if let Err(e) = wvr.handle_command(&display, &command) {
+                eprintln!("{:?}", e);
+            }
         }
     });
 }
+
+pub fn start_wvr_headless(
+    display: HeadlessRenderer,
+    mut wvr: Wvr,
+    frame_budget: Option<usize>,
+    order_receiver: Receiver<Command>,
+) -> Result<()> {
+    // Without a display to pace the rendering, time has to advance one frame at a time
+    wvr.set_locked_speed(true);
+
+    let mut output_texture =
+        Texture2d::empty(&display, wvr.get_width() as u32, wvr.get_height() as u32)
+            .context("Failed to create the offscreen render target")?;
+
+    while !wvr.is_stopped() {
+        // A failing order, such as an undo with an empty history, must not stop the render
+        for command in order_receiver.try_iter() {
+            if let Err(e) = wvr.handle_command(&display, &command) {
+                eprintln!("{:?}", e);
+            }
+        }
+
+        if let Some(frame_budget) = frame_budget {
+            if wvr.get_frame_count() >= frame_budget {
+                break;
+            }
+        }
+
+        if wvr.is_take_complete() {
+            break;
+        }
+
+        if !wvr.is_playing() {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        let resolution = (wvr.get_width(), wvr.get_height());
+        if output_texture.dimensions() != (resolution.0 as u32, resolution.1 as u32) {
+            output_texture = Texture2d::empty(&display, resolution.0 as u32, resolution.1 as u32)
+                .context("Failed to resize the offscreen render target")?;
+        }
+
+        wvr.update(&display, resolution)
+            .context("Failed to update app")?;
+        wvr.render_stages(&display)
+            .context("Failed to render stages")?;
+        wvr.render_final_stage(&display, &mut output_texture.as_surface())
+            .context("Failed to render to offscreen target")?;
+
+        display.finish();
+    }
+
+    wvr.stop();
+
+    Ok(())
+}
+
+pub fn start_wvr_offline(
+    display: HeadlessRenderer,
+    mut wvr: Wvr,
+    render_config: OfflineRenderConfig,
+    order_receiver: Receiver<Command>,
+) -> Result<()> {
+    render_config.create_output_folder()?;
+
+    // Every frame is rendered from the start so that feedback stages stay deterministic,
+    // only frames within the requested range are written to disk.
+    // External clocks run in real time, which would make the render depend on its own speed.
+    wvr.set_locked_speed(true);
+    wvr.set_follow_external_clocks(false);
+    wvr.play()?;
+
+    let resolution = (wvr.get_width(), wvr.get_height());
+    let output_texture = render_config
+        .format
+        .create_render_target(&display, resolution)?;
+
+    let mut written_frame_count = 0;
+    let mut last_progress_report = Instant::now();
+    while !wvr.is_stopped() {
+        // A failing order, such as an undo with an empty history, must not stop the render
+        for command in order_receiver.try_iter() {
+            if let Err(e) = wvr.handle_command(&display, &command) {
+                eprintln!("{:?}", e);
+            }
+        }
+
+        wvr.update(&display, resolution)
+            .context("Failed to update app")?;
+
+        if let Some(to) = render_config.to {
+            if to.is_reached(wvr.get_frame_count(), wvr.time, wvr.beat) {
+                break;
+            }
+        }
+        if let Some(frame_count) = render_config.frame_count {
+            if written_frame_count >= frame_count {
+                break;
+            }
+        }
+
+        let frame_index = wvr.get_frame_count();
+        let is_in_range = render_config
+            .from
+            .is_reached(frame_index, wvr.time, wvr.beat);
+
+        wvr.render_stages(&display)
+            .context("Failed to render stages")?;
+        wvr.render_final_stage(&display, &mut output_texture.as_surface())
+            .context("Failed to render to offscreen target")?;
+
+        if is_in_range {
+            let frame_path = render_config.get_frame_path(frame_index);
+            let frame = offline::read_frame(&output_texture, render_config.format);
+
+            offline::write_frame(&frame_path, &frame)?;
+
+            written_frame_count += 1;
+            if last_progress_report.elapsed() >= Duration::from_secs(1) {
+                println!(
+                    "Rendered {:} frames, up to {:?}",
+                    written_frame_count, frame_path
+                );
+                last_progress_report = Instant::now();
+            }
+        }
+    }
+    println!(
+        "Rendered {:} frames to {:?}",
+        written_frame_count, render_config.output_path
+    );
+
+    wvr.stop();
+
+    Ok(())
+}
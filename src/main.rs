@@ -11,17 +11,22 @@ use glium::glutin::event_loop::EventLoop;
 
 use wvr_com::{data::Message, server::OrderServer};
 
-use wvr::{start_wvr, Wvr};
+use wvr::command::Command;
+use wvr::osc::OscServer;
+use wvr::{start_wvr, start_wvr_headless, start_wvr_offline, Wvr};
 
 fn main() -> Result<()> {
     if let Err(err) = wvr::utils::init_wvr_data_directory() {
         eprintln!("{:?}", err);
     }
 
-    let (project_path, config) = wvr::utils::get_config()?;
+    let (project_path, config, extended_config, run_options) = wvr::utils::get_config()?;
 
     let play_state = Arc::new(AtomicBool::new(true));
-    let (order_sender, order_receiver) = channel();
+    let (order_sender, order_receiver) = channel::<Command>();
+    let app_order_sender = order_sender.clone();
+    let osc_order_sender = order_sender.clone();
+    let reload_order_sender = order_sender.clone();
     if config.server.enable {
         if let Ok(mut order_server) = OrderServer::new(&config.server) {
             let play_state = play_state.clone();
@@ -29,19 +34,89 @@ fn main() -> Result<()> {
             thread::spawn(move || {
                 while play_state.load(Ordering::Relaxed) {
                     if let Some(message) = order_server.next_order(None) {
-                        order_sender.send(message).unwrap();
+                        order_sender.send(message.into()).unwrap();
                     }
                 }
             });
         }
     } else {
-        order_sender.send(Message::Start)?;
+        order_sender.send(Message::Start.into())?;
     }
+
+    let mut osc_replier = None;
+    if extended_config.osc.enable {
+        match OscServer::new(&extended_config.osc) {
+            Ok(mut osc_server) => {
+                match osc_server.replier() {
+                    Ok(replier) => osc_replier = Some(replier),
+                    Err(e) => eprintln!("{:?}", e),
+                }
+                let play_state = play_state.clone();
+
+                thread::spawn(move || {
+                    while play_state.load(Ordering::Relaxed) {
+                        for command in osc_server.next_commands() {
+                            osc_order_sender.send(command).unwrap();
+                        }
+                    }
+                });
+            }
+            Err(e) => eprintln!("{:?}", e),
+        }
+    }
+
+    // Offline renders stick to the config they started with
+    if run_options.offline_render.is_none() {
+        if let Err(e) = wvr::reload::watch_config(&run_options.config_path, reload_order_sender) {
+            eprintln!("{:?}", e);
+        }
+    }
+
+    if run_options.headless {
+        let renderer = wvr::utils::build_headless_renderer(&config.view)?;
+
+        let mut app = Wvr::new(
+            &project_path,
+            config,
+            extended_config,
+            app_order_sender,
+            &renderer,
+        )
+        .context("Failed creating Wvr app")?;
+        app.set_config_path(run_options.config_path.clone());
+        app.set_config_overrides(run_options.config_overrides.clone());
+        if let Some(osc_replier) = osc_replier {
+            app.set_osc_replier(osc_replier);
+        }
+
+        let result = if let Some(offline_render) = run_options.offline_render {
+            start_wvr_offline(renderer, app, offline_render, order_receiver)
+        } else {
+            start_wvr_headless(renderer, app, run_options.frame_budget, order_receiver)
+        };
+
+        play_state.store(false, Ordering::Relaxed);
+
+        return result;
+    }
+
     let event_loop = EventLoop::new();
 
     let window = wvr::utils::build_window(&config.view, &event_loop)?;
 
-    let app = Wvr::new(&project_path, config, &window).context("Failed creating Wvr app")?;
+    let mut app = Wvr::new(
+        &project_path,
+        config,
+        extended_config,
+        app_order_sender,
+        &window,
+    )
+    .context("Failed creating Wvr app")?;
+    app.set_config_path(run_options.config_path);
+    app.set_config_overrides(run_options.config_overrides);
+    if let Some(osc_replier) = osc_replier {
+        app.set_osc_replier(osc_replier);
+    }
 
     start_wvr(window, app, event_loop, order_receiver);
 
@@ -0,0 +1,274 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+
+use glium::backend::Facade;
+use glium::texture::{MipmapsOption, RawImage2d, Texture2d, TextureAny, UncompressedFloatFormat};
+use glium::Rect;
+use image::{imageops, RgbaImage};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FramePosition {
+    Frame(usize),
+    Time(f64),
+    Beat(f64),
+}
+
+impl FramePosition {
+    pub fn is_reached(&self, frame_count: usize, time: f64, beat: f64) -> bool {
+        match self {
+            FramePosition::Frame(frame) => frame_count >= *frame,
+            FramePosition::Time(position) => time >= *position,
+            FramePosition::Beat(position) => beat >= *position,
+        }
+    }
+}
+
+impl FromStr for FramePosition {
+    type Err = anyhow::Error;
+
+    /// Parses positions such as `120` (frames), `4.5s` (seconds) or `16b` (beats)
+    fn from_str(position: &str) -> Result<Self> {
+        let position = position.trim();
+
+        if let Some(time) = position.strip_suffix('s') {
+            let time = time
+                .parse::<f64>()
+                .context(format!("Invalid time position {:?}", position))?;
+            Ok(FramePosition::Time(time))
+        } else if let Some(beat) = position.strip_suffix('b') {
+            let beat = beat
+                .parse::<f64>()
+                .context(format!("Invalid beat position {:?}", position))?;
+            Ok(FramePosition::Beat(beat))
+        } else {
+            let frame = position
+                .parse::<usize>()
+                .context(format!("Invalid frame position {:?}", position))?;
+            Ok(FramePosition::Frame(frame))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameFormat {
+    Png,
+    /// 32 bit float frames, keeping values above 1 and the precision of float stages
+    Exr,
+}
+
+impl FrameFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FrameFormat::Png => "png",
+            FrameFormat::Exr => "exr",
+        }
+    }
+
+    /// Builds the target the final stage renders into, in float for the formats keeping it
+    pub fn create_render_target(
+        &self,
+        display: &dyn Facade,
+        resolution: (usize, usize),
+    ) -> Result<Texture2d> {
+        let (width, height) = (resolution.0 as u32, resolution.1 as u32);
+
+        match self {
+            FrameFormat::Png => Texture2d::empty(display, width, height),
+            FrameFormat::Exr => Texture2d::empty_with_format(
+                display,
+                UncompressedFloatFormat::F32F32F32F32,
+                MipmapsOption::NoMipmap,
+                width,
+                height,
+            ),
+        }
+        .context("Failed to create the offscreen render target")
+    }
+}
+
+impl FromStr for FrameFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.to_lowercase().as_str() {
+            "png" => Ok(FrameFormat::Png),
+            "exr" => Ok(FrameFormat::Exr),
+            _ => bail!("Unsupported frame format {:?}, expected png or exr", format),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OfflineRenderConfig {
+    pub output_path: PathBuf,
+    pub from: FramePosition,
+    pub to: Option<FramePosition>,
+    /// Number of frames written before the render stops
+    pub frame_count: Option<usize>,
+    pub format: FrameFormat,
+}
+
+impl OfflineRenderConfig {
+    pub fn create_output_folder(&self) -> Result<()> {
+        if !self.output_path.exists() {
+            fs::create_dir_all(&self.output_path).context(format!(
+                "Could not create render output folder {:?}",
+                self.output_path
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_frame_path(&self, frame_index: usize) -> PathBuf {
+        self.output_path
+            .join(format!("{:06}.{:}", frame_index, self.format.extension()))
+    }
+}
+
+/// Frame read back from the render target, in the precision of its format
+pub enum Frame {
+    Rgba8(RgbaImage),
+    /// Float pixels, rows top first
+    RgbaF32 {
+        resolution: (usize, usize),
+        pixels: Vec<(f32, f32, f32, f32)>,
+    },
+}
+
+/// Joins rows read from an OpenGL texture, which stores the bottom row first
+fn rows_top_first<P>(rows: Vec<Vec<P>>) -> Vec<P> {
+    rows.into_iter().rev().flatten().collect()
+}
+
+pub fn read_frame(texture: &Texture2d, format: FrameFormat) -> Frame {
+    match format {
+        FrameFormat::Png => {
+            let raw_image: RawImage2d<u8> = texture.read();
+
+            let image = RgbaImage::from_raw(
+                raw_image.width,
+                raw_image.height,
+                raw_image.data.into_owned(),
+            )
+            .unwrap();
+
+            // OpenGL textures are stored bottom row first
+            Frame::Rgba8(imageops::flip_vertical(&image))
+        }
+        FrameFormat::Exr => {
+            let (width, height) = texture.dimensions();
+            // `Texture2d::read` only gives 8 bit pixels, the untyped texture reads any of them
+            let texture: &TextureAny = texture;
+            let rows: Vec<Vec<(f32, f32, f32, f32)>> = texture
+                .main_level()
+                .first_layer()
+                .into_image(None)
+                .unwrap()
+                .raw_read(&Rect {
+                    left: 0,
+                    bottom: 0,
+                    width,
+                    height,
+                });
+
+            Frame::RgbaF32 {
+                resolution: (width as usize, height as usize),
+                pixels: rows_top_first(rows),
+            }
+        }
+    }
+}
+
+pub fn write_frame(path: &Path, frame: &Frame) -> Result<()> {
+    match frame {
+        Frame::Rgba8(image) => image
+            .save(path)
+            .context(format!("Failed to write frame {:?}", path))?,
+        Frame::RgbaF32 {
+            resolution: (width, height),
+            pixels,
+        } => exr::prelude::write_rgba_file(path, *width, *height, |x, y| pixels[y * width + x])
+            .context(format!("Failed to write frame {:?}", path))?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_are_read_in_frames_seconds_or_beats() {
+        assert_eq!(
+            "120".parse::<FramePosition>().unwrap(),
+            FramePosition::Frame(120)
+        );
+        assert_eq!(
+            "4.5s".parse::<FramePosition>().unwrap(),
+            FramePosition::Time(4.5)
+        );
+        assert_eq!(
+            " 16b ".parse::<FramePosition>().unwrap(),
+            FramePosition::Beat(16.0)
+        );
+    }
+
+    #[test]
+    fn invalid_positions_are_refused() {
+        for position in &["", "abc", "1.5", "-3", "s", "4.5x", "2bs"] {
+            assert!(
+                position.parse::<FramePosition>().is_err(),
+                "{:?} was accepted",
+                position
+            );
+        }
+    }
+
+    #[test]
+    fn positions_are_reached_on_their_own_axis() {
+        assert!(FramePosition::Frame(10).is_reached(10, 0.0, 0.0));
+        assert!(!FramePosition::Frame(10).is_reached(9, 100.0, 100.0));
+        assert!(FramePosition::Time(2.0).is_reached(0, 2.5, 0.0));
+        assert!(!FramePosition::Time(2.0).is_reached(100, 1.5, 100.0));
+        assert!(FramePosition::Beat(8.0).is_reached(0, 0.0, 8.0));
+        assert!(!FramePosition::Beat(8.0).is_reached(100, 100.0, 7.9));
+    }
+
+    #[test]
+    fn frame_formats_are_read_from_their_name() {
+        assert_eq!("png".parse::<FrameFormat>().unwrap(), FrameFormat::Png);
+        assert_eq!("EXR".parse::<FrameFormat>().unwrap(), FrameFormat::Exr);
+        assert!("tiff".parse::<FrameFormat>().is_err());
+    }
+
+    #[test]
+    fn texture_rows_are_turned_top_first() {
+        assert_eq!(
+            rows_top_first(vec![vec![1, 2], vec![3, 4], vec![5, 6]]),
+            vec![5, 6, 3, 4, 1, 2]
+        );
+    }
+
+    #[test]
+    fn float_frames_are_written_as_exr() {
+        let frame_path =
+            std::env::temp_dir().join(format!("wvr-frame-{:}.exr", std::process::id()));
+        let frame = Frame::RgbaF32 {
+            resolution: (2, 1),
+            pixels: vec![(0.0, 0.5, 1.0, 1.0), (2.5, 0.0, 0.0, 0.5)],
+        };
+
+        let written = write_frame(&frame_path, &frame);
+        let content = fs::read(&frame_path);
+        let _ = fs::remove_file(&frame_path);
+
+        written.unwrap();
+        // Every OpenEXR file starts with the same magic number
+        assert_eq!(&content.unwrap()[..4], &[0x76, 0x2f, 0x31, 0x01]);
+    }
+}
@@ -0,0 +1,773 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_com::data::{Message, RenderStageUpdate, SetInfo};
+use wvr_data::types::DataHolder;
+
+use crate::audio::tempo::TempoTrackingUpdate;
+use crate::command::Command;
+use crate::config::SaveOrder;
+use crate::history::HistoryOrder;
+use crate::preset::PresetOrder;
+use crate::recording::{RecordOrder, RecordSource, RecordingUpdate};
+use crate::sync::TempoOrder;
+use crate::telemetry::{Query, QueryClient, TelemetryEvent};
+
+/// Time after which a waiting server gives the hand back to its caller
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OscConfig {
+    /// Address the server listens on, only reachable from this machine by default
+    pub ip: String,
+    pub port: u16,
+    pub enable: bool,
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            ip: "127.0.0.1".to_owned(),
+            port: 9000,
+            enable: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArgument {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Blob(Vec<u8>),
+    Bool(bool),
+    Nil,
+}
+
+impl OscArgument {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            OscArgument::Int(value) => Some(*value as f64),
+            OscArgument::Long(value) => Some(*value as f64),
+            OscArgument::Float(value) => Some(*value as f64),
+            OscArgument::Double(value) => Some(*value),
+            OscArgument::Bool(value) => Some(if *value { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            OscArgument::Bool(value) => Some(*value),
+            _ => self.as_f64().map(|value| value != 0.0),
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            OscArgument::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub arguments: Vec<OscArgument>,
+}
+
+struct OscReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> OscReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + length)
+            .context("Truncated OSC packet")?;
+
+        // Every OSC field is padded to a multiple of four bytes
+        self.position += (length + 3) & !3;
+
+        Ok(bytes)
+    }
+
+    fn read_word(&mut self) -> Result<[u8; 4]> {
+        let mut word = [0; 4];
+        word.copy_from_slice(self.read_bytes(4)?);
+        Ok(word)
+    }
+
+    fn read_double_word(&mut self) -> Result<[u8; 8]> {
+        let mut double_word = [0; 8];
+        double_word.copy_from_slice(self.read_bytes(8)?);
+        Ok(double_word)
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        let remaining = self.data.get(self.position..).unwrap_or_default();
+        let length = remaining
+            .iter()
+            .position(|byte| *byte == 0)
+            .context("Unterminated OSC string")?;
+
+        let string = std::str::from_utf8(&remaining[..length])
+            .context("Invalid OSC string")?
+            .to_owned();
+        self.read_bytes(length + 1)?;
+
+        Ok(string)
+    }
+
+    fn read_argument(&mut self, type_tag: char) -> Result<OscArgument> {
+        Ok(match type_tag {
+            'i' => OscArgument::Int(i32::from_be_bytes(self.read_word()?)),
+            'h' => OscArgument::Long(i64::from_be_bytes(self.read_double_word()?)),
+            'f' => OscArgument::Float(f32::from_be_bytes(self.read_word()?)),
+            'd' => OscArgument::Double(f64::from_be_bytes(self.read_double_word()?)),
+            's' | 'S' => OscArgument::String(self.read_string()?),
+            'b' => {
+                let length = i32::from_be_bytes(self.read_word()?).max(0) as usize;
+                OscArgument::Blob(self.read_bytes(length)?.to_vec())
+            }
+            'T' => OscArgument::Bool(true),
+            'F' => OscArgument::Bool(false),
+            'N' | 'I' => OscArgument::Nil,
+            _ => bail!("Unsupported OSC argument type {:?}", type_tag),
+        })
+    }
+}
+
+/// Decodes an OSC packet, flattening bundles into the messages they hold
+pub fn decode_packet(packet: &[u8]) -> Result<Vec<OscMessage>> {
+    let mut reader = OscReader::new(packet);
+
+    if packet.starts_with(b"#bundle\0") {
+        reader.read_bytes(8)?;
+        // Messages are applied as soon as they are received, whatever their time tag
+        reader.read_double_word()?;
+
+        let mut messages = Vec::new();
+        while !reader.is_empty() {
+            let length = i32::from_be_bytes(reader.read_word()?).max(0) as usize;
+            messages.extend(decode_packet(reader.read_bytes(length)?)?);
+        }
+
+        return Ok(messages);
+    }
+
+    let address = reader.read_string()?;
+    if !address.starts_with('/') {
+        bail!("Invalid OSC address {:?}", address);
+    }
+
+    let mut arguments = Vec::new();
+    if !reader.is_empty() {
+        let type_tags = reader.read_string()?;
+        for type_tag in type_tags.chars().skip_while(|type_tag| *type_tag == ',') {
+            arguments.push(reader.read_argument(type_tag)?);
+        }
+    }
+
+    Ok(vec![OscMessage { address, arguments }])
+}
+
+/// Appends bytes to an OSC packet, padded with zeros to a multiple of four bytes
+fn write_padded(packet: &mut Vec<u8>, bytes: &[u8]) {
+    packet.extend_from_slice(bytes);
+    packet.resize((packet.len() + 3) & !3, 0);
+}
+
+fn write_string(packet: &mut Vec<u8>, string: &str) {
+    let mut bytes = string.as_bytes().to_vec();
+    bytes.push(0);
+    write_padded(packet, &bytes);
+}
+
+/// Encodes an OSC message the way `decode_packet` reads it
+pub fn encode_message(message: &OscMessage) -> Vec<u8> {
+    let mut type_tags = ",".to_owned();
+    let mut arguments = Vec::new();
+    for argument in &message.arguments {
+        match argument {
+            OscArgument::Int(value) => {
+                type_tags.push('i');
+                arguments.extend_from_slice(&value.to_be_bytes());
+            }
+            OscArgument::Long(value) => {
+                type_tags.push('h');
+                arguments.extend_from_slice(&value.to_be_bytes());
+            }
+            OscArgument::Float(value) => {
+                type_tags.push('f');
+                arguments.extend_from_slice(&value.to_be_bytes());
+            }
+            OscArgument::Double(value) => {
+                type_tags.push('d');
+                arguments.extend_from_slice(&value.to_be_bytes());
+            }
+            OscArgument::String(value) => {
+                type_tags.push('s');
+                write_string(&mut arguments, value);
+            }
+            OscArgument::Blob(value) => {
+                type_tags.push('b');
+                arguments.extend_from_slice(&(value.len() as i32).to_be_bytes());
+                write_padded(&mut arguments, value);
+            }
+            OscArgument::Bool(true) => type_tags.push('T'),
+            OscArgument::Bool(false) => type_tags.push('F'),
+            OscArgument::Nil => type_tags.push('N'),
+        }
+    }
+
+    let mut packet = Vec::new();
+    write_string(&mut packet, &message.address);
+    write_string(&mut packet, &type_tags);
+    packet.extend(arguments);
+    packet
+}
+
+/// Reads a render size, which has to be at least one pixel
+fn size_from_osc(argument: &OscArgument) -> Option<usize> {
+    let size = argument.as_f64()?;
+    if size >= 1.0 {
+        Some(size as usize)
+    } else {
+        None
+    }
+}
+
+/// Builds a variable value from the numeric arguments of a message, several of them making a vector
+fn arguments_to_data(arguments: &[OscArgument]) -> Option<DataHolder> {
+    match arguments {
+        [OscArgument::Int(value)] => Some(DataHolder::Int(*value)),
+        [OscArgument::Bool(value)] => Some(DataHolder::Bool(*value)),
+        [OscArgument::String(value)] => Some(DataHolder::String(value.clone())),
+        [value] => value.as_f64().map(|value| DataHolder::Float(value as f32)),
+        _ => {
+            let values = arguments
+                .iter()
+                .map(|argument| argument.as_f64().map(|value| value as f32))
+                .collect::<Option<Vec<f32>>>()?;
+
+            match values.as_slice() {
+                [x, y] => Some(DataHolder::Vec2([*x, *y])),
+                [x, y, z] => Some(DataHolder::Vec3([*x, *y, *z])),
+                [x, y, z, w] => Some(DataHolder::Vec4([*x, *y, *z, *w])),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Reads a setting given by the name of its variant, as written in the project config
+fn variant_from_osc<T: DeserializeOwned>(argument: &OscArgument) -> Option<T> {
+    serde_json::from_value(serde_json::Value::String(argument.as_str()?.to_owned())).ok()
+}
+
+fn recording_update_from_osc(path: &[&str], arguments: &[OscArgument]) -> Option<RecordingUpdate> {
+    let first_argument = arguments.first();
+
+    let update = match path {
+        ["path_template"] => RecordingUpdate::PathTemplate(first_argument?.as_str()?.to_owned()),
+        ["container"] => RecordingUpdate::Container(variant_from_osc(first_argument?)?),
+        ["codec"] => RecordingUpdate::Codec(variant_from_osc(first_argument?)?),
+        ["format"] => RecordingUpdate::Format(
+            variant_from_osc(first_argument?)?,
+            variant_from_osc(arguments.get(1)?)?,
+        ),
+        ["bitrate"] => RecordingUpdate::Bitrate(first_argument?.as_f64()?.max(0.0) as u32),
+        ["alpha"] => RecordingUpdate::Alpha(first_argument?.as_bool()?),
+        ["on_resolution_change"] => {
+            RecordingUpdate::OnResolutionChange(variant_from_osc(first_argument?)?)
+        }
+        // `final` stands for the final output, any other name for the stage bearing it
+        ["sources"] => RecordingUpdate::Sources(
+            arguments
+                .iter()
+                .map(|argument| match argument.as_str()? {
+                    "final" => Some(RecordSource::Final),
+                    stage_name => Some(RecordSource::Stage(stage_name.to_owned())),
+                })
+                .collect::<Option<Vec<RecordSource>>>()?,
+        ),
+        _ => return None,
+    };
+
+    Some(update)
+}
+
+fn stage_update_from_osc(path: &[&str], arguments: &[OscArgument]) -> Option<RenderStageUpdate> {
+    match path {
+        ["variable", variable_name] => Some(RenderStageUpdate::Variable(
+            (*variable_name).to_owned(),
+            arguments_to_data(arguments)?,
+        )),
+        ["filter"] => Some(RenderStageUpdate::Filter(
+            arguments.first()?.as_str()?.to_owned(),
+        )),
+        ["name"] => Some(RenderStageUpdate::Name(
+            arguments.first()?.as_str()?.to_owned(),
+        )),
+        _ => None,
+    }
+}
+
+/// Maps an OSC message to the order it stands for, `None` meaning the address or its arguments are not understood.
+/// Queries are answered to `sender`.
+pub fn command_from_osc(message: &OscMessage, sender: SocketAddr) -> Option<Command> {
+    let path = message
+        .address
+        .strip_prefix("/wvr/")?
+        .split('/')
+        .collect::<Vec<&str>>();
+    let arguments = &message.arguments;
+    let first_argument = arguments.first();
+
+    let command = match path.as_slice() {
+        ["start"] => Message::Start.into(),
+        ["pause"] => Message::Pause.into(),
+        ["stop"] => Message::Stop.into(),
+
+        ["bpm"] => Message::Set(SetInfo::Bpm(first_argument?.as_f64()?)).into(),
+        ["width"] => Message::Set(SetInfo::Width(size_from_osc(first_argument?)?)).into(),
+        ["height"] => Message::Set(SetInfo::Height(size_from_osc(first_argument?)?)).into(),
+        ["target_fps"] => Message::Set(SetInfo::TargetFps(first_argument?.as_f64()?)).into(),
+        ["dynamic_resolution"] => {
+            Message::Set(SetInfo::DynamicResolution(first_argument?.as_bool()?)).into()
+        }
+        ["vsync"] => Message::Set(SetInfo::VSync(first_argument?.as_bool()?)).into(),
+        ["fullscreen"] => Message::Set(SetInfo::Fullscreen(first_argument?.as_bool()?)).into(),
+        ["locked_speed"] => Message::Set(SetInfo::LockedSpeed(first_argument?.as_bool()?)).into(),
+
+        ["stage", stage_index, stage_path @ ..] => Message::UpdateRenderStage(
+            stage_index.parse::<usize>().ok()?,
+            stage_update_from_osc(stage_path, arguments)?,
+        )
+        .into(),
+        ["final", stage_path @ ..] => {
+            Message::UpdateFinalStage(stage_update_from_osc(stage_path, arguments)?).into()
+        }
+
+        ["tempo", "tap"] => Command::Tempo(TempoOrder::Tap),
+        ["tempo", "nudge"] => Command::Tempo(TempoOrder::Nudge(first_argument?.as_f64()?)),
+        ["tempo", "resync"] => Command::Tempo(TempoOrder::ResyncToBar),
+        ["tempo", "restart"] => Command::Tempo(TempoOrder::Restart),
+        ["tempo", "beats_per_bar"] => Command::Tempo(TempoOrder::SetBeatsPerBar(
+            first_argument?.as_f64()?.max(1.0) as usize,
+        )),
+
+        ["tempo", "tracking", "enable"] => {
+            Command::TempoTracking(TempoTrackingUpdate::Enable(first_argument?.as_bool()?))
+        }
+        ["tempo", "tracking", "lock"] => {
+            Command::TempoTracking(TempoTrackingUpdate::Lock(first_argument?.as_bool()?))
+        }
+        ["tempo", "tracking", "smoothing"] => {
+            Command::TempoTracking(TempoTrackingUpdate::Smoothing(first_argument?.as_f64()?))
+        }
+
+        ["record", "start"] => Command::Record(RecordOrder::Start),
+        ["record", "stop"] => Command::Record(RecordOrder::Stop),
+        ["record", "split"] => Command::Record(RecordOrder::Split),
+        ["recording", recording_path @ ..] => {
+            Command::Recording(recording_update_from_osc(recording_path, arguments)?)
+        }
+
+        ["undo"] => Command::History(HistoryOrder::Undo),
+        ["redo"] => Command::History(HistoryOrder::Redo),
+
+        ["preset", "store"] => {
+            Command::Preset(PresetOrder::Store(first_argument?.as_str()?.to_owned()))
+        }
+        ["preset", "recall"] => Command::Preset(PresetOrder::Recall(
+            first_argument?.as_str()?.to_owned(),
+            match arguments.get(1) {
+                Some(duration) => duration.as_f64()?,
+                None => 0.0,
+            },
+        )),
+        ["preset", "delete"] => {
+            Command::Preset(PresetOrder::Delete(first_argument?.as_str()?.to_owned()))
+        }
+
+        ["reload"] => Command::ReloadConfig,
+        ["save"] => Command::Save(SaveOrder::Save),
+        ["save_as"] => Command::Save(SaveOrder::SaveAs(PathBuf::from(first_argument?.as_str()?))),
+
+        ["query", "project"] => Command::Query {
+            query: Query::Project,
+            client: QueryClient::Osc(sender),
+        },
+        ["query", "state"] => Command::Query {
+            query: Query::State,
+            client: QueryClient::Osc(sender),
+        },
+
+        _ => return None,
+    };
+
+    Some(command)
+}
+
+/// Receives OSC messages over UDP and turns them into orders for `Wvr`
+pub struct OscServer {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl OscServer {
+    pub fn new(osc_config: &OscConfig) -> Result<Self> {
+        let socket =
+            UdpSocket::bind((osc_config.ip.as_str(), osc_config.port)).context(format!(
+                "Failed to start the OSC server on {:}:{:}",
+                osc_config.ip, osc_config.port
+            ))?;
+        socket.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+
+        Ok(Self {
+            socket,
+            buffer: vec![0; 65536],
+        })
+    }
+
+    /// Replier answering queries from the socket of the server
+    pub fn replier(&self) -> Result<OscReplier> {
+        Ok(OscReplier {
+            socket: self
+                .socket
+                .try_clone()
+                .context("Failed to share the OSC server socket")?,
+        })
+    }
+
+    /// Waits a short while for a packet, returning the orders it holds
+    pub fn next_commands(&mut self) -> Vec<Command> {
+        let (length, sender) = match self.socket.recv_from(&mut self.buffer) {
+            Ok(received) => received,
+            Err(_) => return Vec::new(),
+        };
+
+        let messages = match decode_packet(&self.buffer[..length]) {
+            Ok(messages) => messages,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                return Vec::new();
+            }
+        };
+
+        messages
+            .iter()
+            .filter_map(|message| {
+                let command = command_from_osc(message, sender);
+                if command.is_none() {
+                    eprintln!(
+                        "Ignoring OSC message {:} {:?}",
+                        message.address, message.arguments
+                    );
+                }
+
+                command
+            })
+            .collect()
+    }
+}
+
+/// Answers the queries received by an OSC server, from the port they were sent to
+pub struct OscReplier {
+    socket: UdpSocket,
+}
+
+impl OscReplier {
+    /// Sends the answer to a query as a JSON string, on the address of the query
+    pub fn reply(&self, target: SocketAddr, query: Query, answer: &TelemetryEvent) -> Result<()> {
+        let address = match query {
+            Query::Project => "/wvr/query/project",
+            Query::State => "/wvr/query/state",
+        };
+        let answer = serde_json::to_string(answer).context("Failed to serialize a query answer")?;
+
+        let packet = encode_message(&OscMessage {
+            address: address.to_owned(),
+            arguments: vec![OscArgument::String(answer)],
+        });
+        self.socket
+            .send_to(&packet, target)
+            .context(format!("Failed to answer {:} to {:}", address, target))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::recording::{Codec, Container};
+
+    /// Pads bytes with zeros to a multiple of four bytes, as every OSC field
+    fn padded(mut bytes: Vec<u8>) -> Vec<u8> {
+        bytes.resize((bytes.len() + 3) & !3, 0);
+        bytes
+    }
+
+    fn osc_string(string: &str) -> Vec<u8> {
+        let mut bytes = string.as_bytes().to_vec();
+        bytes.push(0);
+        padded(bytes)
+    }
+
+    fn osc_packet(address: &str, type_tags: &str, arguments: &[u8]) -> Vec<u8> {
+        let mut packet = osc_string(address);
+        packet.extend(osc_string(type_tags));
+        packet.extend_from_slice(arguments);
+        packet
+    }
+
+    fn osc_message(address: &str, arguments: Vec<OscArgument>) -> OscMessage {
+        OscMessage {
+            address: address.to_owned(),
+            arguments,
+        }
+    }
+
+    fn sender() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 9001))
+    }
+
+    fn command_from(message: &OscMessage) -> Option<Command> {
+        command_from_osc(message, sender())
+    }
+
+    /// Message a command applies to the project, if it is one
+    fn project_message(command: Option<Command>) -> Option<Message> {
+        match command? {
+            Command::Message(message) => Some(message),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn arguments_are_decoded_by_type_tag() {
+        let mut arguments = 3i32.to_be_bytes().to_vec();
+        arguments.extend_from_slice(&0.5f32.to_be_bytes());
+        arguments.extend(osc_string("blur"));
+        arguments.extend_from_slice(&(-2i64).to_be_bytes());
+        let packet = osc_packet("/wvr/test", ",ifshTN", &arguments);
+
+        assert_eq!(
+            decode_packet(&packet).unwrap(),
+            vec![osc_message(
+                "/wvr/test",
+                vec![
+                    OscArgument::Int(3),
+                    OscArgument::Float(0.5),
+                    OscArgument::String("blur".to_owned()),
+                    OscArgument::Long(-2),
+                    OscArgument::Bool(true),
+                    OscArgument::Nil,
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn encoded_messages_are_decoded_back() {
+        let message = osc_message(
+            "/wvr/query/state",
+            vec![
+                OscArgument::Int(-3),
+                OscArgument::Long(1 << 40),
+                OscArgument::Float(0.5),
+                OscArgument::Double(0.25),
+                OscArgument::String("{\"type\": \"State\"}".to_owned()),
+                OscArgument::Blob(vec![1, 2, 3, 4, 5]),
+                OscArgument::Bool(true),
+                OscArgument::Bool(false),
+                OscArgument::Nil,
+            ],
+        );
+
+        let packet = encode_message(&message);
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(decode_packet(&packet).unwrap(), vec![message]);
+
+        assert_eq!(
+            encode_message(&osc_message("/wvr/bpm", vec![OscArgument::Float(128.0)])),
+            osc_packet("/wvr/bpm", ",f", &128f32.to_be_bytes())
+        );
+    }
+
+    #[test]
+    fn messages_without_type_tags_have_no_arguments() {
+        assert_eq!(
+            decode_packet(&osc_string("/wvr/start")).unwrap(),
+            vec![osc_message("/wvr/start", Vec::new())]
+        );
+    }
+
+    #[test]
+    fn bundles_are_flattened() {
+        let first_message = osc_packet("/wvr/bpm", ",f", &128f32.to_be_bytes());
+        let second_message = osc_string("/wvr/start");
+
+        let mut packet = b"#bundle\0".to_vec();
+        packet.extend_from_slice(&1u64.to_be_bytes());
+        for message in &[first_message, second_message] {
+            packet.extend_from_slice(&(message.len() as i32).to_be_bytes());
+            packet.extend_from_slice(message);
+        }
+
+        assert_eq!(
+            decode_packet(&packet).unwrap(),
+            vec![
+                osc_message("/wvr/bpm", vec![OscArgument::Float(128.0)]),
+                osc_message("/wvr/start", Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_packets_are_refused() {
+        assert!(decode_packet(&osc_packet("/wvr/bpm", ",f", &[0, 0])).is_err());
+        assert!(decode_packet(&osc_packet("/wvr/bpm", ",x", &[0; 4])).is_err());
+        assert!(decode_packet(&osc_string("wvr/start")).is_err());
+        assert!(decode_packet(b"/wvr").is_err());
+    }
+
+    #[test]
+    fn settings_take_any_numeric_argument() {
+        assert_eq!(
+            project_message(command_from(&osc_message(
+                "/wvr/bpm",
+                vec![OscArgument::Float(128.0)]
+            ))),
+            Some(Message::Set(SetInfo::Bpm(128.0)))
+        );
+        assert_eq!(
+            project_message(command_from(&osc_message(
+                "/wvr/width",
+                vec![OscArgument::Int(1280)]
+            ))),
+            Some(Message::Set(SetInfo::Width(1280)))
+        );
+    }
+
+    #[test]
+    fn sizes_below_one_pixel_are_refused() {
+        for size in &[0.0, -1.0, 0.5, f64::NAN] {
+            assert!(
+                command_from(&osc_message("/wvr/width", vec![OscArgument::Double(*size)]))
+                    .is_none()
+            );
+            assert!(command_from(&osc_message(
+                "/wvr/height",
+                vec![OscArgument::Double(*size)]
+            ))
+            .is_none());
+        }
+        assert!(command_from(&osc_message("/wvr/height", vec![OscArgument::Int(-720)])).is_none());
+    }
+
+    #[test]
+    fn queries_are_answered_to_their_sender() {
+        assert!(matches!(
+            command_from(&osc_message("/wvr/query/project", Vec::new())),
+            Some(Command::Query {
+                query: Query::Project,
+                client: QueryClient::Osc(address),
+            }) if address == sender()
+        ));
+    }
+
+    #[test]
+    fn stage_variables_are_built_from_their_arguments() {
+        assert_eq!(
+            project_message(command_from(&osc_message(
+                "/wvr/stage/1/variable/offset",
+                vec![OscArgument::Float(0.25), OscArgument::Int(1)]
+            ))),
+            Some(Message::UpdateRenderStage(
+                1,
+                RenderStageUpdate::Variable("offset".to_owned(), DataHolder::Vec2([0.25, 1.0]))
+            ))
+        );
+        assert_eq!(
+            project_message(command_from(&osc_message(
+                "/wvr/final/variable/enabled",
+                vec![OscArgument::Bool(true)]
+            ))),
+            Some(Message::UpdateFinalStage(RenderStageUpdate::Variable(
+                "enabled".to_owned(),
+                DataHolder::Bool(true)
+            )))
+        );
+    }
+
+    #[test]
+    fn orders_are_mapped_to_their_command() {
+        assert!(matches!(
+            command_from(&osc_message("/wvr/tempo/tap", Vec::new())),
+            Some(Command::Tempo(TempoOrder::Tap))
+        ));
+        assert!(matches!(
+            command_from(&osc_message(
+                "/wvr/recording/format",
+                vec![
+                    OscArgument::String("Mp4".to_owned()),
+                    OscArgument::String("H264".to_owned())
+                ]
+            )),
+            Some(Command::Recording(RecordingUpdate::Format(
+                Container::Mp4,
+                Codec::H264
+            )))
+        ));
+        assert!(matches!(
+            command_from(&osc_message(
+                "/wvr/preset/recall",
+                vec![OscArgument::String("intro".to_owned())]
+            )),
+            Some(Command::Preset(PresetOrder::Recall(preset_name, duration)))
+                if preset_name == "intro" && duration.abs() < f64::EPSILON
+        ));
+    }
+
+    #[test]
+    fn unknown_addresses_and_missing_arguments_are_ignored() {
+        assert!(command_from(&osc_message("/wvr/unknown", Vec::new())).is_none());
+        assert!(command_from(&osc_message("/other/start", Vec::new())).is_none());
+        assert!(command_from(&osc_message("/wvr/bpm", Vec::new())).is_none());
+        assert!(command_from(&osc_message(
+            "/wvr/bpm",
+            vec![OscArgument::String("fast".to_owned())]
+        ))
+        .is_none());
+        assert!(command_from(&osc_message(
+            "/wvr/recording/container",
+            vec![OscArgument::String("Avi".to_owned())]
+        ))
+        .is_none());
+    }
+}
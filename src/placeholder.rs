@@ -0,0 +1,221 @@
+use anyhow::Result;
+
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_data::config::input::InputConfig;
+use wvr_data::types::{DataHolder, InputProvider};
+
+/// Side of the checkerboard squares, in pixels
+const CHECKER_SIZE: usize = 32;
+/// Resolution of the placeholders standing for inputs without one, such as MIDI controllers
+const DEFAULT_RESOLUTION: (usize, usize) = (64, 64);
+
+/// What becomes of an input that fails to load
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InputFallback {
+    /// The project does not start
+    Abort,
+    /// The input is replaced by a grey checkerboard, which stands out on screen
+    Checkerboard,
+    /// The input is replaced by a black texture
+    Black,
+}
+
+impl Default for InputFallback {
+    fn default() -> Self {
+        InputFallback::Checkerboard
+    }
+}
+
+/// Resolution an input config asks for
+fn config_resolution(input_config: &InputConfig) -> (usize, usize) {
+    match input_config {
+        InputConfig::Video { width, height, .. }
+        | InputConfig::Picture { width, height, .. }
+        | InputConfig::Cam { width, height, .. } => {
+            ((*width as usize).max(1), (*height as usize).max(1))
+        }
+        InputConfig::Midi { .. } => DEFAULT_RESOLUTION,
+    }
+}
+
+/// Texture standing in for an input that could not be loaded, so that the stages reading it
+/// still render until the input is fixed
+pub struct PlaceholderProvider {
+    name: String,
+    fallback: InputFallback,
+    resolution: (usize, usize),
+    texture: DataHolder,
+    /// Whether the texture changed since it was last handed out, the renderer keeping it otherwise
+    texture_changed: bool,
+}
+
+impl PlaceholderProvider {
+    pub fn new(name: &str, input_config: &InputConfig, fallback: InputFallback) -> Self {
+        let resolution = config_resolution(input_config);
+
+        Self {
+            name: name.to_owned(),
+            fallback,
+            resolution,
+            texture: Self::build_texture(fallback, resolution),
+            texture_changed: true,
+        }
+    }
+
+    fn build_texture(fallback: InputFallback, (width, height): (usize, usize)) -> DataHolder {
+        let mut data = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let value = match fallback {
+                    InputFallback::Checkerboard
+                        if (x / CHECKER_SIZE + y / CHECKER_SIZE) % 2 == 0 =>
+                    {
+                        160
+                    }
+                    InputFallback::Checkerboard => 96,
+                    _ => 0,
+                };
+                data.extend_from_slice(&[value, value, value]);
+            }
+        }
+
+        DataHolder::Texture(((width as u32, height as u32), data))
+    }
+
+    fn set_resolution(&mut self, resolution: (usize, usize)) {
+        self.resolution = (resolution.0.max(1), resolution.1.max(1));
+        self.texture = Self::build_texture(self.fallback, self.resolution);
+        self.texture_changed = true;
+    }
+}
+
+/// Placeholder standing in for an input that failed to load, `None` meaning the project
+/// must not go on without the input
+pub fn placeholder_for(
+    input_name: &str,
+    input_config: &InputConfig,
+    fallback: InputFallback,
+) -> Option<PlaceholderProvider> {
+    match fallback {
+        InputFallback::Abort => None,
+        _ => Some(PlaceholderProvider::new(input_name, input_config, fallback)),
+    }
+}
+
+impl InputProvider for PlaceholderProvider {
+    fn set_name(&mut self, name: &str) {
+        self.name = name.to_owned();
+        self.texture_changed = true;
+    }
+
+    fn provides(&self) -> Vec<String> {
+        vec![self.name.clone()]
+    }
+
+    fn set_property(&mut self, property: &str, value: &DataHolder) {
+        match (property, value) {
+            ("width", DataHolder::Int(width)) => {
+                self.set_resolution((*width as usize, self.resolution.1))
+            }
+            ("height", DataHolder::Int(height)) => {
+                self.set_resolution((self.resolution.0, *height as usize))
+            }
+            _ => (),
+        }
+    }
+
+    fn set_time(&mut self, _time: f64, _sync: bool) {}
+
+    fn get(&mut self, uniform_name: &str, invalidate: bool) -> Option<DataHolder> {
+        if uniform_name != self.name || !self.texture_changed {
+            return None;
+        }
+
+        if invalidate {
+            self.texture_changed = false;
+        }
+
+        Some(self.texture.clone())
+    }
+
+    fn play(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midi_input() -> InputConfig {
+        InputConfig::Midi {
+            name: "nanoKONTROL".to_owned(),
+        }
+    }
+
+    /// Resolution and pixels of the texture a placeholder hands out
+    fn texture(placeholder: &mut PlaceholderProvider, name: &str) -> ((u32, u32), Vec<u8>) {
+        match placeholder.get(name, true) {
+            Some(DataHolder::Texture(texture)) => texture,
+            _ => panic!("No texture for {:?}", name),
+        }
+    }
+
+    #[test]
+    fn failed_inputs_are_left_out_under_abort() {
+        assert!(placeholder_for("controller", &midi_input(), InputFallback::Abort).is_none());
+    }
+
+    #[test]
+    fn checkerboards_alternate_every_square() {
+        let mut placeholder =
+            placeholder_for("controller", &midi_input(), InputFallback::Checkerboard).unwrap();
+
+        let ((width, height), data) = texture(&mut placeholder, "controller");
+        assert_eq!((width, height), (64, 64));
+        assert_eq!(data.len(), 64 * 64 * 3);
+
+        let pixel = |x: usize, y: usize| data[(y * 64 + x) * 3];
+        assert_eq!(pixel(0, 0), 160);
+        assert_eq!(pixel(CHECKER_SIZE, 0), 96);
+        assert_eq!(pixel(0, CHECKER_SIZE), 96);
+        assert_eq!(pixel(CHECKER_SIZE, CHECKER_SIZE), 160);
+    }
+
+    #[test]
+    fn black_placeholders_are_black() {
+        let mut placeholder =
+            placeholder_for("controller", &midi_input(), InputFallback::Black).unwrap();
+
+        let (_, data) = texture(&mut placeholder, "controller");
+        assert!(data.iter().all(|value| *value == 0));
+    }
+
+    #[test]
+    fn textures_are_only_handed_out_when_they_change() {
+        let mut placeholder =
+            placeholder_for("controller", &midi_input(), InputFallback::Black).unwrap();
+
+        assert!(placeholder.get("other", true).is_none());
+        assert!(placeholder.get("controller", false).is_some());
+        assert!(placeholder.get("controller", true).is_some());
+        assert!(placeholder.get("controller", true).is_none());
+
+        placeholder.set_property("width", &DataHolder::Int(16));
+        let ((width, height), _) = texture(&mut placeholder, "controller");
+        assert_eq!((width, height), (16, 64));
+
+        placeholder.set_name("pads");
+        assert_eq!(placeholder.provides(), vec!["pads".to_owned()]);
+        assert!(placeholder.get("pads", true).is_some());
+    }
+}
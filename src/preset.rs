@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_com::data::{Message, RenderStageUpdate};
+use wvr_data::config::project::ProjectConfig;
+use wvr_data::config::rendering::RenderStageConfig;
+use wvr_data::types::DataHolder;
+
+/// Variable values of a look, restored all at once
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    /// Variables of the render stages, by stage name so that presets survive stage moves
+    pub render_chain: HashMap<String, HashMap<String, DataHolder>>,
+    pub final_stage: HashMap<String, DataHolder>,
+    /// Project variables
+    pub variables: HashMap<String, DataHolder>,
+}
+
+fn stage_variables(render_stage_config: &RenderStageConfig) -> HashMap<String, DataHolder> {
+    render_stage_config
+        .variables
+        .iter()
+        .map(|(variable_name, (variable_value, _))| (variable_name.clone(), variable_value.clone()))
+        .collect()
+}
+
+impl Preset {
+    /// Captures the configured variable values of a project, automations left aside
+    pub fn capture(project_config: &ProjectConfig) -> Self {
+        Self {
+            render_chain: project_config
+                .render_chain
+                .iter()
+                .map(|render_stage_config| {
+                    (
+                        render_stage_config.name.clone(),
+                        stage_variables(render_stage_config),
+                    )
+                })
+                .collect(),
+            final_stage: stage_variables(&project_config.final_stage),
+            variables: project_config
+                .variables
+                .iter()
+                .map(|(variable_name, (variable_value, _))| {
+                    (variable_name.clone(), variable_value.clone())
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum PresetOrder {
+    /// Captures the current look under the given name, replacing any preset of the same name
+    Store(String),
+    /// Moves to a preset over the given number of beats
+    Recall(String, f64),
+    Delete(String),
+}
+
+/// Value of a variable part way between two others, non numeric values switching at once
+fn interpolate(from: &DataHolder, to: &DataHolder, progress: f64) -> DataHolder {
+    let progress = progress as f32;
+    let mix = |from: f32, to: f32| from + (to - from) * progress;
+
+    match (from, to) {
+        (DataHolder::Int(from), DataHolder::Int(to)) => {
+            DataHolder::Int(mix(*from as f32, *to as f32).round() as i32)
+        }
+        (DataHolder::Float(from), DataHolder::Float(to)) => DataHolder::Float(mix(*from, *to)),
+        (DataHolder::Vec2(from), DataHolder::Vec2(to)) => {
+            DataHolder::Vec2([mix(from[0], to[0]), mix(from[1], to[1])])
+        }
+        (DataHolder::Vec3(from), DataHolder::Vec3(to)) => DataHolder::Vec3([
+            mix(from[0], to[0]),
+            mix(from[1], to[1]),
+            mix(from[2], to[2]),
+        ]),
+        (DataHolder::Vec4(from), DataHolder::Vec4(to)) => DataHolder::Vec4([
+            mix(from[0], to[0]),
+            mix(from[1], to[1]),
+            mix(from[2], to[2]),
+            mix(from[3], to[3]),
+        ]),
+        _ => to.clone(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StageTarget {
+    /// Render stage found by name, wherever it sits in the chain when the values are applied
+    RenderStage(String),
+    FinalStage,
+}
+
+#[derive(Debug, Clone)]
+struct Transition {
+    variable_name: String,
+    from: DataHolder,
+    to: DataHolder,
+}
+
+impl Transition {
+    fn value_at(&self, progress: f64) -> DataHolder {
+        interpolate(&self.from, &self.to, progress)
+    }
+}
+
+/// Moves the variables of a project from their current values to the ones of a preset,
+/// following the beat
+#[derive(Debug, Clone)]
+pub struct Crossfade {
+    start_beat: f64,
+    duration: f64,
+
+    stage_transitions: Vec<(StageTarget, Transition)>,
+    variable_transitions: Vec<Transition>,
+}
+
+fn stage_variable_transitions(
+    render_stage_config: &RenderStageConfig,
+    preset_variables: &HashMap<String, DataHolder>,
+) -> Vec<Transition> {
+    preset_variables
+        .iter()
+        .filter_map(|(variable_name, to)| {
+            let (from, _) = render_stage_config.variables.get(variable_name)?;
+
+            Some(Transition {
+                variable_name: variable_name.clone(),
+                from: from.clone(),
+                to: to.clone(),
+            })
+        })
+        .collect()
+}
+
+impl Crossfade {
+    /// Plans a crossfade from the values of `project_config`, ignoring the stages and variables
+    /// the project does not have anymore
+    pub fn new(
+        preset: &Preset,
+        project_config: &ProjectConfig,
+        start_beat: f64,
+        duration: f64,
+    ) -> Self {
+        let mut stage_transitions = Vec::new();
+
+        for render_stage_config in &project_config.render_chain {
+            if let Some(preset_variables) = preset.render_chain.get(&render_stage_config.name) {
+                let target = StageTarget::RenderStage(render_stage_config.name.clone());
+                stage_transitions.extend(
+                    stage_variable_transitions(render_stage_config, preset_variables)
+                        .into_iter()
+                        .map(|transition| (target.clone(), transition)),
+                );
+            }
+        }
+        stage_transitions.extend(
+            stage_variable_transitions(&project_config.final_stage, &preset.final_stage)
+                .into_iter()
+                .map(|transition| (StageTarget::FinalStage, transition)),
+        );
+
+        let variable_transitions = preset
+            .variables
+            .iter()
+            .filter_map(|(variable_name, to)| {
+                let (from, _) = project_config.variables.get(variable_name)?;
+
+                Some(Transition {
+                    variable_name: variable_name.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                })
+            })
+            .collect();
+
+        Self {
+            start_beat,
+            duration: duration.max(0.0),
+
+            stage_transitions,
+            variable_transitions,
+        }
+    }
+
+    fn progress(&self, beat: f64) -> f64 {
+        if self.duration > 0.0 {
+            ((beat - self.start_beat) / self.duration).clamp(0.0, 1.0)
+        } else {
+            1.0
+        }
+    }
+
+    pub fn is_finished(&self, beat: f64) -> bool {
+        self.progress(beat) >= 1.0
+    }
+
+    /// Messages setting the stage variables to their value at the given beat
+    pub fn stage_messages(&self, project_config: &ProjectConfig, beat: f64) -> Vec<Message> {
+        let progress = self.progress(beat);
+
+        self.stage_transitions
+            .iter()
+            .filter_map(|(target, transition)| {
+                let update = RenderStageUpdate::Variable(
+                    transition.variable_name.clone(),
+                    transition.value_at(progress),
+                );
+
+                match target {
+                    StageTarget::RenderStage(stage_name) => {
+                        let render_stage_index =
+                            project_config
+                                .render_chain
+                                .iter()
+                                .position(|render_stage_config| {
+                                    &render_stage_config.name == stage_name
+                                })?;
+
+                        Some(Message::UpdateRenderStage(render_stage_index, update))
+                    }
+                    StageTarget::FinalStage => Some(Message::UpdateFinalStage(update)),
+                }
+            })
+            .collect()
+    }
+
+    /// Values of the project variables at the given beat
+    pub fn variable_values(&self, beat: f64) -> Vec<(String, DataHolder)> {
+        let progress = self.progress(beat);
+
+        self.variable_transitions
+            .iter()
+            .map(|transition| {
+                (
+                    transition.variable_name.clone(),
+                    transition.value_at(progress),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition(variable_name: &str, from: DataHolder, to: DataHolder) -> Transition {
+        Transition {
+            variable_name: variable_name.to_owned(),
+            from,
+            to,
+        }
+    }
+
+    /// Crossfade of two project variables starting at beat 8
+    fn crossfade(duration: f64) -> Crossfade {
+        Crossfade {
+            start_beat: 8.0,
+            duration,
+
+            stage_transitions: Vec::new(),
+            variable_transitions: vec![
+                transition("level", DataHolder::Float(0.0), DataHolder::Float(1.0)),
+                transition(
+                    "palette",
+                    DataHolder::String("warm".to_owned()),
+                    DataHolder::String("cold".to_owned()),
+                ),
+            ],
+        }
+    }
+
+    fn values(level: f32, palette: &str) -> Vec<(String, DataHolder)> {
+        vec![
+            ("level".to_owned(), DataHolder::Float(level)),
+            ("palette".to_owned(), DataHolder::String(palette.to_owned())),
+        ]
+    }
+
+    #[test]
+    fn numbers_are_interpolated() {
+        let (from, to) = (DataHolder::Float(-1.0), DataHolder::Float(3.0));
+        assert_eq!(interpolate(&from, &to, 0.0), DataHolder::Float(-1.0));
+        assert_eq!(interpolate(&from, &to, 0.5), DataHolder::Float(1.0));
+        assert_eq!(interpolate(&from, &to, 1.0), DataHolder::Float(3.0));
+
+        // Integers are rounded to the closest value
+        let (from, to) = (DataHolder::Int(0), DataHolder::Int(3));
+        assert_eq!(interpolate(&from, &to, 0.0), DataHolder::Int(0));
+        assert_eq!(interpolate(&from, &to, 0.5), DataHolder::Int(2));
+        assert_eq!(interpolate(&from, &to, 1.0), DataHolder::Int(3));
+    }
+
+    #[test]
+    fn vectors_are_interpolated_component_wise() {
+        assert_eq!(
+            interpolate(
+                &DataHolder::Vec2([0.0, 1.0]),
+                &DataHolder::Vec2([1.0, 0.0]),
+                0.5
+            ),
+            DataHolder::Vec2([0.5, 0.5])
+        );
+        assert_eq!(
+            interpolate(
+                &DataHolder::Vec3([0.0, 2.0, 4.0]),
+                &DataHolder::Vec3([2.0, 2.0, 0.0]),
+                0.5
+            ),
+            DataHolder::Vec3([1.0, 2.0, 2.0])
+        );
+
+        let (from, to) = (
+            DataHolder::Vec4([0.0, 0.0, 0.0, 1.0]),
+            DataHolder::Vec4([1.0, 0.5, 0.25, 1.0]),
+        );
+        assert_eq!(interpolate(&from, &to, 0.0), from);
+        assert_eq!(
+            interpolate(&from, &to, 0.5),
+            DataHolder::Vec4([0.5, 0.25, 0.125, 1.0])
+        );
+        assert_eq!(interpolate(&from, &to, 1.0), to);
+    }
+
+    #[test]
+    fn other_values_switch_at_once() {
+        let (from, to) = (DataHolder::Bool(false), DataHolder::Bool(true));
+        assert_eq!(interpolate(&from, &to, 0.0), to);
+
+        // Values changing type cannot be interpolated either
+        let (from, to) = (DataHolder::Int(1), DataHolder::Float(2.0));
+        assert_eq!(interpolate(&from, &to, 0.5), to);
+    }
+
+    #[test]
+    fn crossfades_follow_the_beat() {
+        let crossfade = crossfade(4.0);
+
+        assert_eq!(crossfade.variable_values(6.0), values(0.0, "cold"));
+        assert_eq!(crossfade.variable_values(8.0), values(0.0, "cold"));
+        assert!(!crossfade.is_finished(8.0));
+
+        assert_eq!(crossfade.variable_values(9.0), values(0.25, "cold"));
+        assert_eq!(crossfade.variable_values(10.0), values(0.5, "cold"));
+        assert!(!crossfade.is_finished(11.9));
+
+        assert_eq!(crossfade.variable_values(12.0), values(1.0, "cold"));
+        assert!(crossfade.is_finished(12.0));
+        assert_eq!(crossfade.variable_values(20.0), values(1.0, "cold"));
+    }
+
+    #[test]
+    fn instant_crossfades_are_finished_at_once() {
+        let crossfade = crossfade(0.0);
+
+        assert!(crossfade.is_finished(0.0));
+        assert_eq!(crossfade.variable_values(0.0), values(1.0, "cold"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use glium::backend::Facade;
+use glium::texture::pixel_buffer::PixelBuffer;
+use glium::texture::Texture2d;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Number of frames a readback stays in flight before being mapped, so that the
+/// render loop never waits for the GPU to finish the transfer
+const READBACK_LATENCY: usize = 2;
+
+/// Time a blocked render loop waits for a free frame before giving up on the encoder
+const ENCODER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What to do when the encoder is too slow to keep up with the render loop
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BackpressurePolicy {
+    /// Waits for the encoder, slowing the render loop down but never losing a frame
+    Block,
+    /// Drops the frame and counts it, keeping the render loop at full speed
+    Drop,
+}
+
+#[derive(Debug, Default)]
+pub struct CaptureStats {
+    pub captured: AtomicUsize,
+    pub encoded: AtomicUsize,
+    pub dropped: AtomicUsize,
+}
+
+impl CaptureStats {
+    pub fn snapshot(&self) -> (usize, usize, usize) {
+        (
+            self.captured.load(Ordering::Relaxed),
+            self.encoded.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Fixed set of frame buffers handed from one thread to another and given back once used.
+///
+/// The pool keeps no sender of its own, so that it notices when every holder of
+/// a recycler, such as the encoder thread, is gone.
+pub struct FramePool<T> {
+    free_receiver: Receiver<T>,
+    policy: BackpressurePolicy,
+}
+
+impl<T> FramePool<T> {
+    /// Builds a pool of `size` buffers, returning the handle that gives them back
+    pub fn new<F: Fn() -> T>(
+        size: usize,
+        new_frame: F,
+        policy: BackpressurePolicy,
+    ) -> (Self, Sender<T>) {
+        let (free_sender, free_receiver) = channel();
+        for _ in 0..size.max(1) {
+            free_sender.send(new_frame()).unwrap();
+        }
+
+        (
+            Self {
+                free_receiver,
+                policy,
+            },
+            free_sender,
+        )
+    }
+
+    /// Returns a free buffer, or `None` if the frame has to be dropped.
+    /// Fails once the buffers cannot come back anymore, or when they stay away too long.
+    pub fn acquire(&self) -> Result<Option<T>> {
+        match self.policy {
+            BackpressurePolicy::Block => match self.free_receiver.recv_timeout(ENCODER_TIMEOUT) {
+                Ok(frame) => Ok(Some(frame)),
+                Err(RecvTimeoutError::Timeout) => {
+                    bail!(
+                        "The encoder did not release a frame for {:?}",
+                        ENCODER_TIMEOUT
+                    )
+                }
+                Err(RecvTimeoutError::Disconnected) => bail!("The encoder has stopped"),
+            },
+            BackpressurePolicy::Drop => match self.free_receiver.try_recv() {
+                Ok(frame) => Ok(Some(frame)),
+                Err(TryRecvError::Empty) => Ok(None),
+                Err(TryRecvError::Disconnected) => bail!("The encoder has stopped"),
+            },
+        }
+    }
+}
+
+/// Frame buffer going back to its pool once dropped, which lets gstreamer hold it
+/// as long as the pipeline needs it
+pub struct PooledFrame {
+    data: Vec<u8>,
+    recycler: Sender<Vec<u8>>,
+}
+
+impl PooledFrame {
+    pub fn new(data: Vec<u8>, recycler: Sender<Vec<u8>>) -> Self {
+        Self { data, recycler }
+    }
+}
+
+impl AsMut<[u8]> for PooledFrame {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl Drop for PooledFrame {
+    fn drop(&mut self) {
+        let _ = self.recycler.send(mem::take(&mut self.data));
+    }
+}
+
+pub struct ReadbackFrame {
+    pub pixel_buffer: PixelBuffer<(u8, u8, u8, u8)>,
+    pub resolution: (usize, usize),
+}
+
+/// Reads a render target back through pixel buffers, mapping each one a few frames
+/// after the transfer was requested
+#[derive(Default)]
+pub struct AsyncReadback {
+    texture: Option<Texture2d>,
+    pending: VecDeque<ReadbackFrame>,
+}
+
+impl AsyncReadback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_texture(
+        &mut self,
+        display: &dyn Facade,
+        resolution: (usize, usize),
+    ) -> Result<&Texture2d> {
+        let resolution = (resolution.0 as u32, resolution.1 as u32);
+
+        let needs_rebuild = match &self.texture {
+            Some(texture) => texture.dimensions() != resolution,
+            None => true,
+        };
+
+        if needs_rebuild {
+            self.texture = Some(
+                Texture2d::empty(display, resolution.0, resolution.1)
+                    .context("Failed to create the capture render target")?,
+            );
+        }
+
+        Ok(self.texture.as_ref().unwrap())
+    }
+
+    /// Starts transferring the current content of the capture target
+    pub fn request(&mut self) {
+        if let Some(texture) = &self.texture {
+            let (width, height) = texture.dimensions();
+
+            self.pending.push_back(ReadbackFrame {
+                pixel_buffer: texture.read_to_pixel_buffer(),
+                resolution: (width as usize, height as usize),
+            });
+        }
+    }
+
+    /// Returns the oldest transfer once it is old enough, or any pending one when flushing
+    pub fn next_ready(&mut self, flush: bool) -> Option<ReadbackFrame> {
+        if flush || self.pending.len() > READBACK_LATENCY {
+            self.pending.pop_front()
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+
+use super::capture::PooledFrame;
+use super::{Codec, RecordingConfig};
+
+pub struct VideoEncoder {
+    pipeline: gst::Pipeline,
+    source: gst_app::AppSrc,
+
+    width: usize,
+    height: usize,
+    alpha: bool,
+    pixel_size: usize,
+    fps: f64,
+}
+
+impl VideoEncoder {
+    pub fn new(
+        output_path: &Path,
+        width: usize,
+        height: usize,
+        fps: f64,
+        recording_config: &RecordingConfig,
+    ) -> Result<Self> {
+        recording_config.check_format()?;
+        gst::init().context("Failed to initialize gstreamer")?;
+
+        let alpha = recording_config.keeps_alpha();
+        let (format, pixel_size) = recording_config.frame_format();
+
+        let encoder = match recording_config.codec {
+            Codec::H264 => format!(
+                "x264enc bitrate={:} speed-preset=veryfast ! h264parse",
+                recording_config.bitrate
+            ),
+            Codec::Vp8 => format!(
+                "vp8enc target-bitrate={:} deadline=1",
+                recording_config.bitrate * 1000
+            ),
+            Codec::Vp9 => format!(
+                "vp9enc target-bitrate={:} deadline=1",
+                recording_config.bitrate * 1000
+            ),
+            Codec::ProRes if alpha => "avenc_prores_ks profile=4".to_owned(),
+            Codec::ProRes => "avenc_prores_ks profile=3".to_owned(),
+            Codec::Ffv1 => "avenc_ffv1".to_owned(),
+            Codec::Png => "pngenc".to_owned(),
+        };
+
+        // The source blocks once it holds as many frames as the pool, which stalls
+        // the encoder thread and lets the frame pool apply the backpressure policy
+        let description = format!(
+            "appsrc name=source format=time is-live=false block=true max-bytes={:} \
+             caps=video/x-raw,format={:},width={:},height={:},framerate={:}/1000 \
+             ! videoconvert ! {:} ! {:} ! filesink location=\"{:}\"",
+            width * height * pixel_size * recording_config.frame_pool_size.max(1),
+            format,
+            width,
+            height,
+            (fps * 1000.0).round() as i64,
+            encoder,
+            recording_config.container.muxer(),
+            output_path.to_str().unwrap().replace('\\', "/"),
+        );
+
+        let pipeline = gst::parse_launch(&description)
+            .context("Failed to build the recording pipeline")?
+            .dynamic_cast::<gst::Pipeline>()
+            .unwrap();
+
+        let source = pipeline
+            .get_by_name("source")
+            .unwrap()
+            .dynamic_cast::<gst_app::AppSrc>()
+            .unwrap();
+
+        pipeline
+            .set_state(gst::State::Playing)
+            .context("Failed to start the recording pipeline")?;
+
+        Ok(Self {
+            pipeline,
+            source,
+
+            width,
+            height,
+            alpha,
+            pixel_size,
+            fps,
+        })
+    }
+
+    pub fn get_resolution(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Whether the alpha channel of the frames ends up in the output file, it is dropped otherwise
+    pub fn keeps_alpha(&self) -> bool {
+        self.alpha
+    }
+
+    /// Size in bytes of the pixels of the encoded frames
+    pub fn get_pixel_size(&self) -> usize {
+        self.pixel_size
+    }
+
+    /// Encodes a frame made of tightly packed RGBA pixels, or RGB ones when the alpha channel
+    /// is dropped, `timestamp` being the time
+    /// in seconds since the start of the recording.
+    /// The frame goes back to its pool once the pipeline is done with it.
+    pub fn encode_frame(&mut self, timestamp: f64, frame: PooledFrame) -> Result<()> {
+        let mut buffer = gst::Buffer::from_mut_slice(frame);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_nseconds(
+                (timestamp * 1_000_000_000.0) as u64,
+            ));
+            buffer.set_duration(gst::ClockTime::from_nseconds(
+                (1_000_000_000.0 / self.fps) as u64,
+            ));
+        }
+
+        if self.source.push_buffer(buffer).is_err() {
+            bail!("Recording pipeline refused a frame");
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the pending frames and finalizes the output file
+    pub fn finish(self) -> Result<()> {
+        let _ = self.source.end_of_stream();
+
+        let bus = self.pipeline.get_bus().unwrap();
+        for message in bus.iter_timed(gst::CLOCK_TIME_NONE) {
+            match message.view() {
+                gst::MessageView::Eos(..) => break,
+                gst::MessageView::Error(err) => {
+                    let _ = self.pipeline.set_state(gst::State::Null);
+                    bail!("Recording pipeline failed: {:}", err.get_error());
+                }
+                _ => (),
+            }
+        }
+
+        self.pipeline
+            .set_state(gst::State::Null)
+            .context("Failed to stop the recording pipeline")?;
+
+        Ok(())
+    }
+}
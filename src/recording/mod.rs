@@ -0,0 +1,370 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+
+use serde_derive::{Deserialize, Serialize};
+
+pub mod capture;
+pub mod encoder;
+pub mod recorder;
+
+use capture::BackpressurePolicy;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Container {
+    Mkv,
+    Mp4,
+    Mov,
+    Webm,
+}
+
+impl Container {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Mkv => "mkv",
+            Container::Mp4 => "mp4",
+            Container::Mov => "mov",
+            Container::Webm => "webm",
+        }
+    }
+
+    pub fn muxer(&self) -> &'static str {
+        match self {
+            Container::Mkv => "matroskamux",
+            Container::Mp4 => "mp4mux",
+            Container::Mov => "qtmux",
+            Container::Webm => "webmmux",
+        }
+    }
+
+    /// Whether the muxer of the container accepts the output of the codec
+    pub fn supports_codec(&self, codec: Codec) -> bool {
+        match self {
+            Container::Mkv => codec != Codec::Png,
+            Container::Mp4 => codec == Codec::H264,
+            Container::Mov => matches!(codec, Codec::H264 | Codec::ProRes | Codec::Png),
+            Container::Webm => matches!(codec, Codec::Vp8 | Codec::Vp9),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Codec {
+    H264,
+    Vp8,
+    Vp9,
+    ProRes,
+    Ffv1,
+    Png,
+}
+
+impl Codec {
+    pub fn supports_alpha(&self) -> bool {
+        matches!(self, Codec::ProRes | Codec::Ffv1 | Codec::Png)
+    }
+}
+
+/// Part of the render chain written to a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RecordSource {
+    /// The composited output of the final stage, as displayed in the window
+    Final,
+    /// The output of a named render stage
+    Stage(String),
+}
+
+impl RecordSource {
+    pub fn get_name(&self) -> String {
+        match self {
+            RecordSource::Final => "final".to_owned(),
+            RecordSource::Stage(stage_name) => stage_name.replace(&['/', '\\'][..], "_"),
+        }
+    }
+}
+
+/// What to do with captured frames whose size differs from the one the take was started with
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ResolutionChangePolicy {
+    /// Stretches the frames back to the take resolution
+    Rescale,
+    /// Closes the take and continues in a new file at the new resolution
+    Split,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    /// Output file name without extension, `{take}`, `{timestamp}` and `{source}` are replaced
+    /// by the take counter, the unix time at which the recording started and the recorded stage
+    pub path_template: String,
+    pub container: Container,
+    pub codec: Codec,
+    /// Target bitrate in kbit/s, ignored by lossless codecs
+    pub bitrate: u32,
+    pub alpha: bool,
+    /// Number of frames that can wait for the encoder at once
+    pub frame_pool_size: usize,
+    /// Blocks when the speed is locked and drops frames otherwise when unset
+    pub backpressure: Option<BackpressurePolicy>,
+    pub on_resolution_change: ResolutionChangePolicy,
+    /// Stages recorded simultaneously, each one to its own file
+    pub sources: Vec<RecordSource>,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            path_template: "output_{take}".to_owned(),
+            container: Container::Mkv,
+            codec: Codec::H264,
+            bitrate: 8000,
+            alpha: false,
+            frame_pool_size: 8,
+            backpressure: None,
+            on_resolution_change: ResolutionChangePolicy::Rescale,
+            sources: vec![RecordSource::Final],
+        }
+    }
+}
+
+impl RecordingConfig {
+    /// Builds the path of the next take, skipping take numbers already present in the output folder
+    pub fn get_output_path(&self, output_folder: &Path, source: &RecordSource) -> PathBuf {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        self.take_path(output_folder, source, timestamp)
+    }
+
+    fn take_path(&self, output_folder: &Path, source: &RecordSource, timestamp: u64) -> PathBuf {
+        // Stems of the same take must not overwrite each other
+        let path_template =
+            if self.path_template.contains("{source}") || source == &RecordSource::Final {
+                self.path_template.clone()
+            } else {
+                format!("{:}_{{source}}", self.path_template)
+            };
+        // Templates without a take number get one appended once their file exists, so that
+        // a split or a new take never overwrites an earlier one
+        let has_take_number = path_template.contains("{take}");
+
+        let mut take = 0;
+        loop {
+            let mut file_stem = path_template
+                .replace("{take}", &format!("{:03}", take))
+                .replace("{timestamp}", &timestamp.to_string())
+                .replace("{source}", &source.get_name());
+            if !has_take_number && take > 0 {
+                file_stem.push_str(&format!("_{:03}", take));
+            }
+
+            let output_path =
+                output_folder.join(format!("{:}.{:}", file_stem, self.container.extension()));
+            if !output_path.exists() {
+                return output_path;
+            }
+
+            take += 1;
+        }
+    }
+
+    pub fn keeps_alpha(&self) -> bool {
+        self.alpha && self.codec.supports_alpha()
+    }
+
+    /// Raw format of the frames handed to the encoder and the size of its pixels in bytes,
+    /// the alpha channel being left out unless it is kept
+    pub fn frame_format(&self) -> (&'static str, usize) {
+        if self.keeps_alpha() {
+            ("RGBA", 4)
+        } else {
+            ("RGB", 3)
+        }
+    }
+
+    /// Checks that the codec can be written to the container, which gstreamer
+    /// would otherwise only report once a take starts
+    pub fn check_format(&self) -> Result<()> {
+        if !self.container.supports_codec(self.codec) {
+            bail!(
+                "The {:?} codec cannot be recorded to a {:?} container",
+                self.codec,
+                self.container
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Applies an update, leaving the settings untouched if the result has a container
+    /// that cannot hold its codec
+    pub fn apply(&mut self, update: &RecordingUpdate) -> Result<()> {
+        let mut recording_config = self.clone();
+        recording_config.apply_unchecked(update);
+        recording_config.check_format()?;
+
+        *self = recording_config;
+
+        Ok(())
+    }
+
+    fn apply_unchecked(&mut self, update: &RecordingUpdate) {
+        match update {
+            RecordingUpdate::PathTemplate(path_template) => {
+                self.path_template = path_template.clone()
+            }
+            RecordingUpdate::Container(container) => self.container = *container,
+            RecordingUpdate::Codec(codec) => self.codec = *codec,
+            RecordingUpdate::Format(container, codec) => {
+                self.container = *container;
+                self.codec = *codec;
+            }
+            RecordingUpdate::Bitrate(bitrate) => self.bitrate = *bitrate,
+            RecordingUpdate::Alpha(alpha) => self.alpha = *alpha,
+            RecordingUpdate::OnResolutionChange(policy) => self.on_resolution_change = *policy,
+            RecordingUpdate::Sources(sources) => self.sources = sources.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum RecordingUpdate {
+    PathTemplate(String),
+    Container(Container),
+    Codec(Codec),
+    /// Changes the container and the codec at once, as most codecs only fit some containers
+    Format(Container, Codec),
+    Bitrate(u32),
+    Alpha(bool),
+    OnResolutionChange(ResolutionChangePolicy),
+    Sources(Vec<RecordSource>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum RecordOrder {
+    Start,
+    Stop,
+    /// Closes the current take and starts a new one without missing a frame
+    Split,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    /// Empty output folder of its own, removed by the caller
+    fn output_folder(folder_name: &str) -> PathBuf {
+        let folder_path =
+            std::env::temp_dir().join(format!("wvr-{:}-{:}", folder_name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder_path);
+        fs::create_dir_all(&folder_path).unwrap();
+
+        folder_path
+    }
+
+    fn recording_config(path_template: &str) -> RecordingConfig {
+        RecordingConfig {
+            path_template: path_template.to_owned(),
+            ..RecordingConfig::default()
+        }
+    }
+
+    /// Builds the path of the next take and creates its file, as starting the take would
+    fn start_take(
+        recording_config: &RecordingConfig,
+        output_folder: &Path,
+        source: &RecordSource,
+    ) -> String {
+        let output_path = recording_config.take_path(output_folder, source, 1_600_000_000);
+        fs::write(&output_path, b"").unwrap();
+
+        output_path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn takes_are_numbered_after_the_existing_ones() {
+        let output_folder = output_folder("recording-take-numbers");
+        let recording_config = recording_config("output_{take}");
+
+        let file_names = (0..3)
+            .map(|_| start_take(&recording_config, &output_folder, &RecordSource::Final))
+            .collect::<Vec<String>>();
+        fs::remove_dir_all(&output_folder).unwrap();
+
+        assert_eq!(
+            file_names,
+            vec!["output_000.mkv", "output_001.mkv", "output_002.mkv"]
+        );
+    }
+
+    #[test]
+    fn templates_without_take_number_never_overwrite_a_take() {
+        let output_folder = output_folder("recording-fixed-names");
+
+        let file_names = ["set", "set", "set_{timestamp}", "set_{timestamp}"]
+            .iter()
+            .map(|path_template| {
+                start_take(
+                    &recording_config(path_template),
+                    &output_folder,
+                    &RecordSource::Final,
+                )
+            })
+            .collect::<Vec<String>>();
+        fs::remove_dir_all(&output_folder).unwrap();
+
+        assert_eq!(
+            file_names,
+            vec![
+                "set.mkv",
+                "set_001.mkv",
+                "set_1600000000.mkv",
+                "set_1600000000_001.mkv"
+            ]
+        );
+    }
+
+    #[test]
+    fn stages_are_recorded_to_their_own_files() {
+        let output_folder = output_folder("recording-stages");
+        let recording_config = recording_config("output_{take}");
+
+        let final_file_name = start_take(&recording_config, &output_folder, &RecordSource::Final);
+        let stage_file_name = start_take(
+            &recording_config,
+            &output_folder,
+            &RecordSource::Stage("fx/glow".to_owned()),
+        );
+        fs::remove_dir_all(&output_folder).unwrap();
+
+        assert_eq!(final_file_name, "output_000.mkv");
+        assert_eq!(stage_file_name, "output_000_fx_glow.mkv");
+    }
+
+    #[test]
+    fn alpha_is_only_sent_to_codecs_keeping_it() {
+        let frame_format = |codec, alpha| {
+            RecordingConfig {
+                codec,
+                alpha,
+                ..RecordingConfig::default()
+            }
+            .frame_format()
+        };
+
+        assert_eq!(frame_format(Codec::ProRes, true), ("RGBA", 4));
+        assert_eq!(frame_format(Codec::Png, true), ("RGBA", 4));
+        assert_eq!(frame_format(Codec::ProRes, false), ("RGB", 3));
+        assert_eq!(frame_format(Codec::H264, true), ("RGB", 3));
+        assert_eq!(frame_format(Codec::H264, false), ("RGB", 3));
+    }
+}
@@ -0,0 +1,417 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+
+use super::capture::{BackpressurePolicy, CaptureStats, FramePool, PooledFrame};
+use super::encoder::VideoEncoder;
+use super::{RecordSource, RecordingConfig, ResolutionChangePolicy};
+
+type Pixel = (u8, u8, u8, u8);
+
+/// Frame copied out of the render loop, converted to the take format by the encoder thread
+struct CapturedFrame {
+    pixels: Vec<Pixel>,
+    resolution: (usize, usize),
+    /// Whether rows are stored bottom first, as for OpenGL readbacks
+    bottom_up: bool,
+    /// Time since the start of the take, in seconds
+    timestamp: f64,
+}
+
+/// Writes a captured frame as tightly packed rows of `pixel_size` bytes, top first, stretching
+/// it to the take resolution with a nearest neighbour filter when its size differs.
+/// Pixels of 3 bytes leave the alpha channel out.
+fn convert_frame(
+    captured_frame: &CapturedFrame,
+    target: &mut [u8],
+    resolution: (usize, usize),
+    pixel_size: usize,
+) {
+    let (source_width, source_height) = captured_frame.resolution;
+    let (width, height) = resolution;
+
+    for (row_index, row) in target.chunks_exact_mut(width * pixel_size).enumerate() {
+        let source_row = row_index * source_height / height;
+        let source_row = if captured_frame.bottom_up {
+            source_height - 1 - source_row
+        } else {
+            source_row
+        };
+
+        let source_pixels =
+            &captured_frame.pixels[source_row * source_width..(source_row + 1) * source_width];
+        for (column_index, target) in row.chunks_exact_mut(pixel_size).enumerate() {
+            let (r, g, b, a) = source_pixels[column_index * source_width / width];
+            target.copy_from_slice(&[r, g, b, a][..pixel_size]);
+        }
+    }
+}
+
+/// Converts and encodes the captured frames until the recorder closes the take,
+/// giving the captured frames back to the render loop as soon as they are converted
+fn run_encoder(
+    mut encoder: VideoEncoder,
+    resolution: (usize, usize),
+    pool_size: usize,
+    frame_receiver: impl Iterator<Item = CapturedFrame>,
+    capture_recycler: Sender<Vec<Pixel>>,
+    stats: Arc<CaptureStats>,
+) -> Result<()> {
+    let pixel_size = encoder.get_pixel_size();
+
+    // Encoded frames wait for a buffer gstreamer gave back instead of piling up
+    let (encode_pool, encode_recycler) = FramePool::new(
+        pool_size,
+        || vec![0; resolution.0 * resolution.1 * pixel_size],
+        BackpressurePolicy::Block,
+    );
+
+    let mut result = Ok(());
+    for captured_frame in frame_receiver {
+        let encoded = encode_pool.acquire().and_then(|frame| {
+            let mut frame = frame.context("No frame buffer available for encoding")?;
+            convert_frame(&captured_frame, &mut frame, resolution, pixel_size);
+            let timestamp = captured_frame.timestamp;
+            let _ = capture_recycler.send(captured_frame.pixels);
+
+            encoder.encode_frame(timestamp, PooledFrame::new(frame, encode_recycler.clone()))
+        });
+
+        if let Err(e) = encoded {
+            result = Err(e);
+            break;
+        }
+        stats.encoded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let finished = encoder.finish();
+    result.and(finished)
+}
+
+struct Take {
+    recording_config: RecordingConfig,
+    resolution: (usize, usize),
+    fps: f64,
+    policy: BackpressurePolicy,
+    /// Frames offered to the take so far, dropped ones included, which times the next one.
+    /// The project time cannot be used, as restarts, seeks and clock corrections move it back.
+    frame_count: u64,
+
+    frame_pool: FramePool<Vec<Pixel>>,
+    frame_sender: Sender<CapturedFrame>,
+    encoder_thread: thread::JoinHandle<()>,
+    /// Why the encoder thread stopped before the take was closed
+    encoder_error: Arc<Mutex<Option<String>>>,
+}
+
+/// Records takes of one stage of the render chain, each take being encoded to its own file
+/// by a dedicated thread
+pub struct Recorder {
+    output_folder: PathBuf,
+    source: RecordSource,
+
+    take: Option<Take>,
+    stats: Arc<CaptureStats>,
+}
+
+impl Recorder {
+    pub fn new(output_folder: PathBuf, source: RecordSource) -> Self {
+        Self {
+            output_folder,
+            source,
+
+            take: None,
+            stats: Arc::new(CaptureStats::default()),
+        }
+    }
+
+    pub fn get_source(&self) -> &RecordSource {
+        &self.source
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.take.is_some()
+    }
+
+    /// Returns the captured, encoded and dropped frame counts of the current or last take
+    pub fn get_stats(&self) -> (usize, usize, usize) {
+        self.stats.snapshot()
+    }
+
+    pub fn start(
+        &mut self,
+        recording_config: &RecordingConfig,
+        resolution: (usize, usize),
+        fps: f64,
+        policy: BackpressurePolicy,
+    ) -> Result<()> {
+        if self.is_recording() {
+            return Ok(());
+        }
+
+        if !self.output_folder.exists() {
+            fs::create_dir_all(&self.output_folder).context(format!(
+                "Could not create screenshot output folder {:?}",
+                self.output_folder
+            ))?;
+        }
+
+        let output_path = recording_config.get_output_path(&self.output_folder, &self.source);
+        let mut encoder = VideoEncoder::new(
+            &output_path,
+            resolution.0,
+            resolution.1,
+            fps,
+            recording_config,
+        )
+        .context(format!("Failed to start recording to {:?}", output_path))?;
+
+        println!("Recording to {:?}", output_path);
+
+        let pool_size = recording_config.frame_pool_size;
+        let (frame_pool, capture_recycler) = FramePool::new(pool_size, Vec::new, policy);
+
+        self.stats = Arc::new(CaptureStats::default());
+        let stats = self.stats.clone();
+
+        let encoder_error = Arc::new(Mutex::new(None));
+        let thread_encoder_error = encoder_error.clone();
+
+        let (frame_sender, frame_receiver) = channel::<CapturedFrame>();
+        let encoder_thread = thread::spawn(move || {
+            // The loop ends once the recorder drops its sender
+            if let Err(e) = run_encoder(
+                encoder,
+                resolution,
+                pool_size,
+                frame_receiver.into_iter(),
+                capture_recycler,
+                stats,
+            ) {
+                eprintln!("{:?}", e);
+                *thread_encoder_error.lock().unwrap() = Some(format!("{:#}", e));
+            }
+        });
+
+        self.take = Some(Take {
+            recording_config: recording_config.clone(),
+            resolution,
+            fps,
+            policy,
+            frame_count: 0,
+
+            frame_pool,
+            frame_sender,
+            encoder_thread,
+            encoder_error,
+        });
+
+        Ok(())
+    }
+
+    /// Stops the current take and waits for its file to be finalized
+    pub fn stop(&mut self) {
+        if let Some(take) = self.take.take() {
+            drop(take.frame_sender);
+
+            if take.encoder_thread.join().is_err() {
+                eprintln!("Screenshot processing thread did not finish cleanly");
+            }
+
+            let (captured, encoded, dropped) = self.get_stats();
+            println!(
+                "Recorded {:} frames out of {:}, {:} dropped",
+                encoded, captured, dropped
+            );
+        }
+    }
+
+    /// Closes the current take and immediately opens a new one
+    pub fn split(
+        &mut self,
+        recording_config: &RecordingConfig,
+        resolution: (usize, usize),
+        fps: f64,
+        policy: BackpressurePolicy,
+    ) -> Result<()> {
+        self.stop();
+        self.start(recording_config, resolution, fps, policy)
+    }
+
+    /// Queues a frame for encoding, frames being timed by their position in the take.
+    /// Rows are expected top first unless `bottom_up` is set, as for OpenGL readbacks
+    pub fn push_frame(
+        &mut self,
+        pixels: &[Pixel],
+        resolution: (usize, usize),
+        bottom_up: bool,
+    ) -> Result<()> {
+        if pixels.len() != resolution.0 * resolution.1 {
+            bail!("Captured frame does not match its resolution");
+        }
+
+        let take = if let Some(take) = &self.take {
+            take
+        } else {
+            return Ok(());
+        };
+
+        let encoder_error = take.encoder_error.lock().unwrap().clone();
+        if let Some(encoder_error) = encoder_error {
+            self.stop();
+            bail!(
+                "Recording of {:} stopped, the encoder failed: {:}",
+                self.source.get_name(),
+                encoder_error
+            );
+        }
+
+        if resolution != take.resolution
+            && take.recording_config.on_resolution_change == ResolutionChangePolicy::Split
+        {
+            let recording_config = take.recording_config.clone();
+            let (fps, policy) = (take.fps, take.policy);
+
+            self.split(&recording_config, resolution, fps, policy)?;
+        }
+
+        let take = if let Some(take) = &mut self.take {
+            take
+        } else {
+            return Ok(());
+        };
+
+        self.stats.captured.fetch_add(1, Ordering::Relaxed);
+        // Dropped frames still take their time slot, so that the take keeps the pace of the render
+        let timestamp = take.frame_count as f64 / take.fps;
+        take.frame_count += 1;
+
+        let mut captured_pixels = match take.frame_pool.acquire() {
+            Ok(Some(captured_pixels)) => captured_pixels,
+            Ok(None) => {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(e) => {
+                self.stop();
+                return Err(e.context(format!("Recording of {:} stopped", self.source.get_name())));
+            }
+        };
+
+        // A plain copy, the conversion to the take format happening on the encoder thread
+        captured_pixels.clear();
+        captured_pixels.extend_from_slice(pixels);
+
+        let captured_frame = CapturedFrame {
+            pixels: captured_pixels,
+            resolution,
+            bottom_up,
+            timestamp,
+        };
+        if take.frame_sender.send(captured_frame).is_err() {
+            self.stop();
+            bail!("Screenshot processing thread seems to have crashed");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame whose pixels tell their position, red being the column and green the row
+    fn captured_frame(resolution: (usize, usize), bottom_up: bool) -> CapturedFrame {
+        let (width, height) = resolution;
+        let pixels = (0..height)
+            .flat_map(|row| (0..width).map(move |column| (column as u8, row as u8, 0, 255)))
+            .collect();
+
+        CapturedFrame {
+            pixels,
+            resolution,
+            bottom_up,
+            timestamp: 0.0,
+        }
+    }
+
+    /// Converts a frame, returning the (column, row) each target pixel was taken from
+    fn converted_positions(
+        captured_frame: &CapturedFrame,
+        resolution: (usize, usize),
+    ) -> Vec<(u8, u8)> {
+        let mut target = vec![0; resolution.0 * resolution.1 * 4];
+        convert_frame(captured_frame, &mut target, resolution, 4);
+
+        target
+            .chunks_exact(4)
+            .map(|pixel| (pixel[0], pixel[1]))
+            .collect()
+    }
+
+    #[test]
+    fn frames_of_the_take_size_are_copied() {
+        let frame = captured_frame((3, 2), false);
+
+        assert_eq!(
+            converted_positions(&frame, (3, 2)),
+            vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn bottom_up_frames_are_flipped() {
+        let frame = captured_frame((2, 3), true);
+
+        assert_eq!(
+            converted_positions(&frame, (2, 3)),
+            vec![(0, 2), (1, 2), (0, 1), (1, 1), (0, 0), (1, 0)]
+        );
+    }
+
+    #[test]
+    fn frames_are_stretched_to_the_take_size() {
+        let frame = captured_frame((2, 2), false);
+        assert_eq!(
+            converted_positions(&frame, (4, 2)),
+            vec![
+                (0, 0),
+                (0, 0),
+                (1, 0),
+                (1, 0),
+                (0, 1),
+                (0, 1),
+                (1, 1),
+                (1, 1)
+            ]
+        );
+
+        let frame = captured_frame((4, 4), true);
+        assert_eq!(
+            converted_positions(&frame, (2, 2)),
+            vec![(0, 3), (2, 3), (0, 1), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn alpha_is_kept() {
+        let mut target = vec![0; 4];
+        convert_frame(&captured_frame((1, 1), false), &mut target, (1, 1), 4);
+
+        assert_eq!(target, vec![0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn alpha_is_dropped_from_rgb_frames() {
+        let mut target = vec![0; 2 * 2 * 3];
+        convert_frame(&captured_frame((2, 2), true), &mut target, (2, 2), 3);
+
+        assert_eq!(target, vec![0, 1, 0, 1, 1, 0, 0, 0, 0, 1, 0, 0]);
+    }
+}
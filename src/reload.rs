@@ -0,0 +1,623 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+
+use serde::Serialize;
+
+use wvr_com::data::{InputUpdate, Message, RenderStageUpdate, SetInfo};
+use wvr_data::config::input::InputConfig;
+use wvr_data::config::project::ProjectConfig;
+use wvr_data::config::rendering::RenderStageConfig;
+
+use crate::command::Command;
+use crate::config::{apply_input_update, apply_stage_update};
+use crate::layer;
+
+/// Time during which successive writes to the config file make a single change,
+/// as editors often write a file in several steps
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+
+/// Folders holding the files of a layered config, which are watched rather than the files
+/// themselves, as editors often save by replacing a file, which only a watch on its folder notices
+fn layer_folders(layer_files: &HashSet<PathBuf>) -> HashSet<PathBuf> {
+    layer_files
+        .iter()
+        .filter_map(|layer_file| layer_file.parent())
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// Watches the project config file and every fragment it includes, sending a reload order
+/// each time one of them changes. The watched fragments follow the includes of the config.
+pub fn watch_config(config_path: &Path, order_sender: Sender<Command>) -> Result<()> {
+    let config_path = config_path.to_owned();
+    let mut layer_files: HashSet<PathBuf> = layer::layer_files(&config_path)
+        .context("Failed to watch the project config")?
+        .into_iter()
+        .collect();
+
+    let (event_sender, event_receiver) = channel();
+    let mut config_watcher =
+        watcher(event_sender, DEBOUNCE_DELAY).context("Failed to watch the project config")?;
+    let mut watched_folders = layer_folders(&layer_files);
+    for folder in &watched_folders {
+        config_watcher
+            .watch(folder, RecursiveMode::NonRecursive)
+            .context(format!("Failed to watch {:?}", folder))?;
+    }
+
+    thread::spawn(move || {
+        // While the includes cannot be read, any change next to the known layers may fix them
+        let mut are_layers_known = true;
+
+        for event in event_receiver {
+            let changed_path = match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Rename(_, path) => path,
+                _ => continue,
+            };
+
+            if are_layers_known && !layer_files.contains(&changed_path) {
+                continue;
+            }
+            if order_sender.send(Command::ReloadConfig).is_err() {
+                break;
+            }
+
+            match layer::layer_files(&config_path) {
+                Ok(new_layer_files) => {
+                    layer_files = new_layer_files.into_iter().collect();
+                    are_layers_known = true;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "{:?}",
+                        e.context("Failed to update the watched config files")
+                    );
+                    are_layers_known = false;
+                    continue;
+                }
+            }
+
+            let new_folders = layer_folders(&layer_files);
+            for folder in watched_folders.difference(&new_folders) {
+                if let Err(e) = config_watcher.unwatch(folder) {
+                    eprintln!("Failed to stop watching {:?}: {:?}", folder, e);
+                }
+            }
+            for folder in new_folders.difference(&watched_folders) {
+                if let Err(e) = config_watcher.watch(folder, RecursiveMode::NonRecursive) {
+                    eprintln!("Failed to watch {:?}: {:?}", folder, e);
+                }
+            }
+            watched_folders = new_folders;
+        }
+    });
+
+    Ok(())
+}
+
+/// Compares values through their serialized form, which every part of a project config has
+fn differs<T: Serialize>(first_value: &T, second_value: &T) -> bool {
+    serde_json::to_value(first_value).ok() != serde_json::to_value(second_value).ok()
+}
+
+fn view_changes(running_config: &ProjectConfig, new_config: &ProjectConfig) -> Vec<Message> {
+    let running_view = &running_config.view;
+    let new_view = &new_config.view;
+
+    let mut set_infos = Vec::new();
+    if differs(&running_config.bpm, &new_config.bpm) {
+        set_infos.push(SetInfo::Bpm(new_config.bpm as _));
+    }
+    if running_view.width != new_view.width {
+        set_infos.push(SetInfo::Width(new_view.width as _));
+    }
+    if running_view.height != new_view.height {
+        set_infos.push(SetInfo::Height(new_view.height as _));
+    }
+    if differs(&running_view.target_fps, &new_view.target_fps) {
+        set_infos.push(SetInfo::TargetFps(new_view.target_fps as _));
+    }
+    if running_view.dynamic != new_view.dynamic {
+        set_infos.push(SetInfo::DynamicResolution(new_view.dynamic));
+    }
+    if running_view.vsync != new_view.vsync {
+        set_infos.push(SetInfo::VSync(new_view.vsync));
+    }
+    if running_view.fullscreen != new_view.fullscreen {
+        set_infos.push(SetInfo::Fullscreen(new_view.fullscreen));
+    }
+    if running_view.locked_speed != new_view.locked_speed {
+        set_infos.push(SetInfo::LockedSpeed(new_view.locked_speed));
+    }
+
+    set_infos.into_iter().map(Message::Set).collect()
+}
+
+/// Changes of an input, as updates when they are enough to keep the input running,
+/// or as a replacement of the input
+fn input_changes(
+    input_name: &str,
+    running_input_config: &InputConfig,
+    new_input_config: &InputConfig,
+) -> Vec<Message> {
+    let input_updates = match (running_input_config, new_input_config) {
+        (
+            InputConfig::Video {
+                path: running_path,
+                width: running_width,
+                height: running_height,
+                speed: running_speed,
+                ..
+            },
+            InputConfig::Video {
+                path,
+                width,
+                height,
+                speed,
+                ..
+            },
+        ) => {
+            let mut input_updates = Vec::new();
+            if running_path != path {
+                input_updates.push(InputUpdate::SetPath(path.clone()));
+            }
+            if running_width != width {
+                input_updates.push(InputUpdate::SetWidth(*width as _));
+            }
+            if running_height != height {
+                input_updates.push(InputUpdate::SetHeight(*height as _));
+            }
+            if differs(running_speed, speed) {
+                input_updates.push(InputUpdate::SetSpeed(speed.clone()));
+            }
+
+            input_updates
+        }
+        (
+            InputConfig::Picture {
+                path: running_path,
+                width: running_width,
+                height: running_height,
+                ..
+            },
+            InputConfig::Picture {
+                path,
+                width,
+                height,
+                ..
+            },
+        )
+        | (
+            InputConfig::Cam {
+                path: running_path,
+                width: running_width,
+                height: running_height,
+                ..
+            },
+            InputConfig::Cam {
+                path,
+                width,
+                height,
+                ..
+            },
+        ) => {
+            let mut input_updates = Vec::new();
+            if running_path != path {
+                input_updates.push(InputUpdate::SetPath(path.clone()));
+            }
+            if running_width != width {
+                input_updates.push(InputUpdate::SetWidth(*width as _));
+            }
+            if running_height != height {
+                input_updates.push(InputUpdate::SetHeight(*height as _));
+            }
+
+            input_updates
+        }
+        _ => Vec::new(),
+    };
+
+    let mut updated_input_config = running_input_config.clone();
+    for input_update in &input_updates {
+        apply_input_update(&mut updated_input_config, input_update);
+    }
+
+    if differs(&updated_input_config, new_input_config) {
+        vec![Message::AddInput(
+            input_name.to_owned(),
+            new_input_config.clone(),
+        )]
+    } else {
+        input_updates
+            .into_iter()
+            .map(|input_update| Message::UpdateInput(input_name.to_owned(), input_update))
+            .collect()
+    }
+}
+
+fn inputs_changes(
+    running_inputs: &HashMap<String, InputConfig>,
+    new_inputs: &HashMap<String, InputConfig>,
+) -> Vec<Message> {
+    let mut messages = Vec::new();
+
+    for input_name in running_inputs.keys() {
+        if !new_inputs.contains_key(input_name) {
+            messages.push(Message::RemoveInput(input_name.clone()));
+        }
+    }
+
+    for (input_name, new_input_config) in new_inputs {
+        match running_inputs.get(input_name) {
+            Some(running_input_config) => messages.extend(input_changes(
+                input_name,
+                running_input_config,
+                new_input_config,
+            )),
+            None => messages.push(Message::AddInput(
+                input_name.clone(),
+                new_input_config.clone(),
+            )),
+        }
+    }
+
+    messages
+}
+
+/// Updates bringing a running stage to its new config, `None` meaning they cannot,
+/// as when variables or inputs were removed
+fn stage_updates(
+    running_stage_config: &RenderStageConfig,
+    new_stage_config: &RenderStageConfig,
+) -> Option<Vec<RenderStageUpdate>> {
+    let mut updates = Vec::new();
+
+    if running_stage_config.name != new_stage_config.name {
+        updates.push(RenderStageUpdate::Name(new_stage_config.name.clone()));
+    }
+    if running_stage_config.filter != new_stage_config.filter {
+        updates.push(RenderStageUpdate::Filter(new_stage_config.filter.clone()));
+    }
+    if differs(
+        &running_stage_config.filter_mode_params,
+        &new_stage_config.filter_mode_params,
+    ) {
+        updates.push(RenderStageUpdate::FilterModeParams(
+            new_stage_config.filter_mode_params.clone(),
+        ));
+    }
+    if differs(&running_stage_config.precision, &new_stage_config.precision) {
+        updates.push(RenderStageUpdate::Precision(
+            new_stage_config.precision.clone(),
+        ));
+    }
+
+    for (input_name, input) in &new_stage_config.inputs {
+        let is_changed = running_stage_config
+            .inputs
+            .get(input_name)
+            .map(|running_input| differs(running_input, input))
+            .unwrap_or(true);
+        if is_changed {
+            updates.push(RenderStageUpdate::Input(input_name.clone(), input.clone()));
+        }
+    }
+
+    for (variable_name, (variable_value, variable_automation)) in &new_stage_config.variables {
+        let (running_value, running_automation) =
+            match running_stage_config.variables.get(variable_name) {
+                Some((running_value, running_automation)) => {
+                    (Some(running_value), Some(running_automation))
+                }
+                None => (None, None),
+            };
+
+        if running_value
+            .map(|running_value| differs(running_value, variable_value))
+            .unwrap_or(true)
+        {
+            updates.push(RenderStageUpdate::Variable(
+                variable_name.clone(),
+                variable_value.clone(),
+            ));
+        }
+        if running_automation
+            .map(|running_automation| differs(running_automation, variable_automation))
+            .unwrap_or(true)
+        {
+            updates.push(RenderStageUpdate::VariableAutomation(
+                variable_name.clone(),
+                variable_automation.clone(),
+            ));
+        }
+    }
+
+    let mut updated_stage_config = running_stage_config.clone();
+    for update in &updates {
+        apply_stage_update(&mut updated_stage_config, update);
+    }
+
+    if differs(&updated_stage_config, new_stage_config) {
+        None
+    } else {
+        Some(updates)
+    }
+}
+
+fn has_unique_names(stage_names: &[&str]) -> bool {
+    let mut unique_names = HashSet::new();
+    stage_names
+        .iter()
+        .all(|stage_name| unique_names.insert(stage_name))
+}
+
+/// Step turning the running render chain into the new one
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChainStep {
+    Remove(usize),
+    /// Adds the stage of the new chain at this index to the end of the running chain
+    Add(usize),
+    Move(usize, usize),
+    /// Brings the running stage at the first index to the new stage at the second one,
+    /// which is also where it sits by then
+    Update(usize, usize),
+}
+
+/// Steps turning a chain into another, stages being matched by name so that moved stages
+/// keep running
+fn chain_steps(running_names: &[&str], new_names: &[&str]) -> Vec<ChainStep> {
+    let mut steps = Vec::new();
+
+    // Without unique names, stages cannot be told apart, and the whole chain gets rebuilt
+    if !has_unique_names(running_names) || !has_unique_names(new_names) {
+        steps.extend((0..running_names.len()).rev().map(ChainStep::Remove));
+        steps.extend((0..new_names.len()).map(ChainStep::Add));
+        return steps;
+    }
+
+    let mut stage_names = running_names.to_vec();
+    for stage_index in (0..stage_names.len()).rev() {
+        if !new_names.contains(&stage_names[stage_index]) {
+            steps.push(ChainStep::Remove(stage_index));
+            stage_names.remove(stage_index);
+        }
+    }
+
+    // Stages added below already have their new config
+    let kept_stage_names = stage_names.iter().copied().collect::<HashSet<&str>>();
+
+    for (new_index, stage_name) in new_names.iter().enumerate() {
+        if !stage_names.contains(stage_name) {
+            steps.push(ChainStep::Add(new_index));
+            stage_names.push(stage_name);
+        }
+    }
+
+    for (target_index, stage_name) in new_names.iter().enumerate() {
+        let original_index = stage_names
+            .iter()
+            .position(|name| name == stage_name)
+            .unwrap_or(target_index);
+
+        if original_index != target_index {
+            steps.push(ChainStep::Move(original_index, target_index));
+            let stage_name = stage_names.remove(original_index);
+            stage_names.insert(target_index, stage_name);
+        }
+
+        if kept_stage_names.contains(stage_name) {
+            if let Some(running_index) = running_names.iter().position(|name| name == stage_name) {
+                steps.push(ChainStep::Update(running_index, target_index));
+            }
+        }
+    }
+
+    steps
+}
+
+/// Changes of the render chain, stages being matched by name so that moved stages keep running
+fn render_chain_changes(
+    running_config: &ProjectConfig,
+    new_config: &ProjectConfig,
+) -> Vec<Message> {
+    let running_chain = &running_config.render_chain;
+    let new_chain = &new_config.render_chain;
+    let stage_names = |render_chain: &[RenderStageConfig]| {
+        render_chain
+            .iter()
+            .map(|render_stage_config| render_stage_config.name.as_str())
+            .collect::<Vec<&str>>()
+    };
+
+    let mut messages = Vec::new();
+    for step in chain_steps(&stage_names(running_chain), &stage_names(new_chain)) {
+        match step {
+            ChainStep::Remove(stage_index) => {
+                messages.push(Message::RemoveRenderStage(stage_index))
+            }
+            ChainStep::Add(new_index) => {
+                messages.push(Message::AddRenderStage(new_chain[new_index].clone()))
+            }
+            ChainStep::Move(original_index, target_index) => {
+                messages.push(Message::MoveRenderStage(original_index, target_index))
+            }
+            ChainStep::Update(running_index, target_index) => {
+                let new_stage_config = &new_chain[target_index];
+                match stage_updates(&running_chain[running_index], new_stage_config) {
+                    Some(updates) => messages.extend(
+                        updates
+                            .into_iter()
+                            .map(|update| Message::UpdateRenderStage(target_index, update)),
+                    ),
+                    None => {
+                        // Stages are always added at the end of the chain, from where they get moved back
+                        messages.push(Message::RemoveRenderStage(target_index));
+                        messages.push(Message::AddRenderStage(new_stage_config.clone()));
+                        messages.push(Message::MoveRenderStage(new_chain.len() - 1, target_index));
+                    }
+                }
+            }
+        }
+    }
+
+    messages
+}
+
+/// Changes turning the running project into a new one
+pub struct ConfigChanges {
+    pub messages: Vec<Message>,
+    /// Changes no message applies, which wait for a restart
+    pub restart_needed: Vec<String>,
+}
+
+/// Builds the messages turning the running project into the new one, touching only what changed.
+/// Project variables have no message, and are left to the caller.
+pub fn config_changes(running_config: &ProjectConfig, new_config: &ProjectConfig) -> ConfigChanges {
+    let mut messages = view_changes(running_config, new_config);
+    messages.extend(inputs_changes(&running_config.inputs, &new_config.inputs));
+    messages.extend(render_chain_changes(running_config, new_config));
+
+    let mut restart_needed = Vec::new();
+    match stage_updates(&running_config.final_stage, &new_config.final_stage) {
+        Some(updates) => messages.extend(updates.into_iter().map(Message::UpdateFinalStage)),
+        None => restart_needed.push("Some changes of the final stage".to_owned()),
+    }
+
+    ConfigChanges {
+        messages,
+        restart_needed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies the steps to the running stage names, returning the resulting chain
+    /// and the stages updated in place
+    fn apply_steps(
+        running_names: &[&str],
+        new_names: &[&str],
+    ) -> (Vec<String>, Vec<(usize, usize)>) {
+        let mut chain = running_names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<String>>();
+        let mut updated_stages = Vec::new();
+
+        for step in chain_steps(running_names, new_names) {
+            match step {
+                ChainStep::Remove(stage_index) => {
+                    chain.remove(stage_index);
+                }
+                ChainStep::Add(new_index) => chain.push(new_names[new_index].to_owned()),
+                ChainStep::Move(original_index, target_index) => {
+                    let stage_name = chain.remove(original_index);
+                    chain.insert(target_index, stage_name);
+                }
+                ChainStep::Update(running_index, target_index) => {
+                    assert_eq!(running_names[running_index], chain[target_index]);
+                    updated_stages.push((running_index, target_index));
+                }
+            }
+        }
+
+        (chain, updated_stages)
+    }
+
+    #[test]
+    fn duplicate_names_rebuild_every_stage() {
+        let steps = chain_steps(&["blur", "blur"], &["blur", "blur", "glow"]);
+        assert_eq!(
+            steps,
+            vec![
+                ChainStep::Remove(1),
+                ChainStep::Remove(0),
+                ChainStep::Add(0),
+                ChainStep::Add(1),
+                ChainStep::Add(2),
+            ]
+        );
+
+        let (chain, _) = apply_steps(&["blur", "blur"], &["blur", "blur"]);
+        assert_eq!(chain, vec!["blur", "blur"]);
+
+        let (chain, _) = apply_steps(&["a", "b"], &["blur", "a", "blur"]);
+        assert_eq!(chain, vec!["blur", "a", "blur"]);
+    }
+
+    #[test]
+    fn reordered_stages_are_moved_and_kept() {
+        let (chain, updated_stages) = apply_steps(&["a", "b", "c"], &["c", "a", "b"]);
+
+        assert_eq!(chain, vec!["c", "a", "b"]);
+        assert_eq!(updated_stages, vec![(2, 0), (0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn removed_stages_are_the_only_ones_touched() {
+        let steps = chain_steps(&["a", "b", "c"], &["a", "c"]);
+        assert_eq!(
+            steps,
+            vec![
+                ChainStep::Remove(1),
+                ChainStep::Update(0, 0),
+                ChainStep::Update(2, 1),
+            ]
+        );
+
+        let (chain, _) = apply_steps(&["a", "b", "c", "d"], &["d", "new", "a"]);
+        assert_eq!(chain, vec!["d", "new", "a"]);
+    }
+
+    fn midi_inputs(inputs: &[(&str, &str)]) -> HashMap<String, InputConfig> {
+        inputs
+            .iter()
+            .map(|(input_name, port_name)| {
+                (
+                    input_name.to_string(),
+                    InputConfig::Midi {
+                        name: port_name.to_string(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_inputs_are_left_alone() {
+        let inputs = midi_inputs(&[("controller", "nanoKONTROL"), ("pads", "MPD218")]);
+
+        assert_eq!(inputs_changes(&inputs, &inputs), Vec::new());
+    }
+
+    #[test]
+    fn inputs_are_added_removed_and_replaced() {
+        let running_inputs = midi_inputs(&[("controller", "nanoKONTROL"), ("pads", "MPD218")]);
+        let new_inputs = midi_inputs(&[("pads", "Launchpad"), ("keys", "Keystation")]);
+
+        let messages = inputs_changes(&running_inputs, &new_inputs);
+        assert_eq!(messages.len(), 3);
+        assert!(messages.contains(&Message::RemoveInput("controller".to_owned())));
+        assert!(messages.contains(&Message::AddInput(
+            "pads".to_owned(),
+            new_inputs["pads"].clone()
+        )));
+        assert!(messages.contains(&Message::AddInput(
+            "keys".to_owned(),
+            new_inputs["keys"].clone()
+        )));
+
+        // Removals come first, so that the added inputs never clash with the removed ones
+        assert_eq!(messages[0], Message::RemoveInput("controller".to_owned()));
+    }
+}
@@ -0,0 +1,808 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+const MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 76, 78, 75);
+const MULTICAST_PORT: u16 = 20808;
+
+const DISCOVERY_HEADER: &[u8] = b"_asdp_v\x01";
+const MEASUREMENT_HEADER: &[u8] = b"_link_v\x01";
+
+const ALIVE: u8 = 1;
+const RESPONSE: u8 = 2;
+const BYEBYE: u8 = 3;
+
+const PING: u8 = 1;
+const PONG: u8 = 2;
+
+/// Seconds during which a peer is considered present after its last announcement
+const PEER_TTL: u8 = 5;
+const BROADCAST_INTERVAL: Duration = Duration::from_millis(1000);
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+const MEASUREMENT_SAMPLES: usize = 5;
+const PING_INTERVAL: Duration = Duration::from_millis(50);
+const MEASUREMENT_TIMEOUT: Duration = Duration::from_millis(1000);
+/// Time after which a session that was not joined is measured again
+const REMEASUREMENT_INTERVAL: Duration = Duration::from_secs(30);
+/// Ghost time difference under which two sessions are considered equally old, in microseconds
+const SESSION_EPSILON: i64 = 500_000;
+/// Largest share of the beats played in a frame added or removed to catch up with the session
+/// phase, which sounds like a slight tempo change rather than a jump
+const MAX_PHASE_SLEW: f64 = 0.25;
+
+type NodeId = [u8; 8];
+
+/// Beat offset bringing `beat` towards the phase of `session_beat` within a bar, the shortest way.
+/// The offset is limited to a share of the `beat_diff` beats played since the last frame, so that
+/// the beat never goes back, which would fire timeline cues again.
+pub fn beat_correction(beat: f64, session_beat: f64, beat_diff: f64, bar_length: f64) -> f64 {
+    let phase_error = (beat - session_beat).rem_euclid(bar_length);
+    let correction = if phase_error > bar_length / 2.0 {
+        bar_length - phase_error
+    } else {
+        -phase_error
+    };
+
+    let max_correction = beat_diff.max(0.0) * MAX_PHASE_SLEW;
+    correction.max(-max_correction).min(max_correction)
+}
+
+fn new_node_id() -> NodeId {
+    // Each `RandomState` is seeded differently, which is all the randomness needed here
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    hasher.finish().to_be_bytes()
+}
+
+/// Mapping between the ghost time shared by a session and its beats
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timeline {
+    micros_per_beat: i64,
+    /// Beat at `time_origin`, in millionths of a beat
+    beat_origin: i64,
+    /// Ghost time in microseconds
+    time_origin: i64,
+}
+
+impl Timeline {
+    fn new(bpm: f64, beat: f64, ghost_time: i64) -> Self {
+        Self {
+            micros_per_beat: (60_000_000.0 / bpm.max(1.0)).round() as i64,
+            beat_origin: (beat * 1_000_000.0).round() as i64,
+            time_origin: ghost_time,
+        }
+    }
+
+    fn get_bpm(&self) -> f64 {
+        60_000_000.0 / self.micros_per_beat as f64
+    }
+
+    fn beat_at(&self, ghost_time: i64) -> f64 {
+        self.beat_origin as f64 / 1_000_000.0
+            + (ghost_time - self.time_origin) as f64 / self.micros_per_beat as f64
+    }
+
+    /// Changes the tempo without moving the current beat
+    fn with_tempo(&self, bpm: f64, ghost_time: i64) -> Self {
+        Self::new(bpm, self.beat_at(ghost_time), ghost_time)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(24);
+        value.extend_from_slice(&self.micros_per_beat.to_be_bytes());
+        value.extend_from_slice(&self.beat_origin.to_be_bytes());
+        value.extend_from_slice(&self.time_origin.to_be_bytes());
+        value
+    }
+
+    fn decode(value: &[u8]) -> Option<Self> {
+        let timeline = Self {
+            micros_per_beat: read_i64(value, 0)?,
+            beat_origin: read_i64(value, 8)?,
+            time_origin: read_i64(value, 16)?,
+        };
+
+        if timeline.micros_per_beat > 0 {
+            Some(timeline)
+        } else {
+            None
+        }
+    }
+}
+
+fn read_i64(value: &[u8], offset: usize) -> Option<i64> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(value.get(offset..offset + 8)?);
+    Some(i64::from_be_bytes(bytes))
+}
+
+fn write_entry(buffer: &mut Vec<u8>, key: &[u8; 4], value: &[u8]) {
+    buffer.extend_from_slice(key);
+    buffer.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(value);
+}
+
+fn parse_entries(mut payload: &[u8]) -> HashMap<[u8; 4], &[u8]> {
+    let mut entries = HashMap::new();
+
+    while payload.len() >= 8 {
+        let mut key = [0; 4];
+        key.copy_from_slice(&payload[0..4]);
+
+        let mut length = [0; 4];
+        length.copy_from_slice(&payload[4..8]);
+        let length = u32::from_be_bytes(length) as usize;
+
+        if payload.len() < 8 + length {
+            break;
+        }
+
+        entries.insert(key, &payload[8..8 + length]);
+        payload = &payload[8 + length..];
+    }
+
+    entries
+}
+
+struct Peer {
+    session_id: NodeId,
+    expiry: Instant,
+}
+
+/// Ongoing estimation of the ghost time offset of another session
+struct Measurement {
+    session_id: NodeId,
+    timeline: Timeline,
+    endpoint: SocketAddrV4,
+    samples: Vec<i64>,
+    start_time: Instant,
+    last_ping_time: Option<Instant>,
+}
+
+struct SessionState {
+    node_id: NodeId,
+    session_id: NodeId,
+    timeline: Timeline,
+    /// Difference between the session ghost time and the local host time, in microseconds
+    ghost_offset: i64,
+
+    peers: HashMap<NodeId, Peer>,
+    timeline_changed: bool,
+}
+
+impl SessionState {
+    fn has_session_peers(&self) -> bool {
+        self.peers
+            .values()
+            .any(|peer| peer.session_id == self.session_id)
+    }
+}
+
+/// Joins the Ableton Link session of the local network, sharing one tempo and beat phase
+/// between all peers
+pub struct Link {
+    epoch: Instant,
+    state: Arc<Mutex<SessionState>>,
+
+    running: Arc<AtomicBool>,
+    network_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Link {
+    pub fn start(bpm: f64) -> Result<Self> {
+        let epoch = Instant::now();
+        let node_id = new_node_id();
+
+        let state = Arc::new(Mutex::new(SessionState {
+            node_id,
+            session_id: node_id,
+            timeline: Timeline::new(bpm, 0.0, 0),
+            ghost_offset: 0,
+
+            peers: HashMap::new(),
+            timeline_changed: true,
+        }));
+
+        let multicast_socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
+            .context("Failed to create the Link discovery socket")?;
+        multicast_socket
+            .set_reuse_address(true)
+            .context("Failed to share the Link discovery port")?;
+        #[cfg(unix)]
+        multicast_socket
+            .set_reuse_port(true)
+            .context("Failed to share the Link discovery port")?;
+        multicast_socket
+            .bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MULTICAST_PORT)).into())
+            .context("Failed to bind the Link discovery port")?;
+        multicast_socket
+            .join_multicast_v4(&MULTICAST_ADDRESS, &Ipv4Addr::UNSPECIFIED)
+            .context("Failed to join the Link multicast group")?;
+        let multicast_socket: UdpSocket = multicast_socket.into();
+        multicast_socket.set_nonblocking(true)?;
+
+        let unicast_socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .context("Failed to create the Link measurement socket")?;
+        unicast_socket.set_multicast_loop_v4(true)?;
+        unicast_socket.set_nonblocking(true)?;
+
+        // The address peers reach us at is the one used to reach the multicast group
+        let local_address = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .and_then(|socket| {
+                socket.connect((MULTICAST_ADDRESS, MULTICAST_PORT))?;
+                socket.local_addr()
+            })
+            .map(|address| match address {
+                SocketAddr::V4(address) => *address.ip(),
+                SocketAddr::V6(_) => Ipv4Addr::LOCALHOST,
+            })
+            .unwrap_or(Ipv4Addr::LOCALHOST);
+        let measurement_endpoint =
+            SocketAddrV4::new(local_address, unicast_socket.local_addr()?.port());
+
+        let running = Arc::new(AtomicBool::new(true));
+
+        let network_thread = {
+            let mut session = LinkNetwork {
+                epoch,
+                state: state.clone(),
+                multicast_socket,
+                unicast_socket,
+                measurement_endpoint,
+
+                measurement: None,
+                checked_sessions: HashMap::new(),
+            };
+            let running = running.clone();
+
+            thread::spawn(move || session.run(&running))
+        };
+
+        Ok(Self {
+            epoch,
+            state,
+
+            running,
+            network_thread: Some(network_thread),
+        })
+    }
+
+    fn host_time(&self) -> i64 {
+        self.epoch.elapsed().as_micros() as i64
+    }
+
+    /// Returns the tempo and beat of the session at the current instant, as long as other peers share it
+    pub fn get_session_beat(&self) -> Option<(f64, f64)> {
+        let state = self.state.lock().unwrap();
+        if !state.has_session_peers() {
+            return None;
+        }
+
+        let ghost_time = self.host_time() + state.ghost_offset;
+        Some((state.timeline.get_bpm(), state.timeline.beat_at(ghost_time)))
+    }
+
+    /// Publishes a local tempo change. While alone in the session, the session beat also follows `beat`
+    pub fn set_timeline(&self, bpm: f64, beat: f64) {
+        let mut state = self.state.lock().unwrap();
+        let ghost_time = self.host_time() + state.ghost_offset;
+
+        let timeline = if state.has_session_peers() {
+            if (state.timeline.get_bpm() - bpm).abs() < 1e-3 {
+                return;
+            }
+            state.timeline.with_tempo(bpm, ghost_time)
+        } else {
+            if (state.timeline.get_bpm() - bpm).abs() < 1e-3
+                && (state.timeline.beat_at(ghost_time) - beat).abs() < 1e-2
+            {
+                return;
+            }
+            Timeline::new(bpm, beat, ghost_time)
+        };
+
+        state.timeline = timeline;
+        state.timeline_changed = true;
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+
+        if let Some(network_thread) = self.network_thread.take() {
+            if network_thread.join().is_err() {
+                eprintln!("Link thread did not finish cleanly");
+            }
+        }
+    }
+}
+
+struct LinkNetwork {
+    epoch: Instant,
+    state: Arc<Mutex<SessionState>>,
+    multicast_socket: UdpSocket,
+    unicast_socket: UdpSocket,
+    measurement_endpoint: SocketAddrV4,
+
+    measurement: Option<Measurement>,
+    checked_sessions: HashMap<NodeId, Instant>,
+}
+
+impl LinkNetwork {
+    fn host_time(&self) -> i64 {
+        self.epoch.elapsed().as_micros() as i64
+    }
+
+    fn run(&mut self, running: &AtomicBool) {
+        let mut last_broadcast_time: Option<Instant> = None;
+        let mut buffer = [0; 512];
+
+        while running.load(Ordering::Relaxed) {
+            let now = Instant::now();
+
+            let timeline_changed =
+                std::mem::replace(&mut self.state.lock().unwrap().timeline_changed, false);
+            let broadcast_due = last_broadcast_time
+                .map(|last_broadcast_time| now - last_broadcast_time >= BROADCAST_INTERVAL)
+                .unwrap_or(true);
+            if timeline_changed || broadcast_due {
+                self.send_state(ALIVE, PEER_TTL, (MULTICAST_ADDRESS, MULTICAST_PORT).into());
+                last_broadcast_time = Some(now);
+            }
+
+            self.state
+                .lock()
+                .unwrap()
+                .peers
+                .retain(|_, peer| peer.expiry > now);
+
+            self.update_measurement(now);
+
+            loop {
+                match self.multicast_socket.recv_from(&mut buffer) {
+                    Ok((length, sender)) => self.handle_packet(&buffer[..length], sender),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        eprintln!("Link discovery failed: {:?}", e);
+                        break;
+                    }
+                }
+            }
+
+            loop {
+                match self.unicast_socket.recv_from(&mut buffer) {
+                    Ok((length, sender)) => self.handle_packet(&buffer[..length], sender),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        eprintln!("Link measurement failed: {:?}", e);
+                        break;
+                    }
+                }
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        self.send_state(BYEBYE, 0, (MULTICAST_ADDRESS, MULTICAST_PORT).into());
+    }
+
+    fn send_state(&self, message_type: u8, ttl: u8, target: SocketAddr) {
+        let (node_id, session_id, timeline) = {
+            let state = self.state.lock().unwrap();
+            (state.node_id, state.session_id, state.timeline)
+        };
+
+        let mut message = DISCOVERY_HEADER.to_vec();
+        message.push(message_type);
+        message.push(ttl);
+        message.extend_from_slice(&0u16.to_be_bytes());
+        message.extend_from_slice(&node_id);
+
+        if message_type != BYEBYE {
+            write_entry(&mut message, b"tmln", &timeline.encode());
+            write_entry(&mut message, b"sess", &session_id);
+
+            let mut endpoint = u32::from(*self.measurement_endpoint.ip())
+                .to_be_bytes()
+                .to_vec();
+            endpoint.extend_from_slice(&self.measurement_endpoint.port().to_be_bytes());
+            write_entry(&mut message, b"mep4", &endpoint);
+        }
+
+        if let Err(e) = self.unicast_socket.send_to(&message, target) {
+            eprintln!("Failed to reach Link peers: {:?}", e);
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &[u8], sender: SocketAddr) {
+        if let Some(message) = packet.strip_prefix(DISCOVERY_HEADER) {
+            self.handle_discovery(message, sender);
+        } else if let Some(message) = packet.strip_prefix(MEASUREMENT_HEADER) {
+            self.handle_measurement(message, sender);
+        }
+    }
+
+    fn handle_discovery(&mut self, message: &[u8], sender: SocketAddr) {
+        if message.len() < 12 {
+            return;
+        }
+
+        let message_type = message[0];
+        let ttl = message[1];
+        let mut peer_id = [0; 8];
+        peer_id.copy_from_slice(&message[4..12]);
+
+        let mut state = self.state.lock().unwrap();
+        if peer_id == state.node_id {
+            return;
+        }
+
+        if message_type == BYEBYE {
+            state.peers.remove(&peer_id);
+            return;
+        }
+
+        let entries = parse_entries(&message[12..]);
+        let timeline = entries
+            .get(b"tmln")
+            .and_then(|value| Timeline::decode(value));
+        let session_id = entries.get(b"sess").and_then(|value| {
+            let mut session_id = [0; 8];
+            session_id.copy_from_slice(value.get(0..8)?);
+            Some(session_id)
+        });
+        let measurement_endpoint = entries.get(b"mep4").and_then(|value| {
+            let mut address = [0; 4];
+            address.copy_from_slice(value.get(0..4)?);
+            let mut port = [0; 2];
+            port.copy_from_slice(value.get(4..6)?);
+
+            let address = Ipv4Addr::from(address);
+            // Peers bound to every interface are reached through the address they sent from
+            let address = match sender {
+                SocketAddr::V4(sender) if address.is_unspecified() => *sender.ip(),
+                _ => address,
+            };
+
+            Some(SocketAddrV4::new(address, u16::from_be_bytes(port)))
+        });
+
+        let (timeline, session_id, measurement_endpoint) =
+            match (timeline, session_id, measurement_endpoint) {
+                (Some(timeline), Some(session_id), Some(measurement_endpoint)) => {
+                    (timeline, session_id, measurement_endpoint)
+                }
+                _ => return,
+            };
+
+        state.peers.insert(
+            peer_id,
+            Peer {
+                session_id,
+                expiry: Instant::now() + Duration::from_secs(ttl as u64),
+            },
+        );
+
+        if session_id == state.session_id {
+            if timeline != state.timeline {
+                state.timeline = timeline;
+            }
+        } else if self.measurement.is_none() {
+            let recently_checked = self
+                .checked_sessions
+                .get(&session_id)
+                .map(|check_time| check_time.elapsed() < REMEASUREMENT_INTERVAL)
+                .unwrap_or(false);
+
+            if !recently_checked {
+                self.measurement = Some(Measurement {
+                    session_id,
+                    timeline,
+                    endpoint: measurement_endpoint,
+                    samples: Vec::new(),
+                    start_time: Instant::now(),
+                    last_ping_time: None,
+                });
+            }
+        }
+        drop(state);
+
+        if message_type == ALIVE {
+            self.send_state(RESPONSE, PEER_TTL, sender);
+        }
+    }
+
+    fn handle_measurement(&mut self, message: &[u8], sender: SocketAddr) {
+        let (message_type, payload) = match message.split_first() {
+            Some((message_type, payload)) => (*message_type, payload),
+            None => return,
+        };
+
+        match message_type {
+            PING => {
+                let (session_id, ghost_time) = {
+                    let state = self.state.lock().unwrap();
+                    (state.session_id, self.host_time() + state.ghost_offset)
+                };
+
+                let mut reply = MEASUREMENT_HEADER.to_vec();
+                reply.push(PONG);
+                write_entry(&mut reply, b"sess", &session_id);
+                write_entry(&mut reply, b"__gt", &ghost_time.to_be_bytes());
+                reply.extend_from_slice(payload);
+
+                if let Err(e) = self.unicast_socket.send_to(&reply, sender) {
+                    eprintln!("Failed to answer a Link peer: {:?}", e);
+                }
+            }
+            PONG => {
+                let receive_time = self.host_time();
+
+                if let Some(measurement) = &mut self.measurement {
+                    let entries = parse_entries(payload);
+
+                    let session_id = entries.get(b"sess").and_then(|value| value.get(0..8));
+                    let ghost_time = entries.get(b"__gt").and_then(|value| read_i64(value, 0));
+                    let host_time = entries.get(b"__ht").and_then(|value| read_i64(value, 0));
+
+                    if let (Some(session_id), Some(ghost_time), Some(host_time)) =
+                        (session_id, ghost_time, host_time)
+                    {
+                        if session_id == measurement.session_id {
+                            measurement
+                                .samples
+                                .push(ghost_time - (host_time + receive_time) / 2);
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn update_measurement(&mut self, now: Instant) {
+        let host_time = self.host_time();
+        let measurement = if let Some(measurement) = &mut self.measurement {
+            measurement
+        } else {
+            return;
+        };
+
+        if measurement.samples.len() < MEASUREMENT_SAMPLES
+            && now - measurement.start_time < MEASUREMENT_TIMEOUT
+        {
+            let ping_due = measurement
+                .last_ping_time
+                .map(|last_ping_time| now - last_ping_time >= PING_INTERVAL)
+                .unwrap_or(true);
+
+            if ping_due {
+                let mut ping = MEASUREMENT_HEADER.to_vec();
+                ping.push(PING);
+                write_entry(&mut ping, b"__ht", &host_time.to_be_bytes());
+
+                if let Err(e) = self.unicast_socket.send_to(&ping, measurement.endpoint) {
+                    eprintln!("Failed to measure a Link session: {:?}", e);
+                }
+                measurement.last_ping_time = Some(now);
+            }
+
+            return;
+        }
+
+        let mut measurement = self.measurement.take().unwrap();
+        self.checked_sessions.insert(measurement.session_id, now);
+
+        if measurement.samples.is_empty() {
+            return;
+        }
+
+        measurement.samples.sort_unstable();
+        let ghost_offset = measurement.samples[measurement.samples.len() / 2];
+
+        // The oldest session wins, the one with the lowest identifier in case of doubt
+        let mut state = self.state.lock().unwrap();
+        let ghost_difference = ghost_offset - state.ghost_offset;
+        if ghost_difference > SESSION_EPSILON
+            || (ghost_difference.abs() < SESSION_EPSILON
+                && measurement.session_id < state.session_id)
+        {
+            state.session_id = measurement.session_id;
+            state.timeline = measurement.timeline;
+            state.ghost_offset = ghost_offset;
+            state.timeline_changed = true;
+
+            println!(
+                "Joined a Link session at {:.2} BPM",
+                state.timeline.get_bpm()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ALIVE announcement of a peer at 120 BPM, as sent on the discovery multicast group
+    #[rustfmt::skip]
+    const ALIVE_PACKET: &[u8] = &[
+        // Header, message type, TTL, group and node identifier
+        b'_', b'a', b's', b'd', b'p', b'_', b'v', 0x01,
+        0x01, 0x05, 0x00, 0x00,
+        0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11,
+        // Timeline: 500000 µs per beat, beat 0 at ghost time 1000000000 µs
+        b't', b'm', b'l', b'n', 0x00, 0x00, 0x00, 0x18,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0xa1, 0x20,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x3b, 0x9a, 0xca, 0x00,
+        // Session identifier
+        b's', b'e', b's', b's', 0x00, 0x00, 0x00, 0x08,
+        0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0,
+        // Measurement endpoint: 192.168.1.10:20000
+        b'm', b'e', b'p', b'4', 0x00, 0x00, 0x00, 0x06,
+        0xc0, 0xa8, 0x01, 0x0a, 0x4e, 0x20,
+    ];
+
+    #[test]
+    fn timeline_round_trips_through_its_encoding() {
+        let timeline = Timeline::new(128.0, 4.5, 1_000_000);
+        let value = timeline.encode();
+
+        assert_eq!(value.len(), 24);
+        assert_eq!(Timeline::decode(&value), Some(timeline));
+
+        assert_eq!(Timeline::decode(&value[..16]), None);
+        assert_eq!(Timeline::decode(&[0; 24]), None);
+    }
+
+    #[test]
+    fn entries_are_read_from_a_captured_packet() {
+        let message = ALIVE_PACKET.strip_prefix(DISCOVERY_HEADER).unwrap();
+        assert_eq!(message[0], ALIVE);
+
+        let entries = parse_entries(&message[12..]);
+        assert_eq!(entries.len(), 3);
+
+        let timeline = Timeline::decode(entries[b"tmln"]).unwrap();
+        assert_eq!(timeline.micros_per_beat, 500_000);
+        assert_eq!(timeline.time_origin, 1_000_000_000);
+        assert_eq!(
+            entries[b"sess"],
+            &[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]
+        );
+        assert_eq!(entries[b"mep4"], &[0xc0, 0xa8, 0x01, 0x0a, 0x4e, 0x20]);
+
+        // A truncated entry ends the parsing, keeping the entries before it
+        let entries = parse_entries(&message[12..message.len() - 2]);
+        assert_eq!(entries.len(), 2);
+        assert!(!entries.contains_key(b"mep4"));
+    }
+
+    #[test]
+    fn entries_written_are_read_back() {
+        let mut payload = Vec::new();
+        write_entry(&mut payload, b"__gt", &42i64.to_be_bytes());
+        write_entry(&mut payload, b"sess", &[7; 8]);
+
+        let entries = parse_entries(&payload);
+        assert_eq!(read_i64(entries[b"__gt"], 0), Some(42));
+        assert_eq!(entries[b"sess"], &[7; 8]);
+    }
+
+    #[test]
+    fn beats_follow_the_ghost_time() {
+        let timeline = Timeline::new(120.0, 8.0, 2_000_000);
+
+        assert_eq!(timeline.get_bpm(), 120.0);
+        assert_eq!(timeline.beat_at(2_000_000), 8.0);
+        assert_eq!(timeline.beat_at(3_000_000), 10.0);
+        assert_eq!(timeline.beat_at(1_750_000), 7.5);
+
+        // A tempo change keeps the current beat, later beats going at the new tempo
+        let timeline = timeline.with_tempo(60.0, 3_000_000);
+        assert_eq!(timeline.get_bpm(), 60.0);
+        assert_eq!(timeline.beat_at(3_000_000), 10.0);
+        assert_eq!(timeline.beat_at(4_000_000), 11.0);
+    }
+    #[test]
+    fn the_phase_is_caught_up_the_shortest_way() {
+        // A quarter beat late or early is caught up within a frame playing a whole beat
+        assert_eq!(beat_correction(3.75, 4.0, 1.0, 4.0), 0.25);
+        assert_eq!(beat_correction(4.25, 4.0, 1.0, 4.0), -0.25);
+        // Only the phase within a bar matters
+        assert_eq!(beat_correction(3.75, 12.0, 1.0, 4.0), 0.25);
+        assert_eq!(beat_correction(0.0, 3.75, 1.0, 4.0), -0.25);
+    }
+
+    #[test]
+    fn the_phase_is_slewed_rather_than_snapped() {
+        // Half a bar away, the beat moves at most a quarter faster or slower
+        assert_eq!(beat_correction(6.0, 4.0, 0.1, 4.0), -0.025);
+        assert_eq!(beat_correction(2.5, 4.0, 0.1, 4.0), 0.025);
+
+        let (mut beat, mut session_beat) = (1.0, 0.0);
+        for _ in 0..1000 {
+            let beat_diff = 0.05;
+            session_beat += beat_diff;
+            let correction = beat_correction(beat + beat_diff, session_beat, beat_diff, 4.0);
+
+            assert!(beat_diff + correction > 0.0);
+            beat += beat_diff + correction;
+        }
+        assert!((beat - session_beat).abs() < 1e-9);
+
+        // A paused beat stays where it is
+        assert_eq!(beat_correction(6.0, 4.0, 0.0, 4.0), 0.0);
+    }
+
+    /// Waits until `condition` holds, for up to a few seconds
+    fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        false
+    }
+
+    #[test]
+    fn sessions_share_tempo_and_timeline_over_loopback() {
+        let first_link = match Link::start(120.0) {
+            Ok(link) => link,
+            Err(e) => {
+                // Hosts without multicast, such as some sandboxes, cannot run Link at all
+                eprintln!("Skipping the Link loopback test: {:?}", e);
+                return;
+            }
+        };
+        // Sessions started closer than this are equally old, the winner then being picked by identifier
+        thread::sleep(Duration::from_micros(2 * SESSION_EPSILON as u64));
+        let second_link = Link::start(90.0).unwrap();
+
+        // The second instance joins the older session, taking its tempo
+        assert!(wait_for(|| {
+            match (
+                first_link.get_session_beat(),
+                second_link.get_session_beat(),
+            ) {
+                (Some((first_bpm, _)), Some((second_bpm, _))) => {
+                    (first_bpm - 120.0).abs() < 1e-3 && (second_bpm - 120.0).abs() < 1e-3
+                }
+                _ => false,
+            }
+        }));
+
+        let (_, first_beat) = first_link.get_session_beat().unwrap();
+        let (_, second_beat) = second_link.get_session_beat().unwrap();
+        assert!((first_beat - second_beat).abs() < 0.05);
+
+        // A tempo change on either side reaches the other one, keeping the beats aligned
+        second_link.set_timeline(140.0, second_beat);
+        assert!(wait_for(|| {
+            first_link
+                .get_session_beat()
+                .map(|(bpm, _)| (bpm - 140.0).abs() < 1e-3)
+                .unwrap_or(false)
+        }));
+
+        let (_, first_beat) = first_link.get_session_beat().unwrap();
+        let (_, second_beat) = second_link.get_session_beat().unwrap();
+        assert!((first_beat - second_beat).abs() < 0.05);
+    }
+}
@@ -0,0 +1,625 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_com::data::Message;
+
+use crate::command::Command;
+use crate::sync::TempoOrder;
+
+const CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const CONTINUE: u8 = 0xFB;
+const STOP: u8 = 0xFC;
+const SONG_POSITION: u8 = 0xF2;
+const QUARTER_FRAME: u8 = 0xF1;
+const SYSEX_START: u8 = 0xF0;
+
+const TICKS_PER_BEAT: f64 = 24.0;
+const TICKS_PER_SIXTEENTH: u64 = 6;
+/// Number of clock ticks the incoming tempo is averaged over
+const TEMPO_TICK_COUNT: usize = 24;
+/// Time without clock or timecode after which the source is considered gone
+const CLOCK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Frame rate of the emitted timecode, matching the rate code below
+const MTC_FRAME_RATE: f64 = 25.0;
+const MTC_RATE_CODE: u8 = 1;
+const MTC_FRAME_RATES: [f64; 4] = [24.0, 25.0, 29.97, 30.0];
+const SEND_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MidiClockMode {
+    /// Follows the clock, transport and timecode received on the port
+    Input,
+    /// Sends the wvr beat clock, transport and timecode to the port
+    Output,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MidiClockConfig {
+    /// Part of the name of the MIDI port to use
+    pub port: String,
+    pub mode: MidiClockMode,
+    /// Also sends MIDI timecode when acting as output
+    #[serde(default)]
+    pub mtc: bool,
+}
+
+fn find_port<T>(ports: Vec<T>, port_names: Vec<String>, port_name: &str) -> Result<T> {
+    ports
+        .into_iter()
+        .zip(port_names.iter())
+        .find(|(_, name)| name.contains(port_name))
+        .map(|(port, _)| port)
+        .context(format!(
+            "Could not find MIDI port {:?}, available ports are {:?}",
+            port_name, port_names
+        ))
+}
+
+#[derive(Default)]
+struct InputState {
+    running: bool,
+    tick_count: u64,
+    tick_times: VecDeque<Instant>,
+
+    timecode_pieces: [u8; 8],
+    timecode: Option<(f64, Instant)>,
+}
+
+impl InputState {
+    /// Applies a message received at `now`
+    fn handle_message(&mut self, message: &[u8], now: Instant, order_sender: &Sender<Command>) {
+        match message {
+            // Ticks of a stopped sequencer are not counted
+            [CLOCK] if self.running => {
+                self.tick_count += 1;
+                self.tick_times.push_back(now);
+                while self.tick_times.len() > TEMPO_TICK_COUNT + 1 {
+                    self.tick_times.pop_front();
+                }
+            }
+            // A sequencer starts over from the top, and continues from where it stopped
+            [START] => {
+                self.running = true;
+                self.tick_count = 0;
+                self.tick_times.clear();
+                let _ = order_sender.send(Command::Tempo(TempoOrder::Restart));
+                let _ = order_sender.send(Message::Start.into());
+            }
+            [CONTINUE] => {
+                self.running = true;
+                self.tick_times.clear();
+                let _ = order_sender.send(Message::Start.into());
+            }
+            [STOP] => {
+                self.running = false;
+                // `Message::Stop` ends wvr, a stopped sequencer only pauses it
+                let _ = order_sender.send(Message::Pause.into());
+            }
+            [SONG_POSITION, lsb, msb] => {
+                let sixteenths = ((*msb as u64) << 7) | *lsb as u64;
+                self.tick_count = sixteenths * TICKS_PER_SIXTEENTH;
+            }
+            [QUARTER_FRAME, data] => {
+                let piece = ((data >> 4) & 0x07) as usize;
+                self.timecode_pieces[piece] = data & 0x0F;
+
+                if piece == 7 {
+                    let pieces = &self.timecode_pieces;
+                    let frames = pieces[0] | (pieces[1] << 4);
+                    let seconds = pieces[2] | (pieces[3] << 4);
+                    let minutes = pieces[4] | (pieces[5] << 4);
+                    let hours = pieces[6] | ((pieces[7] & 0x01) << 4);
+                    let frame_rate = MTC_FRAME_RATES[((pieces[7] >> 1) & 0x03) as usize];
+
+                    // The last piece arrives two frames after the one it describes
+                    self.timecode = Some((
+                        hours as f64 * 3600.0
+                            + minutes as f64 * 60.0
+                            + seconds as f64
+                            + (frames as f64 + 2.0) / frame_rate,
+                        now,
+                    ));
+                }
+            }
+            [SYSEX_START, 0x7F, _, 0x01, 0x01, hours, minutes, seconds, frames, ..] => {
+                let frame_rate = MTC_FRAME_RATES[((hours >> 5) & 0x03) as usize];
+
+                self.timecode = Some((
+                    (hours & 0x1F) as f64 * 3600.0
+                        + *minutes as f64 * 60.0
+                        + *seconds as f64
+                        + *frames as f64 / frame_rate,
+                    now,
+                ));
+            }
+            _ => (),
+        }
+    }
+
+    /// Tempo and beat of the clock source at `now`, while it is running
+    fn clock(&self, now: Instant) -> Option<(f64, f64)> {
+        if !self.running || self.tick_times.len() < 2 {
+            return None;
+        }
+
+        let first_tick = self.tick_times.front().unwrap();
+        let last_tick = self.tick_times.back().unwrap();
+        let since_last_tick = now.saturating_duration_since(*last_tick);
+        if since_last_tick > CLOCK_TIMEOUT {
+            return None;
+        }
+
+        let tick_duration =
+            (*last_tick - *first_tick).as_secs_f64() / (self.tick_times.len() - 1) as f64;
+        if tick_duration <= 0.0 {
+            return None;
+        }
+
+        // Ticks are interpolated, never running ahead of the next one
+        let tick_progress = (since_last_tick.as_secs_f64() / tick_duration).min(1.0);
+
+        Some((
+            60.0 / (tick_duration * TICKS_PER_BEAT),
+            (self.tick_count as f64 + tick_progress) / TICKS_PER_BEAT,
+        ))
+    }
+
+    /// Time given by the incoming timecode at `now`, in seconds
+    fn timecode(&self, now: Instant) -> Option<f64> {
+        match self.timecode {
+            Some((time, update_time)) => {
+                let since_update = now.saturating_duration_since(update_time);
+                if since_update < CLOCK_TIMEOUT {
+                    Some(time + since_update.as_secs_f64())
+                } else {
+                    None
+                }
+            }
+            None => None,
+        }
+    }
+}
+
+/// Beat clock driven by the MIDI clock and timecode of an external sequencer
+pub struct MidiClockInput {
+    state: Arc<Mutex<InputState>>,
+    _connection: MidiInputConnection<()>,
+}
+
+impl MidiClockInput {
+    pub fn connect(port_name: &str, order_sender: Sender<Command>) -> Result<Self> {
+        let mut midi_input =
+            MidiInput::new("wvr clock").context("Failed to initialize MIDI input")?;
+        // Clock, timecode and system exclusive messages are all needed here
+        midi_input.ignore(midir::Ignore::ActiveSense);
+
+        let ports = midi_input.ports();
+        let port_names = ports
+            .iter()
+            .map(|port| midi_input.port_name(port).unwrap_or_default())
+            .collect();
+        let port = find_port(ports, port_names, port_name)?;
+
+        let state = Arc::new(Mutex::new(InputState::default()));
+        let connection = {
+            let state = state.clone();
+
+            midi_input
+                .connect(
+                    &port,
+                    "wvr clock input",
+                    move |_, message, _| {
+                        state
+                            .lock()
+                            .unwrap()
+                            .handle_message(message, Instant::now(), &order_sender)
+                    },
+                    (),
+                )
+                .map_err(|e| anyhow::anyhow!("{:}", e))
+                .context(format!("Failed to connect to MIDI port {:?}", port_name))?
+        };
+
+        Ok(Self {
+            state,
+            _connection: connection,
+        })
+    }
+
+    /// Returns the tempo and beat of the clock source at the current instant, while it is running
+    pub fn get_clock(&self) -> Option<(f64, f64)> {
+        self.state.lock().unwrap().clock(Instant::now())
+    }
+
+    /// Returns the time given by the incoming timecode, in seconds
+    pub fn get_timecode(&self) -> Option<f64> {
+        self.state.lock().unwrap().timecode(Instant::now())
+    }
+}
+
+struct OutputState {
+    bpm: f64,
+    beat: f64,
+    time: f64,
+    update_time: Instant,
+    playing: bool,
+}
+
+impl OutputState {
+    /// Extrapolates the beat and time, the render loop updating them only once per frame
+    fn get_position(&self) -> (f64, f64) {
+        if self.playing {
+            let elapsed = self.update_time.elapsed().as_secs_f64();
+            (self.beat + elapsed * self.bpm / 60.0, self.time + elapsed)
+        } else {
+            (self.beat, self.time)
+        }
+    }
+}
+
+/// Sends the wvr beat clock to an external device from a dedicated thread
+pub struct MidiClockOutput {
+    state: Arc<Mutex<OutputState>>,
+
+    running: Arc<AtomicBool>,
+    clock_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MidiClockOutput {
+    pub fn connect(port_name: &str, mtc: bool) -> Result<Self> {
+        let midi_output =
+            MidiOutput::new("wvr clock").context("Failed to initialize MIDI output")?;
+
+        let ports = midi_output.ports();
+        let port_names = ports
+            .iter()
+            .map(|port| midi_output.port_name(port).unwrap_or_default())
+            .collect();
+        let port = find_port(ports, port_names, port_name)?;
+
+        let connection = midi_output
+            .connect(&port, "wvr clock output")
+            .map_err(|e| anyhow::anyhow!("{:}", e))
+            .context(format!("Failed to connect to MIDI port {:?}", port_name))?;
+
+        let state = Arc::new(Mutex::new(OutputState {
+            bpm: 120.0,
+            beat: 0.0,
+            time: 0.0,
+            update_time: Instant::now(),
+            playing: false,
+        }));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let clock_thread = {
+            let state = state.clone();
+            let running = running.clone();
+
+            thread::spawn(move || send_clock(connection, &state, &running, mtc))
+        };
+
+        Ok(Self {
+            state,
+
+            running,
+            clock_thread: Some(clock_thread),
+        })
+    }
+
+    pub fn set_position(&self, bpm: f64, beat: f64, time: f64, playing: bool) {
+        let mut state = self.state.lock().unwrap();
+
+        state.bpm = bpm;
+        state.beat = beat;
+        state.time = time;
+        state.update_time = Instant::now();
+        state.playing = playing;
+    }
+}
+
+impl Drop for MidiClockOutput {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+
+        if let Some(clock_thread) = self.clock_thread.take() {
+            if clock_thread.join().is_err() {
+                eprintln!("MIDI clock thread did not finish cleanly");
+            }
+        }
+    }
+}
+
+fn send(connection: &mut MidiOutputConnection, message: &[u8]) {
+    if let Err(e) = connection.send(message) {
+        eprintln!("Failed to send MIDI clock: {:?}", e);
+    }
+}
+
+fn send_song_position(connection: &mut MidiOutputConnection, beat: f64) -> u64 {
+    let sixteenths = (beat.max(0.0) * 4.0).floor() as u64 & 0x3FFF;
+    send(
+        connection,
+        &[
+            SONG_POSITION,
+            (sixteenths & 0x7F) as u8,
+            ((sixteenths >> 7) & 0x7F) as u8,
+        ],
+    );
+
+    sixteenths * TICKS_PER_SIXTEENTH
+}
+
+fn timecode_piece(quarter_frame: u64) -> u8 {
+    // The eight pieces of a sequence describe the frame at which it started
+    let frame_index = (quarter_frame / 8) * 2;
+    let frame_rate = MTC_FRAME_RATE as u64;
+
+    let frames = frame_index % frame_rate;
+    let seconds = (frame_index / frame_rate) % 60;
+    let minutes = (frame_index / (frame_rate * 60)) % 60;
+    let hours = (frame_index / (frame_rate * 3600)) % 24;
+
+    let piece = quarter_frame % 8;
+    let value = match piece {
+        0 => frames & 0x0F,
+        1 => frames >> 4,
+        2 => seconds & 0x0F,
+        3 => seconds >> 4,
+        4 => minutes & 0x0F,
+        5 => minutes >> 4,
+        6 => hours & 0x0F,
+        _ => (hours >> 4) | ((MTC_RATE_CODE as u64) << 1),
+    };
+
+    ((piece << 4) | value) as u8
+}
+
+fn send_clock(
+    mut connection: MidiOutputConnection,
+    state: &Mutex<OutputState>,
+    running: &AtomicBool,
+    mtc: bool,
+) {
+    let mut was_playing = false;
+    let mut sent_ticks = 0;
+    let mut sent_quarter_frames = 0;
+
+    while running.load(Ordering::Relaxed) {
+        let ((beat, time), playing) = {
+            let state = state.lock().unwrap();
+            (state.get_position(), state.playing)
+        };
+
+        let target_ticks = (beat.max(0.0) * TICKS_PER_BEAT).floor() as u64;
+        let target_quarter_frames = (time.max(0.0) * MTC_FRAME_RATE * 4.0).floor() as u64;
+
+        if playing && !was_playing {
+            sent_ticks = send_song_position(&mut connection, beat);
+            send(
+                &mut connection,
+                &[if sent_ticks == 0 { START } else { CONTINUE }],
+            );
+            sent_quarter_frames = target_quarter_frames - target_quarter_frames % 8;
+        } else if !playing && was_playing {
+            send(&mut connection, &[STOP]);
+        }
+        was_playing = playing;
+
+        if playing {
+            // Jumps in the beat, like a resync, are announced instead of being caught up on
+            if target_ticks < sent_ticks || target_ticks - sent_ticks > TICKS_PER_BEAT as u64 {
+                send(&mut connection, &[STOP]);
+                sent_ticks = send_song_position(&mut connection, beat);
+                send(&mut connection, &[CONTINUE]);
+            }
+
+            while sent_ticks < target_ticks {
+                send(&mut connection, &[CLOCK]);
+                sent_ticks += 1;
+            }
+
+            if mtc {
+                if target_quarter_frames < sent_quarter_frames
+                    || target_quarter_frames - sent_quarter_frames > 8
+                {
+                    sent_quarter_frames = target_quarter_frames - target_quarter_frames % 8;
+                }
+
+                while sent_quarter_frames < target_quarter_frames {
+                    send(
+                        &mut connection,
+                        &[QUARTER_FRAME, timecode_piece(sent_quarter_frames)],
+                    );
+                    sent_quarter_frames += 1;
+                }
+            }
+        }
+
+        thread::sleep(SEND_INTERVAL);
+    }
+
+    if was_playing {
+        send(&mut connection, &[STOP]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// Duration of a clock tick at 120 BPM
+    const TICK_DURATION: Duration = Duration::from_micros(500_000 / 24);
+
+    struct ClockSource {
+        state: InputState,
+        time: Instant,
+        order_sender: Sender<Command>,
+        order_receiver: Receiver<Command>,
+    }
+
+    impl ClockSource {
+        fn new() -> Self {
+            let (order_sender, order_receiver) = channel();
+            Self {
+                state: InputState::default(),
+                time: Instant::now(),
+                order_sender,
+                order_receiver,
+            }
+        }
+
+        fn send(&mut self, message: &[u8]) {
+            self.state
+                .handle_message(message, self.time, &self.order_sender);
+        }
+
+        fn send_ticks(&mut self, tick_count: usize) {
+            for _ in 0..tick_count {
+                self.time += TICK_DURATION;
+                self.send(&[CLOCK]);
+            }
+        }
+
+        fn orders(&self) -> Vec<Command> {
+            self.order_receiver.try_iter().collect()
+        }
+    }
+
+    fn assert_clock(clock: Option<(f64, f64)>, expected_bpm: f64, expected_beat: f64) {
+        let (bpm, beat) = clock.unwrap();
+        // The tick duration is rounded to the microsecond
+        assert!((bpm - expected_bpm).abs() < 1e-2, "{:} BPM", bpm);
+        assert!((beat - expected_beat).abs() < 1e-3, "beat {:}", beat);
+    }
+
+    #[test]
+    fn clock_ticks_give_the_tempo_and_beat() {
+        let mut clock_source = ClockSource::new();
+
+        // Ticks of a stopped sequencer are not counted
+        clock_source.send_ticks(12);
+        assert_eq!(clock_source.state.clock(clock_source.time), None);
+
+        clock_source.send(&[START]);
+        let orders = clock_source.orders();
+        assert!(matches!(
+            orders.as_slice(),
+            [
+                Command::Tempo(TempoOrder::Restart),
+                Command::Message(Message::Start)
+            ]
+        ));
+
+        clock_source.send_ticks(48);
+        assert_clock(clock_source.state.clock(clock_source.time), 120.0, 2.0);
+
+        // Between two ticks the beat is interpolated, without passing the next tick
+        let time = clock_source.time;
+        assert_clock(
+            clock_source.state.clock(time + TICK_DURATION / 2),
+            120.0,
+            2.0 + 0.5 / 24.0,
+        );
+        assert_clock(
+            clock_source.state.clock(time + TICK_DURATION * 3),
+            120.0,
+            2.0 + 1.0 / 24.0,
+        );
+        assert_eq!(clock_source.state.clock(time + CLOCK_TIMEOUT * 2), None);
+
+        clock_source.send(&[STOP]);
+        assert!(matches!(
+            clock_source.orders().as_slice(),
+            [Command::Message(Message::Pause)]
+        ));
+        assert_eq!(clock_source.state.clock(clock_source.time), None);
+    }
+
+    #[test]
+    fn song_position_moves_the_beat() {
+        let mut clock_source = ClockSource::new();
+
+        // 136 sixteenth notes, split over two 7 bit values
+        clock_source.send(&[SONG_POSITION, 8, 1]);
+        clock_source.send(&[CONTINUE]);
+        assert!(matches!(
+            clock_source.orders().as_slice(),
+            [Command::Message(Message::Start)]
+        ));
+
+        clock_source.send_ticks(3);
+        assert_clock(
+            clock_source.state.clock(clock_source.time),
+            120.0,
+            34.0 + 3.0 / 24.0,
+        );
+    }
+
+    #[test]
+    fn quarter_frames_sent_are_read_back() {
+        // Frame 2000 at 25 fps, and 1 hour 2 minutes and 5.04 seconds
+        for (frame_index, expected_time) in &[(2000, 80.0), (93126, 3725.04)] {
+            let mut clock_source = ClockSource::new();
+            let first_quarter_frame = frame_index / 2 * 8;
+            for quarter_frame in first_quarter_frame..first_quarter_frame + 8 {
+                assert_eq!(clock_source.state.timecode(clock_source.time), None);
+                clock_source.send(&[QUARTER_FRAME, timecode_piece(quarter_frame)]);
+            }
+
+            // The sequence is complete two frames after the one it describes
+            let timecode = clock_source.state.timecode(clock_source.time).unwrap();
+            assert!((timecode - (expected_time + 2.0 / 25.0)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn full_frames_set_the_timecode() {
+        let mut clock_source = ClockSource::new();
+
+        // 01:02:03, frame 10 at 25 fps, the rate being given by bits 5 and 6 of the hours
+        clock_source.send(&[
+            SYSEX_START,
+            0x7F,
+            0x7F,
+            0x01,
+            0x01,
+            1 | (1 << 5),
+            2,
+            3,
+            10,
+            0xF7,
+        ]);
+        let timecode = clock_source.state.timecode(clock_source.time).unwrap();
+        assert!((timecode - (3723.0 + 10.0 / 25.0)).abs() < 1e-9);
+
+        // The timecode runs on from the last frame received, until it times out
+        let timecode = clock_source
+            .state
+            .timecode(clock_source.time + Duration::from_millis(100))
+            .unwrap();
+        assert!((timecode - 3723.5).abs() < 1e-9);
+        assert_eq!(
+            clock_source
+                .state
+                .timecode(clock_source.time + CLOCK_TIMEOUT),
+            None
+        );
+
+        // Frame 15 at 30 fps
+        clock_source.send(&[SYSEX_START, 0x7F, 0x7F, 0x01, 0x01, 3 << 5, 0, 0, 15, 0xF7]);
+        let timecode = clock_source.state.timecode(clock_source.time).unwrap();
+        assert!((timecode - 0.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use serde_derive::{Deserialize, Serialize};
+
+pub mod link;
+pub mod midi_clock;
+
+use midi_clock::MidiClockConfig;
+
+/// Number of taps the tempo is averaged over
+const TAP_HISTORY_LENGTH: usize = 8;
+/// Time after which a tap starts a new sequence instead of continuing the current one, in seconds
+const TAP_TIMEOUT: f64 = 2.0;
+/// Beat offset applied by a single nudge
+pub const NUDGE_STEP: f64 = 1.0 / 16.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    pub beats_per_bar: usize,
+    /// Follows the tempo and beat phase of the Ableton Link session of the local network
+    pub link: bool,
+    pub midi_clock: Option<MidiClockConfig>,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            beats_per_bar: 4,
+            link: false,
+            midi_clock: None,
+        }
+    }
+}
+
+/// Manual adjustments of the tempo and of the beat phase, as done when syncing to a live booth
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum TempoOrder {
+    /// Marks the current instant as a beat, the tempo following the interval between taps
+    Tap,
+    /// Shifts the beat phase by the given number of beats
+    Nudge(f64),
+    /// Makes the current instant the first beat of a bar
+    ResyncToBar,
+    /// Moves the animation back to its start, as a sequencer starting over does
+    Restart,
+    SetBeatsPerBar(usize),
+}
+
+/// Estimates a tempo from the intervals between successive taps
+#[derive(Debug, Default)]
+pub struct TapTempo {
+    taps: VecDeque<Instant>,
+}
+
+impl TapTempo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a tap and returns the resulting tempo once at least two taps are known
+    pub fn tap(&mut self, tap_time: Instant) -> Option<f64> {
+        if let Some(last_tap) = self.taps.back() {
+            if (tap_time - *last_tap).as_secs_f64() > TAP_TIMEOUT {
+                self.taps.clear();
+            }
+        }
+
+        self.taps.push_back(tap_time);
+        while self.taps.len() > TAP_HISTORY_LENGTH {
+            self.taps.pop_front();
+        }
+
+        if self.taps.len() < 2 {
+            return None;
+        }
+
+        let first_tap = self.taps.front().unwrap();
+        let last_tap = self.taps.back().unwrap();
+        let mean_interval = (*last_tap - *first_tap).as_secs_f64() / (self.taps.len() - 1) as f64;
+
+        if mean_interval > 0.0 {
+            Some(60.0 / mean_interval)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn assert_tempo(tempo: Option<f64>, expected_tempo: f64) {
+        let tempo = tempo.expect("No tempo estimated");
+        assert!(
+            (tempo - expected_tempo).abs() < 1e-6,
+            "Estimated {:} instead of {:}",
+            tempo,
+            expected_tempo
+        );
+    }
+
+    #[test]
+    fn a_single_tap_gives_no_tempo() {
+        let mut tap_tempo = TapTempo::new();
+
+        assert_eq!(tap_tempo.tap(Instant::now()), None);
+    }
+
+    #[test]
+    fn the_tempo_follows_the_mean_interval() {
+        let mut tap_tempo = TapTempo::new();
+        let start = Instant::now();
+
+        tap_tempo.tap(start);
+        assert_tempo(tap_tempo.tap(start + Duration::from_millis(500)), 120.0);
+        assert_tempo(
+            tap_tempo.tap(start + Duration::from_millis(1100)),
+            109.090_909,
+        );
+    }
+
+    #[test]
+    fn only_the_last_taps_are_averaged() {
+        let mut tap_tempo = TapTempo::new();
+        let start = Instant::now();
+
+        // A slow first interval, forgotten once enough taps followed
+        tap_tempo.tap(start);
+        let mut tempo = None;
+        for tap_index in 0..TAP_HISTORY_LENGTH {
+            tempo = tap_tempo.tap(start + Duration::from_millis(1500 + 500 * tap_index as u64));
+        }
+
+        assert_tempo(tempo, 120.0);
+    }
+
+    #[test]
+    fn a_long_pause_starts_a_new_sequence() {
+        let mut tap_tempo = TapTempo::new();
+        let start = Instant::now();
+
+        tap_tempo.tap(start);
+        tap_tempo.tap(start + Duration::from_millis(500));
+        assert_eq!(tap_tempo.tap(start + Duration::from_secs(5)), None);
+        assert_tempo(tap_tempo.tap(start + Duration::from_millis(5400)), 150.0);
+    }
+}
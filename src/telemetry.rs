@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_com::data::Message;
+use wvr_data::config::project::ProjectConfig;
+use wvr_data::config::rendering::RenderStageConfig;
+use wvr_data::types::DataHolder;
+
+use crate::audio::AudioInputConfig;
+use crate::command::Command;
+
+/// Time after which a client that does not read what is sent to it gets disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub ip: String,
+    pub port: u16,
+    pub enable: bool,
+    /// Time between two state snapshots, in seconds
+    pub state_interval: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            ip: "127.0.0.1".to_owned(),
+            port: 3001,
+            enable: false,
+            state_interval: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageState {
+    pub name: String,
+    pub filter: String,
+    /// Current variable values, automations applied
+    pub variables: HashMap<String, DataHolder>,
+}
+
+impl StageState {
+    pub fn from_config(render_stage_config: &RenderStageConfig, beat: f64) -> Self {
+        Self {
+            name: render_stage_config.name.clone(),
+            filter: render_stage_config.filter.clone(),
+            variables: render_stage_config
+                .variables
+                .iter()
+                .map(|(variable_name, (variable_value, variable_automation))| {
+                    (
+                        variable_name.clone(),
+                        variable_automation
+                            .apply(variable_value, beat)
+                            .unwrap_or_else(|| variable_value.clone()),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EngineState {
+    pub frame_count: usize,
+    pub time: f64,
+    pub beat: f64,
+    pub bpm: f64,
+    pub fps: f64,
+    pub playing: bool,
+    pub recording: bool,
+    pub resolution: (usize, usize),
+    pub render_chain: Vec<StageState>,
+    pub final_stage: StageState,
+    pub inputs: Vec<String>,
+    pub audio_inputs: Vec<String>,
+    /// Current values of the project variables, automations applied
+    pub variables: HashMap<String, DataHolder>,
+}
+
+/// Part of the live state a client can ask for
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Query {
+    /// The project config as edited since startup, along with the engine state
+    Project,
+    State,
+}
+
+/// Where the answer to a query goes
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum QueryClient {
+    /// A telemetry client, by the identifier it got when connecting
+    Telemetry(usize),
+    /// The address an OSC query was sent from
+    Osc(SocketAddr),
+}
+
+/// Request sent by a client as a line of JSON, such as `{"query": "Project"}`
+#[derive(Debug, Deserialize)]
+struct QueryRequest {
+    query: Query,
+}
+
+/// Messages sent to the telemetry clients, one JSON object per line
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum TelemetryEvent {
+    State(EngineState),
+    /// An order applied by the engine
+    Order {
+        order: Command,
+    },
+    /// A change applied to the running project, be it ordered, mapped from an input, played
+    /// by the timeline or a crossfade, undone or reloaded
+    Change {
+        message: Message,
+    },
+    Error {
+        message: String,
+    },
+    Project {
+        config: ProjectConfig,
+        /// Audio inputs are not part of the project config
+        audio_inputs: HashMap<String, AudioInputConfig>,
+        state: EngineState,
+    },
+}
+
+/// Streams the engine state and events to every client connected over TCP, and forwards
+/// the queries of the clients to the engine
+pub struct TelemetryServer {
+    /// Events along with the client they are meant for, `None` sending them to every client
+    event_sender: Sender<(Option<usize>, String)>,
+
+    state_interval: Duration,
+    last_state_time: Option<Instant>,
+}
+
+impl TelemetryServer {
+    pub fn new(telemetry_config: &TelemetryConfig, order_sender: Sender<Command>) -> Result<Self> {
+        let listener = TcpListener::bind((telemetry_config.ip.as_str(), telemetry_config.port))
+            .context(format!(
+                "Failed to start the telemetry server on {:}:{:}",
+                telemetry_config.ip, telemetry_config.port
+            ))?;
+        listener.set_nonblocking(true)?;
+
+        let (event_sender, event_receiver) = channel::<(Option<usize>, String)>();
+
+        thread::spawn(move || {
+            let mut clients: HashMap<usize, TcpStream> = HashMap::new();
+            let mut next_client_id = 0;
+
+            loop {
+                loop {
+                    match listener.accept() {
+                        Ok((client, _)) => {
+                            let client_reader = client.try_clone();
+                            if let (Ok(()), Ok(()), Ok(client_reader)) = (
+                                client.set_nonblocking(false),
+                                client.set_write_timeout(Some(WRITE_TIMEOUT)),
+                                client_reader,
+                            ) {
+                                let client_id = next_client_id;
+                                next_client_id += 1;
+
+                                clients.insert(client_id, client);
+                                read_queries(client_id, client_reader, order_sender.clone());
+                            }
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            eprintln!("Failed to accept a telemetry client: {:?}", e);
+                            break;
+                        }
+                    }
+                }
+
+                // The loop ends once the engine drops its sender
+                match event_receiver.recv_timeout(ACCEPT_INTERVAL) {
+                    Ok((target_client_id, event)) => {
+                        clients.retain(|client_id, client| {
+                            if target_client_id.is_some() && target_client_id != Some(*client_id) {
+                                return true;
+                            }
+
+                            client
+                                .write_all(event.as_bytes())
+                                .and_then(|_| client.write_all(b"\n"))
+                                .is_ok()
+                        });
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        Ok(Self {
+            event_sender,
+
+            state_interval: Duration::from_secs_f64(telemetry_config.state_interval.max(0.0)),
+            last_state_time: None,
+        })
+    }
+
+    /// Whether enough time passed since the last state snapshot to send a new one
+    pub fn is_state_due(&mut self) -> bool {
+        let now = Instant::now();
+
+        let is_due = self
+            .last_state_time
+            .map(|last_state_time| now - last_state_time >= self.state_interval)
+            .unwrap_or(true);
+        if is_due {
+            self.last_state_time = Some(now);
+        }
+
+        is_due
+    }
+
+    pub fn publish(&self, event: &TelemetryEvent) {
+        self.send(None, event);
+    }
+
+    /// Sends an event to one client only
+    pub fn respond(&self, client_id: usize, event: &TelemetryEvent) {
+        self.send(Some(client_id), event);
+    }
+
+    fn send(&self, client_id: Option<usize>, event: &TelemetryEvent) {
+        match serde_json::to_string(event) {
+            Ok(event) => {
+                let _ = self.event_sender.send((client_id, event));
+            }
+            Err(e) => eprintln!("Failed to serialize a telemetry event: {:?}", e),
+        }
+    }
+}
+
+/// Reads the queries of a client until it disconnects
+fn read_queries(client_id: usize, client: TcpStream, order_sender: Sender<Command>) {
+    thread::spawn(move || {
+        for line in BufReader::new(client).lines() {
+            let line = if let Ok(line) = line {
+                line
+            } else {
+                break;
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str::<QueryRequest>(&line) {
+                Ok(request) => {
+                    let query = Command::Query {
+                        query: request.query,
+                        client: QueryClient::Telemetry(client_id),
+                    };
+                    if order_sender.send(query).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("Invalid telemetry query {:?}: {:?}", line, e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_tagged_with_their_type() {
+        let change = TelemetryEvent::Change {
+            message: Message::Pause,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&change).unwrap(),
+            r#"{"type":"Change","message":"Pause"}"#
+        );
+    }
+}
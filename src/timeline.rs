@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use wvr_com::data::Message;
+
+/// Number of jumps followed in a single update, in case cues jump to each other endlessly
+pub const MAX_JUMPS_PER_UPDATE: usize = 8;
+
+/// Position of a cue, counted from the start of the animation
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CuePosition {
+    Beat(f64),
+    /// In seconds
+    Time(f64),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CueAction {
+    Message(Message),
+    RecallPreset {
+        name: String,
+        /// Crossfade length, in beats
+        #[serde(default)]
+        duration: f64,
+    },
+    /// Moves the animation to another cue, `count` times before letting it go on,
+    /// or forever when no count is given
+    Jump {
+        to: String,
+        #[serde(default)]
+        count: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cue {
+    pub at: CuePosition,
+    /// Name the jumps refer to
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<CueAction>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimelineConfig {
+    /// Cues in the order they are met, those sharing a position firing in list order
+    pub cues: Vec<Cue>,
+}
+
+/// What the animation has to do after moving forward on the timeline
+#[derive(Debug, Clone, Default)]
+pub struct TimelineStep {
+    pub actions: Vec<CueAction>,
+    pub jump: Option<CuePosition>,
+}
+
+/// Fires the cues of the timeline as the animation goes past them
+#[derive(Debug)]
+pub struct Timeline {
+    cues: Vec<Cue>,
+
+    last_time: f64,
+    last_beat: f64,
+    /// Whether cues sitting exactly at the last position are still to fire, as after a jump
+    inclusive: bool,
+
+    /// Number of jumps already made by each jump action, by cue index
+    jump_counts: HashMap<usize, usize>,
+}
+
+impl Timeline {
+    pub fn new(timeline_config: &TimelineConfig) -> Self {
+        Self {
+            cues: timeline_config.cues.clone(),
+
+            last_time: 0.0,
+            last_beat: 0.0,
+            inclusive: true,
+
+            jump_counts: HashMap::new(),
+        }
+    }
+
+    fn find_cue(&self, cue_name: &str) -> Option<usize> {
+        self.cues
+            .iter()
+            .position(|cue| cue.name.as_deref() == Some(cue_name))
+    }
+
+    /// Collects the actions of the cues met since the last call, stopping at the first jump taken
+    pub fn advance(&mut self, time: f64, beat: f64) -> TimelineStep {
+        let mut step = TimelineStep::default();
+
+        for cue_index in 0..self.cues.len() {
+            let (cue_position, last_position, current_position) = match self.cues[cue_index].at {
+                CuePosition::Beat(cue_beat) => (cue_beat, self.last_beat, beat),
+                CuePosition::Time(cue_time) => (cue_time, self.last_time, time),
+            };
+
+            let is_reached = if self.inclusive {
+                cue_position >= last_position
+            } else {
+                cue_position > last_position
+            };
+            if !is_reached || cue_position > current_position {
+                continue;
+            }
+
+            for action in &self.cues[cue_index].actions {
+                if let CueAction::Jump { to, count } = action {
+                    let jump_count = self.jump_counts.entry(cue_index).or_insert(0);
+                    if count.map(|count| *jump_count >= count).unwrap_or(false) {
+                        continue;
+                    }
+                    *jump_count += 1;
+
+                    match self.find_cue(to) {
+                        Some(target_index) => {
+                            // The loops nested in the part jumped back over play again in full
+                            for looped_index in target_index..cue_index {
+                                self.jump_counts.remove(&looped_index);
+                            }
+
+                            step.jump = Some(self.cues[target_index].at);
+                            break;
+                        }
+                        None => eprintln!("Unknown cue {:?} in the timeline", to),
+                    }
+                } else {
+                    step.actions.push(action.clone());
+                }
+            }
+
+            if step.jump.is_some() {
+                break;
+            }
+        }
+
+        // Small steps back, such as the corrections made when following an external clock,
+        // leave the position where it was, so that the cues already met do not fire again
+        if time >= self.last_time && beat >= self.last_beat {
+            self.inclusive = false;
+        }
+        self.last_time = self.last_time.max(time);
+        self.last_beat = self.last_beat.max(beat);
+
+        step
+    }
+
+    /// Moves the position on purpose, the cues between the old and new positions being skipped
+    pub fn seek(&mut self, time: f64, beat: f64) {
+        self.last_time = time;
+        self.last_beat = beat;
+        self.inclusive = false;
+    }
+
+    /// Goes back to the start of the animation, the cues and loops playing again from there
+    pub fn restart(&mut self) {
+        self.last_time = 0.0;
+        self.last_beat = 0.0;
+        self.inclusive = true;
+
+        self.jump_counts.clear();
+    }
+
+    /// Moves the position after a jump, so that the cue jumped to fires on the next advance
+    pub fn jumped(&mut self, time: f64, beat: f64) {
+        self.last_time = time;
+        self.last_beat = beat;
+        self.inclusive = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(beat: f64, name: Option<&str>, actions: Vec<CueAction>) -> Cue {
+        Cue {
+            at: CuePosition::Beat(beat),
+            name: name.map(str::to_owned),
+            actions,
+        }
+    }
+
+    fn recall(name: &str) -> CueAction {
+        CueAction::RecallPreset {
+            name: name.to_owned(),
+            duration: 0.0,
+        }
+    }
+
+    fn jump(to: &str, count: usize) -> CueAction {
+        CueAction::Jump {
+            to: to.to_owned(),
+            count: Some(count),
+        }
+    }
+
+    /// Plays the timeline beat by beat at 120 BPM, following its jumps,
+    /// and returns the presets recalled on the way
+    fn play(timeline: &mut Timeline, beats: &[f64]) -> Vec<String> {
+        let mut recalled = Vec::new();
+
+        for &beat in beats {
+            let mut beat = beat;
+            for _ in 0..MAX_JUMPS_PER_UPDATE {
+                let step = timeline.advance(beat / 2.0, beat);
+                for action in step.actions {
+                    if let CueAction::RecallPreset { name, .. } = action {
+                        recalled.push(name);
+                    }
+                }
+
+                match step.jump {
+                    Some(CuePosition::Beat(target)) => {
+                        beat = target;
+                        timeline.jumped(beat / 2.0, beat);
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        recalled
+    }
+
+    #[test]
+    fn cues_fire_once_as_the_beat_goes_past_them() {
+        let mut timeline = Timeline::new(&TimelineConfig {
+            cues: vec![
+                cue(0.0, None, vec![recall("intro")]),
+                cue(2.0, None, vec![recall("verse")]),
+            ],
+        });
+
+        assert_eq!(play(&mut timeline, &[0.0, 0.5, 1.0]), vec!["intro"]);
+        assert_eq!(play(&mut timeline, &[2.5, 3.0]), vec!["verse"]);
+    }
+
+    #[test]
+    fn small_steps_back_do_not_fire_cues_again() {
+        let mut timeline = Timeline::new(&TimelineConfig {
+            cues: vec![cue(1.0, None, vec![recall("drop")])],
+        });
+
+        assert_eq!(play(&mut timeline, &[0.5, 1.0]), vec!["drop"]);
+        assert!(play(&mut timeline, &[0.98, 1.01, 1.5]).is_empty());
+
+        timeline.seek(0.25, 0.5);
+        assert_eq!(play(&mut timeline, &[1.5]), vec!["drop"]);
+    }
+
+    #[test]
+    fn nested_loops_play_again_when_the_outer_loop_jumps_back() {
+        let mut timeline = Timeline::new(&TimelineConfig {
+            cues: vec![
+                cue(0.0, Some("outer"), Vec::new()),
+                cue(4.0, Some("inner"), Vec::new()),
+                cue(6.0, None, vec![recall("verse")]),
+                cue(8.0, None, vec![jump("inner", 1)]),
+                cue(12.0, None, vec![jump("outer", 1)]),
+            ],
+        });
+
+        let beats: Vec<f64> = (0..=40).map(|beat| beat as f64).collect();
+        assert_eq!(play(&mut timeline, &beats).len(), 4);
+    }
+}
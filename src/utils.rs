@@ -1,16 +1,16 @@
 use std::collections::HashMap;
 use std::fs;
-use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::{App, Arg};
 use git2::Repository;
 
 use glium::glutin;
 use glium::glutin::event_loop::EventLoop;
-use glium::Display;
+use glium::{Display, HeadlessRenderer};
 
 use glutin::dpi::PhysicalSize;
 use glutin::window::WindowBuilder;
@@ -26,6 +26,15 @@ use wvr_image::image::PictureProvider;
 use wvr_midi::midi::controller::MidiProvider;
 use wvr_video::video::VideoProvider;
 
+use crate::audio::tempo::TempoTracker;
+use crate::audio::{audio_input_from_config, AudioInputConfig};
+use crate::check;
+use crate::config::ExtendedConfig;
+use crate::format::{self, ConfigFormat};
+use crate::layer;
+use crate::offline::{FrameFormat, FramePosition, OfflineRenderConfig};
+use crate::placeholder::{self, InputFallback};
+
 pub fn init_wvr_data_directory() -> Result<()> {
     let data_path = wvr_data::get_data_path();
 
@@ -66,7 +75,17 @@ pub fn init_wvr_data_directory() -> Result<()> {
     Ok(())
 }
 
-pub fn get_config() -> Result<(PathBuf, ProjectConfig)> {
+pub struct RunOptions {
+    /// File the project config was loaded from, and is saved back to
+    pub config_path: PathBuf,
+    /// Settings given on the command line, such as `view.width=1920`, applied over the config
+    pub config_overrides: Vec<String>,
+    pub headless: bool,
+    pub frame_budget: Option<usize>,
+    pub offline_render: Option<OfflineRenderConfig>,
+}
+
+pub fn get_config() -> Result<(PathBuf, ProjectConfig, ExtendedConfig, RunOptions)> {
     let data_path = wvr_data::get_data_path();
 
     let matches = App::new("Wvr")
@@ -115,18 +134,129 @@ pub fn get_config() -> Result<(PathBuf, ProjectConfig)> {
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("set")
+                .long("set")
+                .value_name("KEY=VALUE")
+                .about("Overrides a setting of the project config, such as view.width=1920")
+                .required(false)
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("headless")
+                .long("headless")
+                .about("Renders offscreen without opening a window")
+                .required(false),
+        )
+        .arg(
+            Arg::new("frames")
+                .short('f')
+                .long("frames")
+                .value_name("COUNT")
+                .about("Stops after rendering the given number of frames, counted from --from when rendering offline")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("render")
+                .short('r')
+                .long("render")
+                .value_name("FOLDER")
+                .about("Renders the project offline as a numbered image sequence")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("POSITION")
+                .about("First rendered position, in frames (120), seconds (4.5s) or beats (16b)")
+                .required(false)
+                .takes_value(true)
+                .requires("render"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_name("POSITION")
+                .about("Position at which rendering stops, in frames (120), seconds (4.5s) or beats (16b)")
+                .required(false)
+                .takes_value(true)
+                .requires("render"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .about("Image format of the rendered frames, png (default) or exr")
+                .required(false)
+                .takes_value(true)
+                .requires("render"),
+        )
+        .subcommand(
+            App::new("convert")
+                .about("Rewrites a config file as JSON, RON or TOML, following the output extension")
+                .arg(
+                    Arg::new("input")
+                        .about("Converted config file")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("output")
+                        .about("Written config file, ending in .json, .ron or .toml")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("filter")
+                        .long("filter")
+                        .about("Converts a filter config rather than a project one"),
+                ),
+        )
+        .subcommand(
+            App::new("check")
+                .about("Checks a project and the filters it uses, reporting every problem found")
+                .arg(
+                    Arg::new("project")
+                        .about("Name, folder or config file of the checked project")
+                        .required(true)
+                        .index(1),
+                ),
+        )
         .get_matches();
 
+    if let Some(convert_matches) = matches.subcommand_matches("convert") {
+        let input_path = PathBuf::from(convert_matches.value_of("input").unwrap());
+        let output_path = PathBuf::from(convert_matches.value_of("output").unwrap());
+
+        convert_config(
+            &input_path,
+            &output_path,
+            convert_matches.is_present("filter"),
+        )?;
+        println!("Converted {:?} to {:?}", input_path, output_path);
+
+        std::process::exit(0);
+    }
+
+    if let Some(check_matches) = matches.subcommand_matches("check") {
+        let config_path = check::find_project_config(check_matches.value_of("project").unwrap());
+        let is_valid = check::run_check(&config_path);
+
+        std::process::exit(if is_valid { 0 } else { 1 });
+    }
+
     let config_path = if let Some(config_path) = matches.value_of("config") {
         let mut config_path = PathBuf::from_str(config_path).unwrap();
         config_path = fs::canonicalize(&config_path).unwrap();
         Some(config_path)
     } else if let Some(project_name) = matches.value_of("project_name") {
+        let project_path = data_path.join("projects").join(project_name);
         Some(
-            data_path
-                .join("projects")
-                .join(project_name)
-                .join("config.json"),
+            format::find_config_file(&project_path)
+                .unwrap_or_else(|| project_path.join("config.json")),
         )
     } else if let Some(shadertoy_url) = matches.value_of("shadertoy") {
         Some(wvr_shadertoy::create_project_from_shadertoy_url(
@@ -141,13 +271,127 @@ pub fn get_config() -> Result<(PathBuf, ProjectConfig)> {
     let config_path = config_path.unwrap();
 
     let project_path = config_path.parent().unwrap().to_owned();
-    let config: ProjectConfig = if let Ok(file) = File::open(&config_path) {
-        serde_json::from_reader::<File, ProjectConfig>(file).unwrap()
+    let config_overrides: Vec<String> = matches
+        .values_of("set")
+        .map(|values| values.map(str::to_owned).collect())
+        .unwrap_or_default();
+    let (config, extended_config) = load_config(&config_path, &config_overrides)?;
+
+    let frame_budget = if let Some(frame_budget) = matches.value_of("frames") {
+        Some(
+            frame_budget
+                .parse::<usize>()
+                .context("Frame count should be a positive integer")?,
+        )
+    } else {
+        None
+    };
+
+    let offline_render = if let Some(output_path) = matches.value_of("render") {
+        let from = if let Some(from) = matches.value_of("from") {
+            from.parse::<FramePosition>()?
+        } else {
+            FramePosition::Frame(0)
+        };
+
+        let to = if let Some(to) = matches.value_of("to") {
+            Some(to.parse::<FramePosition>()?)
+        } else {
+            None
+        };
+
+        // Offline renders run as fast as they can, so they need to know where to stop
+        if to.is_none() && frame_budget.is_none() {
+            bail!("Offline renders need an end, given by --to or --frames");
+        }
+
+        let format = if let Some(format) = matches.value_of("format") {
+            format.parse::<FrameFormat>()?
+        } else {
+            FrameFormat::Png
+        };
+
+        Some(OfflineRenderConfig {
+            output_path: PathBuf::from(output_path),
+            from,
+            to,
+            frame_count: frame_budget,
+            format,
+        })
+    } else {
+        None
+    };
+
+    let run_options = RunOptions {
+        config_path,
+        config_overrides,
+        headless: matches.is_present("headless") || offline_render.is_some(),
+        frame_budget,
+        offline_render,
+    };
+
+    Ok((project_path, config, extended_config, run_options))
+}
+
+/// Reads a project config file, in the format given by its extension, along with
+/// the settings `ProjectConfig` does not know about.
+/// Includes are resolved and `overrides`, such as `view.width=1920`, applied last.
+pub fn load_config(
+    config_path: &Path,
+    overrides: &[String],
+) -> Result<(ProjectConfig, ExtendedConfig)> {
+    // A file that cannot be read for its includes is parsed below, which tells where it fails
+    if !overrides.is_empty() || layer::has_includes(config_path).unwrap_or(false) {
+        let mut config = layer::read_layered_config(config_path)?;
+        for assignment in overrides {
+            layer::apply_override(&mut config, assignment)?;
+        }
+
+        let extended_config: ExtendedConfig =
+            serde_json::from_value(config.clone()).context(format!(
+                "Failed to parse the project extended settings in {:?} and its includes",
+                config_path
+            ))?;
+        let config: ProjectConfig = serde_json::from_value(config).context(format!(
+            "Failed to parse the project config {:?} and its includes",
+            config_path
+        ))?;
+
+        return Ok((config, extended_config));
+    }
+
+    // A single file is parsed directly, which keeps the position of errors
+    let config_format = ConfigFormat::from_path(config_path)?;
+    let config = fs::read_to_string(config_path)
+        .context(format!("Could not find config file {:?}", config_path))?;
+
+    let extended_config: ExtendedConfig = config_format.parse(&config).context(format!(
+        "Failed to parse the project extended settings in {:?}",
+        config_path
+    ))?;
+    let config: ProjectConfig = config_format.parse(&config).context(format!(
+        "Failed to parse the project config {:?}",
+        config_path
+    ))?;
+
+    Ok((config, extended_config))
+}
+
+/// Rewrites a project or filter config in the format given by the extension of `output_path`
+pub fn convert_config(input_path: &Path, output_path: &Path, is_filter: bool) -> Result<()> {
+    let output_format = ConfigFormat::from_path(output_path)?;
+
+    let config = if is_filter {
+        let filter_config: FilterConfig = format::read_config(input_path)?;
+        output_format.write(&filter_config)?
     } else {
-        panic!("Could not find config file {:?}", config_path);
+        let (project_config, extended_config) = load_config(input_path, &[])?;
+        format::write_project_config(output_format, &project_config, &extended_config)?
     };
 
-    Ok((project_path, config))
+    fs::write(output_path, config).context(format!("Failed to write {:?}", output_path))?;
+
+    Ok(())
 }
 
 pub fn get_path_for_resource<P: AsRef<Path>>(path: P, resource_path: &str) -> String {
@@ -256,9 +500,11 @@ pub fn load_available_filter_list(
     if searched_path.exists() && searched_path.is_dir() {
         for folder_entry in searched_path.read_dir()? {
             let filter_path = folder_entry?.path();
-            let filter_config_path = filter_path.join("config.json");
-
-            if !filter_config_path.exists() {
+            let filter_config_path = if let Some(filter_config_path) =
+                format::find_config_file(&filter_path)
+            {
+                filter_config_path
+            } else {
                 let prefix = filter_path
                     .file_name()
                     .unwrap()
@@ -274,7 +520,7 @@ pub fn load_available_filter_list(
                 }
 
                 continue;
-            }
+            };
 
             let filter_name = filter_path
                 .file_name()
@@ -283,9 +529,7 @@ pub fn load_available_filter_list(
                 .unwrap()
                 .to_string();
 
-            let filter_config: FilterConfig =
-                serde_json::from_reader::<File, FilterConfig>(File::open(&filter_config_path)?)
-                    .unwrap();
+            let filter_config: FilterConfig = format::read_config(&filter_config_path)?;
 
             available_filter_list
                 .insert(filter_name, (filter_path, filter_config, is_system_filter));
@@ -295,15 +539,67 @@ pub fn load_available_filter_list(
     Ok(available_filter_list)
 }
 
+/// Loads the inputs of a project, those that fail being replaced by a placeholder
+/// unless the fallback is `Abort`. The configs of the replaced inputs are returned along.
 pub fn load_inputs(
     project_path: &Path,
     input_list: &HashMap<String, InputConfig>,
+    input_fallback: InputFallback,
+) -> Result<(
+    HashMap<String, Box<dyn InputProvider>>,
+    HashMap<String, InputConfig>,
+)> {
+    let mut uniform_sources = HashMap::new();
+    let mut placeholder_inputs = HashMap::new();
+
+    for (input_name, input_config) in input_list {
+        let input_provider: Box<dyn InputProvider> =
+            match input_from_config(project_path, input_config, input_name, 0.0, 0.0, true) {
+                Ok(input_provider) => input_provider,
+                Err(e) => {
+                    let placeholder = match placeholder::placeholder_for(
+                        input_name,
+                        input_config,
+                        input_fallback,
+                    ) {
+                        Some(placeholder) => placeholder,
+                        None => {
+                            return Err(e.context(format!("Failed to load input {:?}", input_name)))
+                        }
+                    };
+                    eprintln!(
+                        "Failed to load input {:?}, replaced by a placeholder: {:?}",
+                        input_name, e
+                    );
+                    placeholder_inputs.insert(input_name.clone(), input_config.clone());
+
+                    Box::new(placeholder)
+                }
+            };
+
+        uniform_sources.insert(input_name.clone(), input_provider);
+    }
+
+    Ok((uniform_sources, placeholder_inputs))
+}
+
+pub fn load_audio_inputs(
+    project_path: &Path,
+    input_list: &HashMap<String, AudioInputConfig>,
+    tempo_input: Option<(&str, &Arc<Mutex<TempoTracker>>)>,
 ) -> Result<HashMap<String, Box<dyn InputProvider>>> {
     let mut uniform_sources = HashMap::new();
 
     for (input_name, input_config) in input_list {
+        let tempo_tracker = match tempo_input {
+            Some((tempo_input_name, tempo_tracker)) if tempo_input_name == input_name => {
+                Some(tempo_tracker.clone())
+            }
+            _ => None,
+        };
+
         let input_provider =
-            input_from_config(project_path, input_config, input_name, 0.0, 0.0, true)?;
+            audio_input_from_config(project_path, input_config, input_name, tempo_tracker)?;
 
         uniform_sources.insert(input_name.clone(), input_provider);
     }
@@ -361,3 +657,44 @@ pub fn build_window(view_config: &ViewConfig, events_loop: &EventLoop<()>) -> Re
 
     Display::new(window, context, events_loop).context("Failed to create the rendering window")
 }
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+))]
+pub fn build_headless_renderer(view_config: &ViewConfig) -> Result<HeadlessRenderer> {
+    use glutin::platform::unix::HeadlessContextExt;
+
+    // OSMesa is a software rasterizer, so no display server or GPU is needed
+    let context = ContextBuilder::new()
+        .build_osmesa(PhysicalSize::new(
+            view_config.width as u32,
+            view_config.height as u32,
+        ))
+        .context("Failed to create the offscreen rendering context")?;
+
+    HeadlessRenderer::new(context).context("Failed to create the offscreen renderer")
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+)))]
+pub fn build_headless_renderer(view_config: &ViewConfig) -> Result<HeadlessRenderer> {
+    let events_loop = EventLoop::new();
+
+    let context = ContextBuilder::new()
+        .build_headless(
+            &events_loop,
+            PhysicalSize::new(view_config.width as u32, view_config.height as u32),
+        )
+        .context("Failed to create the offscreen rendering context")?;
+
+    HeadlessRenderer::new(context).context("Failed to create the offscreen renderer")
+}